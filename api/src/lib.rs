@@ -1,4 +1,144 @@
 //! The api to communicate with easyinit.
 //! 
 //! Most functions will fail if there is a version mismatch between easyinit and
-//! this library 
\ No newline at end of file
+//! this library 
+//! 
+//! Messages are sent over the unix socket at [`SOCKET_PATH`], as one JSON document per line.
+//! Each request gets exactly one response.
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+pub use config::{Setting, Source};
+pub use config::loader::ReloadDiff;
+pub use config::service::ServiceDefinition;
+pub use system::condition::CheckFailure;
+pub use system::manager::{ServiceList, ServiceState, ServiceStatus};
+
+/// Where easyinit listens for requests
+pub const SOCKET_PATH: &str = "/run/easyinit/api.sock";
+
+/// Version of the protocol, both sides need to have the same one
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A request sent to easyinit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum Request{
+    /// Every kernel command line parameter, with its value and where it came from
+    Settings,
+    /// Overrides a parameter until the next boot
+    SetSetting{
+        /// Name of the parameter, like `easyinit.loglevel`
+        name: String,
+        /// The value, as it would be written on the kernel command line
+        value: String,
+    },
+    /// Reads the global configuration and every service definition again, like `SIGHUP`
+    Reload,
+    /// Every service and template
+    List,
+    /// The definition of a service, with drop-ins applied and specifiers expanded
+    Show{
+        /// Name of the service
+        name: String,
+    },
+    /// Starts a service, instances of templates like `getty@tty1` are created on demand
+    Start{
+        /// Name of the service
+        name: String,
+    },
+}
+
+/// A response from easyinit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum Response{
+    /// The request succeeded and has nothing to return
+    Ok,
+    /// The request failed
+    Error(String),
+    /// Response to [`Request::Settings`]
+    Settings(Vec<Setting>),
+    /// Response to [`Request::Reload`], what changed
+    Reloaded(ReloadDiff),
+    /// Response to [`Request::List`]
+    Services(ServiceList),
+    /// Response to [`Request::Show`]
+    Definition(Box<ServiceDefinition>),
+}
+
+/// Wraps every message so the versions can be compared
+#[derive(Debug, Serialize, Deserialize)]
+struct Message<T>{
+    version: String,
+    body: T,
+}
+
+/// Errors while talking to easyinit
+#[derive(thiserror::Error, Debug)]
+pub enum Error{
+    /// The other side uses a different version of the protocol
+    #[error("Version mismatch, we are {VERSION} but the other side is {0}")]
+    VersionMismatch(String),
+    /// The other side closed the connection before sending a message
+    #[error("Connection closed")]
+    Closed,
+    /// The message could not be understood
+    #[error("Malformed message: {0}")]
+    Malformed(#[from] serde_json::Error),
+    /// A generic IO error
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+}
+
+/// A connection to easyinit
+#[derive(Debug)]
+pub struct Client{
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+impl Client{
+    /// Connects to easyinit at [`SOCKET_PATH`]
+    pub fn connect()->Result<Self, Error>{
+        Client::connect_to(SOCKET_PATH.as_ref())
+    }
+    /// Connects to a socket at a specific path
+    pub fn connect_to(path:&Path)->Result<Self, Error>{
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Client { reader, writer })
+    }
+    /// Sends a request and waits for the response
+    pub fn send(&mut self, request:&Request)->Result<Response, Error>{
+        write_message(&mut self.writer, request)?;
+        read_message(&mut self.reader)?.ok_or(Error::Closed)
+    }
+}
+
+/// Writes a single message, used by both sides
+pub fn write_message<T:Serialize>(writer:&mut impl Write, body:&T)->Result<(), Error>{
+    let mut line = serde_json::to_vec(&Message { version: VERSION.to_string(), body })?;
+    line.push(b'\n');
+    writer.write_all(&line)?;
+    Ok(())
+}
+
+/// Reads a single message, used by both sides
+/// 
+/// Returns `None` if the other side closed the connection.
+pub fn read_message<T:DeserializeOwned>(reader:&mut impl BufRead)->Result<Option<T>, Error>{
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0{
+        return Ok(None);
+    }
+    // The version is checked before the body, as the body may have changed between versions
+    let message: Message<serde_json::Value> = serde_json::from_str(&line)?;
+    if message.version != VERSION{
+        return Err(Error::VersionMismatch(message.version));
+    }
+    Ok(Some(serde_json::from_value(message.body)?))
+}
@@ -1,3 +1,271 @@
-fn main() {
-    println!("Hello, world!");
+//! The commandline interface for easyinit
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("boot-params") => boot_params(),
+        Some("settings") => settings(),
+        Some("reload") => reload(),
+        Some("list") => list(),
+        Some("show") => match args.next() {
+            Some(name) => show(name),
+            None => {
+                eprintln!("Usage: easyctl show <service>");
+                ExitCode::from(2)
+            }
+        },
+        Some("start") => match args.next() {
+            Some(name) => start(name),
+            None => {
+                eprintln!("Usage: easyctl start <service>");
+                ExitCode::from(2)
+            }
+        },
+        Some("set") => match (args.next(), args.next()) {
+            (Some(name), Some(value)) => set(name, value),
+            _ => {
+                eprintln!("Usage: easyctl set <parameter> <value>");
+                ExitCode::from(2)
+            }
+        },
+        #[cfg(feature = "systemd")]
+        Some("import-systemd") => import_systemd(args.collect()),
+        Some("help" | "--help" | "-h") => {
+            usage();
+            ExitCode::SUCCESS
+        }
+        Some(cmd) => {
+            eprintln!("Unknown command `{cmd}`");
+            usage();
+            ExitCode::from(2)
+        }
+        None => {
+            usage();
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn usage() {
+    eprintln!("Usage: easyctl <command>");
+    eprintln!();
+    eprintln!("Commands:");
+    eprintln!("    boot-params    List the kernel command line parameters easyinit supports");
+    eprintln!("    settings       Show the current value of each parameter and where it came from");
+    eprintln!("    set <p> <v>    Override a parameter until the next boot");
+    eprintln!("    reload         Read the configuration and service definitions again");
+    eprintln!("    list           Show every service with its state, and the templates");
+    eprintln!("    show <s>       Print the definition of a service as it is loaded");
+    eprintln!("    start <s>      Start a service, or an instance of a template like getty@tty1");
+    #[cfg(feature = "systemd")]
+    eprintln!("    import-systemd <unit>...  Print systemd units as easyinit definitions");
+    eprintln!("    help           Show this message");
+}
+
+/// Sends a single request to easyinit, printing any error
+fn request(request: &api::Request) -> Result<api::Response, ExitCode> {
+    let response = api::Client::connect().and_then(|mut c| c.send(request));
+    match response {
+        Ok(api::Response::Error(e)) => {
+            eprintln!("easyinit: {e}");
+            Err(ExitCode::FAILURE)
+        }
+        Ok(response) => Ok(response),
+        Err(e) => {
+            eprintln!("Failed to talk to easyinit: {e}");
+            Err(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// Prints the current settings and where they came from
+fn settings() -> ExitCode {
+    match request(&api::Request::Settings) {
+        Ok(api::Response::Settings(settings)) => {
+            for s in settings {
+                let value = s.value.as_deref().unwrap_or("<unset>");
+                println!("{}={}    ({})", s.name, value, s.source);
+            }
+            ExitCode::SUCCESS
+        }
+        Ok(other) => unexpected(&other),
+        Err(code) => code,
+    }
+}
+
+fn set(name: String, value: String) -> ExitCode {
+    match request(&api::Request::SetSetting { name, value }) {
+        Ok(api::Response::Ok) => ExitCode::SUCCESS,
+        Ok(other) => unexpected(&other),
+        Err(code) => code,
+    }
+}
+
+/// Prints every service and template
+fn list() -> ExitCode {
+    match request(&api::Request::List) {
+        Ok(api::Response::Services(list)) => {
+            let width = list.services.iter().map(|s| s.name.len()).max().unwrap_or(0);
+            for s in &list.services {
+                let mut line = format!("{:width$}  {}", s.name, s.state);
+                if let Some(pid) = s.pid {
+                    line.push_str(&format!("  pid {pid}"));
+                }
+                if s.removed {
+                    line.push_str("  (removed, running until it stops)");
+                } else if s.needs_restart {
+                    line.push_str("  (needs restart)");
+                }
+                if let Some(check) = &s.check {
+                    line.push_str(&format!("  ({check})"));
+                }
+                println!("{line}");
+            }
+            if !list.templates.is_empty() {
+                println!();
+                println!("Templates: {}", list.templates.join(" "));
+            }
+            ExitCode::SUCCESS
+        }
+        Ok(other) => unexpected(&other),
+        Err(code) => code,
+    }
+}
+
+/// Prints a definition as TOML, with drop-ins applied and specifiers expanded
+fn show(name: String) -> ExitCode {
+    match request(&api::Request::Show { name: name.clone() }) {
+        Ok(api::Response::Definition(definition)) => match toml::to_string(&definition) {
+            Ok(text) => {
+                println!("# {name}");
+                print!("{text}");
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("Failed to print {name}: {e}");
+                ExitCode::FAILURE
+            }
+        },
+        Ok(other) => unexpected(&other),
+        Err(code) => code,
+    }
+}
+
+fn start(name: String) -> ExitCode {
+    match request(&api::Request::Start { name }) {
+        Ok(api::Response::Ok) => ExitCode::SUCCESS,
+        Ok(other) => unexpected(&other),
+        Err(code) => code,
+    }
+}
+
+/// Reloads and prints what changed
+fn reload() -> ExitCode {
+    match request(&api::Request::Reload) {
+        Ok(api::Response::Reloaded(diff)) => {
+            if diff.is_empty() {
+                println!("Nothing changed");
+            }
+            for name in &diff.added {
+                println!("+ {name}");
+            }
+            for name in &diff.removed {
+                if diff.needs_restart.contains(name) {
+                    println!("- {name} (running until it stops)");
+                } else {
+                    println!("- {name}");
+                }
+            }
+            for name in &diff.changed {
+                if diff.needs_restart.contains(name) {
+                    println!("~ {name} (needs restart)");
+                } else {
+                    println!("~ {name}");
+                }
+            }
+            for error in &diff.errors {
+                eprintln!("error: {error}");
+            }
+            if diff.errors.is_empty() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Ok(other) => unexpected(&other),
+        Err(code) => code,
+    }
+}
+
+fn unexpected(response: &api::Response) -> ExitCode {
+    eprintln!("Unexpected response from easyinit: {response:?}");
+    ExitCode::FAILURE
+}
+
+/// Prints every parameter in the registry
+///
+/// The same settings, except the ones for a single boot, can be put in the global configuration
+/// file without the `easyinit.` prefix.
+fn boot_params() -> ExitCode {
+    for param in config::params::PARAMETERS {
+        println!("{}=<{}>", param.name, param.kind.placeholder());
+        println!("    {}", param.help);
+        if !param.kind.choices().is_empty() {
+            println!("    One of: {}", param.kind.choices().join(", "));
+        }
+        if !param.default.is_empty() {
+            println!("    Default: {}", param.default);
+        }
+        for alias in param.aliases {
+            println!("    Alias: `{}` is the same as `{}={}`", alias.flag, param.name, alias.value);
+        }
+        if param.per_boot {
+            println!("    Only on the kernel command line, not in {}", config::global::PATH);
+        }
+        println!();
+    }
+    ExitCode::SUCCESS
+}
+
+/// Prints each unit file as a definition, warnings go to stderr
+///
+/// A directory imports every unit in it, with sockets and timers merged into their services.
+#[cfg(feature = "systemd")]
+fn import_systemd(units: Vec<String>) -> ExitCode {
+    use config::systemd;
+    if units.is_empty() {
+        eprintln!("Usage: easyctl import-systemd <unit or directory>...");
+        return ExitCode::from(2);
+    }
+    let mut code = ExitCode::SUCCESS;
+    for unit in units {
+        let path = std::path::Path::new(&unit);
+        let (imports, errors) = if path.is_dir() {
+            match systemd::import_dir(path) {
+                Ok(result) => result,
+                Err(e) => (Vec::new(), vec![systemd::ImportError::Io { path: path.to_path_buf(), source: e }]),
+            }
+        } else {
+            match systemd::import_file(path) {
+                Ok(import) => (vec![import], Vec::new()),
+                Err(e) => (Vec::new(), vec![e]),
+            }
+        };
+        for import in imports {
+            for warning in &import.warnings {
+                eprintln!("warning: {warning}");
+            }
+            println!("# {}.toml, imported from {}", import.definition.name, import.path.display());
+            match toml::to_string(&import.definition) {
+                Ok(text) => println!("{text}"),
+                Err(e) => eprintln!("Failed to write definition: {e}"),
+            }
+        }
+        for error in errors {
+            eprintln!("error: {error}");
+            code = ExitCode::FAILURE;
+        }
+    }
+    code
 }
@@ -0,0 +1,167 @@
+//! Conditions and assertions, checked right before a service is started
+//!
+//! ```toml
+//! [conditions]
+//! path-exists = ["/etc/example.conf"]
+//! path-is-directory = ["/var/lib/example"]
+//! path-is-mountpoint = ["/data"]
+//! file-not-empty = ["/etc/example/key"]
+//! kernel-command-line = ["!example.disable", "console=ttyS0"]
+//! architecture = "x86-64"
+//! virtualization = "!container"
+//! first-boot = false
+//! memory = ">=2G"
+//! cpus = ">=4"
+//!
+//! [assertions]
+//! path-exists = ["/etc/example/key"]
+//! ```
+//!
+//! If a condition does not hold the service is skipped, it stays inactive without that being an
+//! error. If an assertion does not hold the service fails. Both sections take the same keys.
+//!
+//! Every check has to hold. An entry starting with `!` holds if the check does not.
+//!
+//! - `kernel-command-line` holds if a parameter is set, `name=value` only if it has that value.
+//! - `architecture` is a name like `x86-64`, `x86`, `arm64`, `arm`, `riscv64`, `ppc64le` or `s390x`.
+//! - `virtualization` is `yes`, `no`, `vm`, `container`, or the name of one, like `kvm` or `docker`.
+//! - `first-boot` holds if `/etc/machine-id` is missing or not initialized yet.
+//! - `memory` and `cpus` compare the memory and usable CPUs with `=`, `!=`, `<`, `<=`, `>` or
+//!   `>=`, which is the default. `memory` takes the suffixes `K`, `M` and `G`.
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// The `[conditions]` and `[assertions]` sections
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Conditions {
+    /// Paths that exist
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub path_exists: Vec<String>,
+    /// Paths that are directories
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub path_is_directory: Vec<String>,
+    /// Paths that something is mounted on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub path_is_mountpoint: Vec<String>,
+    /// Files that exist and are not empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub file_not_empty: Vec<String>,
+    /// Kernel command line parameters, `name` or `name=value`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kernel_command_line: Vec<String>,
+    /// The CPU architecture
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub architecture: Option<String>,
+    /// The kind of virtualization
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub virtualization: Option<String>,
+    /// If this is the first boot of the system
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_boot: Option<bool>,
+    /// Total memory in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<Threshold>,
+    /// Number of usable CPUs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<Threshold>,
+}
+
+impl Conditions {
+    /// If nothing is set
+    pub fn is_empty(&self) -> bool {
+        *self == Conditions::default()
+    }
+}
+
+/// Splits the `!` off an entry, returns if the check has to hold and the rest
+pub fn negation(entry: &str) -> (bool, &str) {
+    match entry.strip_prefix('!') {
+        Some(rest) => (false, rest),
+        None => (true, entry),
+    }
+}
+
+/// A comparison with a number, like `>=2G`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Threshold {
+    /// How the value is compared
+    pub comparison: Comparison,
+    /// The number compared with
+    pub value: u64,
+}
+
+/// How a [`Threshold`] compares
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    /// `=`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `<`
+    Less,
+    /// `<=`
+    LessOrEqual,
+    /// `>`
+    Greater,
+    /// `>=`
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    /// Every comparison with its operator, longer ones first so `>=` is not read as `>`
+    const OPERATORS: &[(&str, Comparison)] = &[
+        ("!=", Comparison::NotEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("=", Comparison::Equal),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+    ];
+
+    /// The operator, like `>=`
+    pub fn operator(self) -> &'static str {
+        Comparison::OPERATORS.iter().find(|(_, c)| *c == self).map_or("", |(op, _)| op)
+    }
+}
+
+impl Threshold {
+    /// If `actual` is within the threshold
+    pub fn holds(&self, actual: u64) -> bool {
+        let ordering = actual.cmp(&self.value);
+        match self.comparison {
+            Comparison::Equal => ordering == Ordering::Equal,
+            Comparison::NotEqual => ordering != Ordering::Equal,
+            Comparison::Less => ordering == Ordering::Less,
+            Comparison::LessOrEqual => ordering != Ordering::Greater,
+            Comparison::Greater => ordering == Ordering::Greater,
+            Comparison::GreaterOrEqual => ordering != Ordering::Less,
+        }
+    }
+}
+
+impl TryFrom<String> for Threshold {
+    type Error = String;
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let trimmed = text.trim();
+        let (comparison, value) = Comparison::OPERATORS
+            .iter()
+            .find_map(|(op, comparison)| trimmed.strip_prefix(op).map(|rest| (*comparison, rest)))
+            .unwrap_or((Comparison::GreaterOrEqual, trimmed));
+        let value = crate::params::parse_size(value.trim()).map_err(|_| format!("`{text}` is not a comparison like `>=2G`"))?;
+        Ok(Threshold { comparison, value })
+    }
+}
+
+impl From<Threshold> for String {
+    fn from(threshold: Threshold) -> Self {
+        threshold.to_string()
+    }
+}
+
+impl std::fmt::Display for Threshold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.comparison.operator(), self.value)
+    }
+}
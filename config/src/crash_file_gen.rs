@@ -2,77 +2,47 @@
 //!
 //! The file would be prefixed with `easyinit-` followed by the date and identifier.
 //!
-//! The date would be in `YY-MM-DD-#` format, where `#` means is to prevent overwriting preexisting crash logs
+//! The date would be in `YYYY-MM-DD-#` format, where `#` means is to prevent overwriting preexisting crash logs.
+//! The date is taken from the system clock, in UTC, as there may not be a time zone database this early.
 //!
 //! This should be used to lazily get the file name. If it gets created after the fact, by a alternative program, while it would technically create data
 //! loss, with a time-of-check to time-of-use ([TOCTOU]) race condition, but due to the unique format this generally is not a concern.
 //!
 //! [TOCTOU]: std::fs#time-of-check-to-time-of-use-toctou
-use std::ffi::{OsStr, OsString};
-use std::fs::read_dir;
-use std::io::Result;
-use std::ops::{Add, AddAssign};
+use crate::crash_retention::{COMPRESSED_EXTENSION, EXTENSION, PREFIX};
+use chrono::{DateTime, Datelike};
+use std::io::{Error, ErrorKind, Result};
 use std::path::{Path, PathBuf};
-
-static CRASH_FILE_EXTENSION: &str = ".crash";
-
-
-
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// View [module level documentation] for more info
 ///
 /// [module level documentation]: self
-#[cfg(feature = "coreutils")]
-fn gen_filename(dir: &PathBuf) -> Result<PathBuf> {
-    let date = std::process::Command::new("/bin/date")
-        .env_clear()
-        .arg("+%Y-%m-%d")
-        .env("LC_ALL", "C") // prevent locales to become an issue
-        .output()?;
-    let mut out: OsString = "easyinit-".to_string().into();
-    let date = String::from_utf8_lossy(&date.stdout);
-
-    let date = date.trim_end_matches('\x0a'); //removes newline the the command generates, for some reason
-    out.push(date);
-    out.push("-");
-    get_available_file(dir, &out)
+pub fn gen_filename(dir: &Path) -> Result<PathBuf> {
+    let (year, month, day) = date(SystemTime::now());
+    get_available_file(dir, &format!("{PREFIX}{year:04}-{month:02}-{day:02}-"))
 }
-#[cfg(feature = "coreutils")]
-fn get_available_file(dir: &PathBuf, prefix: &OsString) -> Result<PathBuf> {
-    // let direntry = read_dir(dir)?;
-    // direntry.
-
-    let ext = OsString::from(CRASH_FILE_EXTENSION);
-    let mut id: u32 = 0;
-    let mut id_str: OsString = id.to_string().into();
-
-    let mut out = OsString::with_capacity(dir.capacity() + prefix.len() + ext.len() + id_str.len());
-    // out.reserve();
-    loop {
-        out.push(dir.as_os_str());
-        out.push(prefix);
-        out.push(&id_str);
-        out.push(CRASH_FILE_EXTENSION);
-
-        match std::fs::exists(&out) {
-            Ok(v) if !v => return Ok(out.into()),
-
-            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
-                unreachable!("Permission should not be denied as program should be ran as root");
-                // log::error!("Permission denied while accessing directory {}",out.display());
-            }
 
-            _ => {
-                id = id.saturating_add(1);
-                id_str = id.to_string().into();
-                out.clear();
-            }
+/// The first `<prefix><id>.crash` in `dir` that does not exist yet, compressed or not
+fn get_available_file(dir: &Path, prefix: &str) -> Result<PathBuf> {
+    for id in 0..=u32::MAX {
+        let path = dir.join(format!("{prefix}{id}.{EXTENSION}"));
+        let compressed = dir.join(format!("{prefix}{id}.{EXTENSION}.{COMPRESSED_EXTENSION}"));
+        // Errors like `PermissionDenied` are returned, trying the next id would fail the same way
+        if !std::fs::exists(&path)? && !std::fs::exists(&compressed)? {
+            return Ok(path);
         }
     }
+    Err(Error::new(ErrorKind::AlreadyExists, "every crash report name for today is taken"))
 }
 
-#[cfg(not(doc))]
-#[cfg(not(feature = "coreutils"))]
-pub fn gen_filename() -> Result<String> {
-    todo!()
+/// The UTC date of `time` as year, month and day, a clock before 1970 counts as 1970-01-01
+fn date(time: SystemTime) -> (i32, u32, u32) {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let date = i64::try_from(seconds)
+        .ok()
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+        .unwrap_or_default()
+        .date_naive();
+    (date.year(), date.month(), date.day())
 }
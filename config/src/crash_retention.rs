@@ -0,0 +1,233 @@
+//! Keeps the crash report directory from growing without bound
+//!
+//! Only generated reports, `easyinit-*.crash` and their compressed `easyinit-*.crash.gz`, are
+//! touched. When easyinit starts it first compresses the reports if
+//! `easyinit.crash-compress` is set, then removes the oldest reports until every limit is met:
+//!
+//! * `easyinit.crash-max-count`, the number of reports kept
+//! * `easyinit.crash-max-size`, the total size of the reports kept
+//! * `easyinit.crash-max-age`, reports older than this are removed
+//!
+//! A limit of `0` disables it.
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use logging::prelude::*;
+use std::fs::File;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// File name prefix of generated crash reports
+pub(crate) const PREFIX: &str = "easyinit-";
+/// Extension of crash reports
+pub(crate) const EXTENSION: &str = "crash";
+/// Extension added to compressed crash reports
+pub(crate) const COMPRESSED_EXTENSION: &str = "gz";
+
+/// The limits on generated crash reports, `None` is no limit
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// Number of reports kept
+    pub max_count: Option<u64>,
+    /// Total size of the reports kept, in bytes
+    pub max_size: Option<u64>,
+    /// Reports last changed longer ago than this are removed
+    pub max_age: Option<Duration>,
+    /// If reports are compressed with gzip
+    pub compress: bool,
+}
+
+/// What [`rotate`] did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rotated {
+    /// Reports that were compressed, with their new path
+    pub compressed: Vec<PathBuf>,
+    /// Reports that were removed
+    pub removed: Vec<PathBuf>,
+}
+
+/// A report found in the directory
+struct Report {
+    path: PathBuf,
+    modified: SystemTime,
+    size: u64,
+}
+
+/// Applies `retention` to the reports in `dir`, as if it is `now`
+///
+/// Failing to compress or remove a single report is logged and skipped, only failing to read the
+/// directory is an error.
+pub fn rotate(dir: &Path, retention: &Retention, now: SystemTime) -> Result<Rotated> {
+    let mut rotated = Rotated::default();
+    let mut reports = reports(dir)?;
+    if retention.compress {
+        for report in reports.iter_mut().filter(|r| !is_compressed(&r.path)) {
+            match compress(&report.path) {
+                Ok(path) => {
+                    report.size = std::fs::metadata(&path).map_or(report.size, |m| m.len());
+                    report.path = path.clone();
+                    rotated.compressed.push(path);
+                }
+                Err(e) => warn!("Failed to compress crash report {}: {e}", report.path.display()),
+            }
+        }
+    }
+
+    // Newest first, so the oldest are the ones over the limits
+    reports.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| b.path.cmp(&a.path)));
+    let mut total_size = 0u64;
+    for (count, report) in reports.into_iter().enumerate() {
+        total_size = total_size.saturating_add(report.size);
+        let age = now.duration_since(report.modified).unwrap_or_default();
+        let keep = retention.max_count.is_none_or(|max| (count as u64) < max)
+            && retention.max_size.is_none_or(|max| total_size <= max)
+            && retention.max_age.is_none_or(|max| age <= max);
+        if keep {
+            continue;
+        }
+        match std::fs::remove_file(&report.path) {
+            Ok(()) => rotated.removed.push(report.path),
+            Err(e) => warn!("Failed to remove crash report {}: {e}", report.path.display()),
+        }
+    }
+    Ok(rotated)
+}
+
+/// Every generated report in `dir`
+fn reports(dir: &Path) -> Result<Vec<Report>> {
+    let mut reports = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let is_report = path.file_name().and_then(|n| n.to_str()).is_some_and(|name| {
+            name.starts_with(PREFIX)
+                && (name.ends_with(&format!(".{EXTENSION}"))
+                    || name.ends_with(&format!(".{EXTENSION}.{COMPRESSED_EXTENSION}")))
+        });
+        let metadata = entry.metadata()?;
+        if is_report && metadata.is_file() {
+            reports.push(Report {
+                path,
+                modified: metadata.modified()?,
+                size: metadata.len(),
+            });
+        }
+    }
+    Ok(reports)
+}
+
+fn is_compressed(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == COMPRESSED_EXTENSION)
+}
+
+/// Compresses a report to `<path>.gz`, keeping its modification time so its age is unchanged
+fn compress(path: &Path) -> Result<PathBuf> {
+    let mut compressed_path = path.as_os_str().to_os_string();
+    compressed_path.push(format!(".{COMPRESSED_EXTENSION}"));
+    let compressed_path = PathBuf::from(compressed_path);
+
+    let mut input = File::open(path)?;
+    let modified = input.metadata()?.modified()?;
+    let output = File::create(&compressed_path)?;
+    let mut encoder = GzEncoder::new(output, Compression::default());
+    let result = std::io::copy(&mut input, &mut encoder).and_then(|_| encoder.finish()).and_then(|output| {
+        output.set_modified(modified)?;
+        output.sync_all()
+    });
+    if let Err(e) = result {
+        // A partial file would be mistaken for a report
+        _ = std::fs::remove_file(&compressed_path);
+        return Err(e);
+    }
+    std::fs::remove_file(path)?;
+    Ok(compressed_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use utils::testing::TempDir;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    /// Writes a report of `size` bytes, last changed `age` before `now`
+    fn report(dir: &TempDir, name: &str, size: usize, age: Duration, now: SystemTime) -> PathBuf {
+        dir.write(name, "x".repeat(size));
+        File::options().write(true).open(dir.join(name)).unwrap().set_modified(now - age).unwrap();
+        dir.join(name)
+    }
+
+    /// Reports from oldest to newest, an hour apart
+    fn reports(dir: &TempDir, count: u32, size: usize, now: SystemTime) -> Vec<PathBuf> {
+        (0..count).map(|i| report(dir, &format!("{PREFIX}{i}.{EXTENSION}"), size, HOUR * (count - i), now)).collect()
+    }
+
+    #[test]
+    fn count() {
+        let dir = TempDir::new("crash-count");
+        let now = SystemTime::now();
+        let paths = reports(&dir, 5, 10, now);
+        dir.write("other.crash", "not generated");
+        dir.write(format!("{PREFIX}notes.txt"), "not a report");
+        let retention = Retention { max_count: Some(3), ..Retention::default() };
+        let rotated = rotate(dir.path(), &retention, now).unwrap();
+        assert_eq!(rotated.removed, [paths[1].clone(), paths[0].clone()]);
+        assert!(rotated.compressed.is_empty());
+        assert!(paths[2..].iter().all(|p| p.exists()));
+        assert!(dir.join("other.crash").exists() && dir.join(format!("{PREFIX}notes.txt")).exists());
+        // Already within the limits
+        assert_eq!(rotate(dir.path(), &retention, now).unwrap(), Rotated::default());
+    }
+
+    #[test]
+    fn size() {
+        let dir = TempDir::new("crash-size");
+        let now = SystemTime::now();
+        let paths = reports(&dir, 4, 100, now);
+        let retention = Retention { max_size: Some(250), ..Retention::default() };
+        assert_eq!(rotate(dir.path(), &retention, now).unwrap().removed, [paths[1].clone(), paths[0].clone()]);
+        // A limit of `None` keeps everything
+        assert!(rotate(dir.path(), &Retention::default(), now).unwrap().removed.is_empty());
+    }
+
+    #[test]
+    fn age() {
+        let dir = TempDir::new("crash-age");
+        let now = SystemTime::now();
+        let paths = reports(&dir, 4, 10, now);
+        let retention = Retention { max_age: Some(HOUR * 2), ..Retention::default() };
+        assert_eq!(rotate(dir.path(), &retention, now).unwrap().removed, [paths[1].clone(), paths[0].clone()]);
+        // Every limit has to be met
+        let retention = Retention { max_count: Some(1), max_age: Some(HOUR * 2), ..Retention::default() };
+        assert_eq!(rotate(dir.path(), &retention, now).unwrap().removed, [paths[2].clone()]);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn compress_old_reports() {
+        let dir = TempDir::new("crash-compress");
+        let now = SystemTime::now();
+        let paths = reports(&dir, 3, 1000, now);
+        let retention = Retention { compress: true, max_count: Some(2), ..Retention::default() };
+        let rotated = rotate(dir.path(), &retention, now).unwrap();
+        let compressed: Vec<_> = paths.iter().map(|p| PathBuf::from(format!("{}.{COMPRESSED_EXTENSION}", p.display()))).collect();
+        assert_eq!(rotated.compressed.len(), 3);
+        assert!(compressed.iter().all(|p| rotated.compressed.contains(p)));
+        // The oldest is still the oldest after compressing
+        assert_eq!(rotated.removed, [compressed[0].clone()]);
+        assert!(paths.iter().all(|p| !p.exists()));
+        assert_eq!(std::fs::metadata(&compressed[1]).unwrap().modified().unwrap(), now - HOUR * 2);
+
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(File::open(&compressed[2]).unwrap()).read_to_string(&mut text).unwrap();
+        assert_eq!(text, "x".repeat(1000));
+
+        // Compressed reports are not compressed again, but still count
+        let newest = report(&dir, &format!("{PREFIX}3.{EXTENSION}"), 10, Duration::ZERO, now);
+        let retention = Retention { max_count: Some(1), ..retention };
+        let rotated = rotate(dir.path(), &retention, now).unwrap();
+        assert_eq!(rotated.compressed, [PathBuf::from(format!("{}.{COMPRESSED_EXTENSION}", newest.display()))]);
+        assert_eq!(rotated.removed, [compressed[2].clone(), compressed[1].clone()]);
+    }
+}
@@ -0,0 +1,283 @@
+//! Environment files, `KEY=value` lines like the ones in `/etc/default`
+//!
+//! Values are quoted like in a POSIX shell, without any expansion, so files written for `sh` are read
+//! the same way:
+//!
+//! ```sh
+//! # Comments start with `#` or `;`
+//! export DAEMON_OPTS="--verbose --port 8080"   # `export` is ignored
+//! GREETING='Hello $USER'                       # Nothing is expanded
+//! LONG=first\
+//! second                                       # Continued on the next line
+//! ```
+//!
+//! Whitespace around an unquoted value is dropped, and an unquoted `#` after whitespace starts a
+//! comment.
+//!
+//! A service's environment is built in this order, later values replace earlier ones:
+//!
+//! 1. the `default-environment` of the global configuration, view [`crate::global`]
+//! 2. the `environment` table of the definition
+//! 3. each of its `environment-files`, in the order they are listed
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::path::PathBuf;
+
+/// A file listed in `environment-files`
+///
+/// Written as the absolute path, with a `-` in front if it is fine for the file to not exist.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct EnvironmentFile {
+    /// The file
+    pub path: PathBuf,
+    /// If it is fine for the file to not exist
+    pub optional: bool,
+}
+
+impl TryFrom<String> for EnvironmentFile {
+    type Error = String;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (optional, path) = match value.strip_prefix('-') {
+            Some(path) => (true, path),
+            None => (false, value.as_str()),
+        };
+        if !path.starts_with('/') {
+            return Err(format!("`{path}` is not an absolute path"));
+        }
+        Ok(EnvironmentFile { path: PathBuf::from(path), optional })
+    }
+}
+
+impl From<EnvironmentFile> for String {
+    fn from(value: EnvironmentFile) -> Self {
+        let prefix = if value.optional { "-" } else { "" };
+        format!("{prefix}{}", value.path.display())
+    }
+}
+
+/// Errors while reading a environment file
+#[derive(thiserror::Error, Debug)]
+pub enum EnvironmentError {
+    /// The file could not be read
+    #[error("Failed to read {}: {source}", path.display())]
+    Io {
+        /// The file
+        path: PathBuf,
+        /// What went wrong
+        source: std::io::Error,
+    },
+    /// A line is not a valid assignment
+    #[error("{}:{line}: {message}", path.display())]
+    Parse {
+        /// The file
+        path: PathBuf,
+        /// Line of the error, starting at 1
+        line: usize,
+        /// What went wrong
+        message: String,
+    },
+}
+
+impl EnvironmentFile {
+    /// Reads the variables from the file, in the order they are assigned
+    ///
+    /// A optional file that does not exist has no variables.
+    pub fn read(&self) -> Result<Vec<(String, String)>, EnvironmentError> {
+        let text = match std::fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if self.optional && e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(source) => return Err(EnvironmentError::Io { path: self.path.clone(), source }),
+        };
+        parse(&text).map_err(|(line, message)| EnvironmentError::Parse {
+            path: self.path.clone(),
+            line,
+            message,
+        })
+    }
+}
+
+/// Merges the variables of every file on top of `base`, in order
+pub fn merge(base: &BTreeMap<String, String>, files: &[EnvironmentFile]) -> Result<BTreeMap<String, String>, EnvironmentError> {
+    let mut environment = base.clone();
+    for file in files {
+        environment.extend(file.read()?);
+    }
+    Ok(environment)
+}
+
+/// Parses the contents of a environment file
+///
+/// Errors are the line they are on, starting at 1, and what went wrong.
+pub fn parse(text: &str) -> Result<Vec<(String, String)>, (usize, String)> {
+    let mut chars = Lines { chars: text.chars().peekable(), line: 1 };
+    let mut variables = Vec::new();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let line = chars.line;
+        let Some(c) = chars.next() else {
+            return Ok(variables);
+        };
+        if c == '#' || c == ';' {
+            while chars.next_if(|c| *c != '\n').is_some() {}
+            continue;
+        }
+        let mut key = c.to_string();
+        while let Some(c) = chars.next_if(|c| *c != '=' && *c != '\n') {
+            key.push(c);
+        }
+        if chars.next() != Some('=') {
+            return Err((line, format!("`{}` is not KEY=value", key.trim())));
+        }
+        let key = key.trim();
+        let key = key.strip_prefix("export").filter(|k| k.starts_with(char::is_whitespace)).map_or(key, str::trim_start);
+        if !is_valid_key(key) {
+            return Err((line, format!("`{key}` is not a valid variable name")));
+        }
+        let value = value(&mut chars).map_err(|message| (line, message))?;
+        variables.push((key.to_string(), value));
+    }
+}
+
+/// Reads a value up to the end of the line, or the comment after it
+fn value(chars: &mut Lines) -> Result<String, String> {
+    let mut value = String::new();
+    // Unquoted whitespace, only kept if something follows it
+    let mut whitespace = String::new();
+    while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+    while let Some(c) = chars.next_if(|c| *c != '\n') {
+        if c == ' ' || c == '\t' {
+            whitespace.push(c);
+            continue;
+        }
+        if c == '#' && !whitespace.is_empty() {
+            while chars.next_if(|c| *c != '\n').is_some() {}
+            break;
+        }
+        value.push_str(&whitespace);
+        whitespace.clear();
+        crate::shell::unquote_char(c, chars, &mut value)?;
+    }
+    Ok(value)
+}
+
+/// Same rules as a shell variable name
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Characters of the file, counting lines for errors
+struct Lines<'a> {
+    chars: Peekable<std::str::Chars<'a>>,
+    line: usize,
+}
+
+impl Lines<'_> {
+    fn next_if(&mut self, f: impl FnOnce(&char) -> bool) -> Option<char> {
+        let c = self.chars.next_if(f)?;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+}
+
+impl Iterator for Lines<'_> {
+    type Item = char;
+    fn next(&mut self) -> Option<char> {
+        self.next_if(|_| true)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::testing::TempDir;
+
+    /// Parses `text`, which has to be valid
+    fn variables(text: &str) -> Vec<(String, String)> {
+        parse(text).unwrap_or_else(|(line, message)| panic!("{line}: {message}"))
+    }
+
+    /// A single variable
+    fn var(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn quoting() {
+        let text = "PLAIN=value\nSINGLE='Hello $USER # not a comment'\nDOUBLE=\"say \\\"hi\\\" \\$HOME \\n\"\nMIXED=a'b c'\"d\"\\ e\nEMPTY=\nQUOTED_EMPTY=''\n";
+        assert_eq!(
+            variables(text),
+            [
+                var("PLAIN", "value"),
+                var("SINGLE", "Hello $USER # not a comment"),
+                var("DOUBLE", "say \"hi\" $HOME \\n"),
+                var("MIXED", "ab cd e"),
+                var("EMPTY", ""),
+                var("QUOTED_EMPTY", ""),
+            ]
+        );
+        assert_eq!(variables("SPACED =   a  b  \t\n"), [var("SPACED", "a  b")]);
+        assert_eq!(variables("KEPT=\"  a  \"\n"), [var("KEPT", "  a  ")]);
+        assert_eq!(variables("export OPTS=\"--verbose\"\nexported=1\n"), [var("OPTS", "--verbose"), var("exported", "1")]);
+    }
+
+    #[test]
+    fn continuations() {
+        let text = "LONG=first\\\nsecond\nQUOTED=\"one \\\ntwo\"\nSINGLE='a\nb'\nAFTER=1\n";
+        assert_eq!(
+            variables(text),
+            [var("LONG", "firstsecond"), var("QUOTED", "one two"), var("SINGLE", "a\nb"), var("AFTER", "1")]
+        );
+        // Lines in errors still count the continued ones
+        assert_eq!(parse("A=1\\\n2\nB='x\\\n'\n3 = bad\n").unwrap_err().0, 5);
+    }
+
+    #[test]
+    fn comments() {
+        let text = "# A comment\n; Another one\n\n   # Indented\nA=value # trailing\nB=a#b\nC=\"a # b\" # trailing\n";
+        assert_eq!(variables(text), [var("A", "value"), var("B", "a#b"), var("C", "a # b")]);
+        assert!(variables("# Only comments\n").is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("A=1\nnot an assignment\n"), Err((2, "`not an assignment` is not KEY=value".to_string())));
+        assert_eq!(parse("1A=1\n"), Err((1, "`1A` is not a valid variable name".to_string())));
+        assert_eq!(parse("\nA='open\n"), Err((2, "unterminated single quote".to_string())));
+        assert_eq!(parse("A=\"open\n"), Err((1, "unterminated double quote".to_string())));
+        assert_eq!(parse("A=end\\"), Err((1, "trailing backslash".to_string())));
+    }
+
+    #[test]
+    fn optional_files() {
+        let dir = TempDir::new("environment-files");
+        dir.write("defaults", "MODE=default\nLEVEL=1\n");
+        dir.write("local", "MODE=local\n");
+        let file = |name: &str| EnvironmentFile::try_from(name.replace("DIR", &dir.path().display().to_string())).unwrap();
+
+        let optional = file("-DIR/missing");
+        assert!(optional.optional);
+        assert_eq!(optional.path, dir.join("missing"));
+        assert_eq!(String::from(optional.clone()), format!("-{}", dir.join("missing").display()));
+        assert!(optional.read().unwrap().is_empty());
+        assert!(matches!(file("DIR/missing").read(), Err(EnvironmentError::Io { .. })));
+        assert_eq!(
+            EnvironmentFile::try_from("-relative".to_string()),
+            Err("`relative` is not an absolute path".to_string())
+        );
+
+        let base = BTreeMap::from([var("MODE", "base"), var("BASE", "1")]);
+        let merged = merge(&base, &[file("DIR/defaults"), optional, file("-DIR/local")]).unwrap();
+        assert_eq!(merged, BTreeMap::from([var("BASE", "1"), var("LEVEL", "1"), var("MODE", "local")]));
+
+        dir.write("bad", "A=1\n\nB\n");
+        let error = file("DIR/bad").read().unwrap_err();
+        assert_eq!(error.to_string(), format!("{}:3: `B` is not KEY=value", dir.join("bad").display()));
+    }
+}
@@ -0,0 +1,88 @@
+//! How the root filesystem is checked at boot
+//!
+//! These are the same as systemd's `fsck.mode=` and `fsck.repair=` kernel parameters, which
+//! easyinit also reads under their own names.
+use serde::{Deserialize, Serialize};
+
+/// When the root filesystem is checked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    /// When `/etc/fstab` gives it a pass number, and the checker thinks it is needed
+    #[default]
+    Auto,
+    /// Every boot, even if the filesystem looks clean
+    Force,
+    /// Never
+    Skip,
+}
+
+impl Mode {
+    /// Every mode by name
+    pub const NAMES: &[&str] = &["auto", "force", "skip"];
+
+    /// Finds a mode by name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(Mode::Auto),
+            "force" => Some(Mode::Force),
+            "skip" => Some(Mode::Skip),
+            _ => None,
+        }
+    }
+
+    /// The name of the mode
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Auto => "auto",
+            Mode::Force => "force",
+            Mode::Skip => "skip",
+        }
+    }
+}
+
+/// What the checker does about errors it finds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Repair {
+    /// Only repairs that are safe without asking, `-a`
+    #[default]
+    Preen,
+    /// Every repair, answering yes to every question, `-y`
+    Yes,
+    /// Nothing, only reports the errors, `-n`
+    No,
+}
+
+impl Repair {
+    /// Every repair setting by name
+    pub const NAMES: &[&str] = &["preen", "yes", "no"];
+
+    /// Finds a repair setting by name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "preen" => Some(Repair::Preen),
+            "yes" => Some(Repair::Yes),
+            "no" => Some(Repair::No),
+            _ => None,
+        }
+    }
+
+    /// The name of the setting
+    pub fn name(self) -> &'static str {
+        match self {
+            Repair::Preen => "preen",
+            Repair::Yes => "yes",
+            Repair::No => "no",
+        }
+    }
+
+    /// The option of the checker
+    pub fn flag(self) -> &'static str {
+        match self {
+            Repair::Preen => "-a",
+            Repair::Yes => "-y",
+            Repair::No => "-n",
+        }
+    }
+}
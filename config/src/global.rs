@@ -0,0 +1,184 @@
+//! The global configuration file, `/etc/easyinit/easyinit.toml`
+//!
+//! Every key is a parameter from [`PARAMETERS`] without the `easyinit.` prefix, and takes the
+//! same values. Numbers and booleans can be written without quotes.
+//!
+//! `target`, `mask`, `wants` and `run` only change a single boot, so they are only read from the
+//! kernel command line. In this file they would change every boot, and could silently keep the
+//! default target from starting. The default target is changed by what is installed into it.
+//!
+//! ```toml
+//! loglevel = "info"
+//! log-target = "kmsg"
+//! crash-prefix = "/var/crash/"
+//! crash-max-count = 20
+//! crash-compress = true
+//! default-restart = "on-failure"
+//! default-stop-timeout = "30s"
+//! watchdog-timeout = "1min"
+//!
+//! # Environment every service starts with
+//! [default-environment]
+//! LANG = "C.UTF-8"
+//! ```
+//!
+//! A setting can come from several places, the one with the highest precedence wins:
+//!
+//! 1. `easyctl set`, until the next boot
+//! 2. the kernel command line
+//! 3. this file
+//! 4. the built in default
+//!
+//! So the file changes the defaults of a whole fleet, while a single boot can still be changed
+//! from the bootloader. `easyctl settings` shows where each value came from.
+//!
+//! Unknown keys and values that cannot be parsed are logged as a warning and ignored, like on the
+//! kernel command line.
+//!
+//! [`PARAMETERS`]: crate::params::PARAMETERS
+use crate::{Cmdline, Source, params};
+use logging::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Where the global configuration file is
+pub const PATH: &str = "/etc/easyinit/easyinit.toml";
+
+/// The table with the environment of every service
+const DEFAULT_ENVIRONMENT: &str = "default-environment";
+
+/// Errors while reading the global configuration file
+#[derive(thiserror::Error, Debug)]
+pub enum GlobalError {
+    /// The file exists but could not be read
+    #[error("Failed to read {}: {source}", path.display())]
+    Io {
+        /// The file
+        path: PathBuf,
+        /// What went wrong
+        source: std::io::Error,
+    },
+    /// The file is not valid TOML
+    #[error("{}:{line}:{column}: {message}", path.display())]
+    Parse {
+        /// The file
+        path: PathBuf,
+        /// Line of the error, starting at 1
+        line: usize,
+        /// Column of the error in characters, starting at 1
+        column: usize,
+        /// What went wrong
+        message: String,
+    },
+}
+
+/// Reads `path` and applies it to `cmdline`, a file that does not exist is not an error
+pub fn apply_file(cmdline: &mut Cmdline, path: &Path) -> Result<(), GlobalError> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(source) => return Err(GlobalError::Io { path: path.to_path_buf(), source }),
+    };
+    apply_str(cmdline, path, &text)
+}
+
+/// Applies a file that has already been read, `path` is only used for messages
+pub fn apply_str(cmdline: &mut Cmdline, path: &Path, text: &str) -> Result<(), GlobalError> {
+    let table: toml::Table = toml::from_str(text).map_err(|e| {
+        let (line, column) = crate::service::toml_position(text, &e);
+        GlobalError::Parse { path: path.to_path_buf(), line, column, message: e.message().to_string() }
+    })?;
+    for (key, value) in table {
+        if key == DEFAULT_ENVIRONMENT {
+            apply_environment(cmdline, path, value);
+            continue;
+        }
+        let name = format!("{}{key}", params::PREFIX);
+        let Some(parameter) = params::find(&name) else {
+            warn!("Unknown setting `{key}` in {}, ignoring", path.display());
+            continue;
+        };
+        if parameter.per_boot {
+            warn!("Ignoring `{key}` in {}, it only works on the kernel command line", path.display());
+            continue;
+        }
+        let value = match value {
+            toml::Value::String(s) => s,
+            toml::Value::Integer(i) => i.to_string(),
+            toml::Value::Boolean(b) => if b { "yes" } else { "no" }.to_string(),
+            other => {
+                warn!("Ignoring `{key}` in {}: expected a string, number or boolean, found {}", path.display(), other.type_str());
+                continue;
+            }
+        };
+        if let Err(e) = parameter.apply(cmdline, &value, Source::ConfigFile) {
+            warn!("Ignoring `{key} = {value:?}` in {}: {e}", path.display());
+        }
+    }
+    Ok(())
+}
+
+fn apply_environment(cmdline: &mut Cmdline, path: &Path, value: toml::Value) {
+    let toml::Value::Table(table) = value else {
+        warn!("Ignoring `{DEFAULT_ENVIRONMENT}` in {}: expected a table", path.display());
+        return;
+    };
+    for (key, value) in table {
+        match value {
+            toml::Value::String(value) => {
+                cmdline.default_environment.insert(key, value);
+            }
+            other => warn!(
+                "Ignoring `{DEFAULT_ENVIRONMENT}.{key}` in {}: expected a string, found {}",
+                path.display(),
+                other.type_str()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LogTarget, Target};
+
+    fn apply(cmdline: &mut Cmdline, text: &str) {
+        apply_str(cmdline, Path::new("easyinit.toml"), text).unwrap();
+    }
+
+    #[test]
+    fn precedence() {
+        let mut cmdline = Cmdline::parse("easyinit.log-target=console");
+        apply(&mut cmdline, "log-target = \"kmsg\"\ndefault-stop-timeout = \"1min\"\n[default-environment]\nLANG = \"C\"\n");
+        assert_eq!(cmdline.log_target(), LogTarget::Console);
+        let defaults = cmdline.service_defaults();
+        assert_eq!(defaults.stop_timeout, std::time::Duration::from_secs(60));
+        assert_eq!(defaults.environment.get("LANG").map(String::as_str), Some("C"));
+    }
+
+    #[test]
+    fn per_boot_settings_are_ignored() {
+        let mut cmdline = Cmdline::default();
+        apply(&mut cmdline, "target = \"rescue\"\nmask = \"sshd\"\nwants = \"debug-shell\"\nrun = \"/bin/sh\"\n");
+        assert_eq!(cmdline.target(), Target::Default);
+        assert!(cmdline.masked().is_empty());
+        assert!(cmdline.wants().is_empty());
+        assert!(cmdline.run().is_none());
+    }
+
+    #[test]
+    fn reload_resets_removed_settings() {
+        let dir = std::env::temp_dir().join(format!("easyinit-global-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("easyinit.toml");
+        std::fs::write(&path, "loglevel = \"debug\"\n").unwrap();
+        let mut cmdline = Cmdline::default();
+        cmdline.read_config(&path).unwrap();
+        assert_eq!(cmdline.loglevel(), log::LevelFilter::Debug);
+        std::fs::write(&path, "").unwrap();
+        cmdline.reload_config(&path).unwrap();
+        assert_eq!(cmdline.loglevel(), log::LevelFilter::Warn);
+        std::fs::write(&path, "loglevel = [").unwrap();
+        assert!(matches!(cmdline.reload_config(&path), Err(GlobalError::Parse { line: 1, .. })));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
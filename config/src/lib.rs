@@ -1,25 +1,46 @@
-//! Parses the kernel's command line and 
+//! Parses the kernel's command line and service definitions
+pub mod condition;
 mod crash_file_gen;
+pub mod crash_retention;
+pub mod environment;
+pub mod fsck;
+pub mod global;
+pub mod loader;
+pub mod mountpoint;
+pub mod params;
+pub mod service;
+mod shell;
+pub mod specifier;
+#[cfg(feature = "systemd")]
+pub mod systemd;
+pub mod sysv;
+pub mod tokenizer;
 
 // cSpell:words Cmdline
-use std::fs::File;
 use log::LevelFilter;
+use logging::prelude::*;
+use serde::{Deserialize, Serialize};
+use service::{RestartPolicy, ServiceDefaults};
 use std::path::PathBuf;
-use std::sync::LazyLock;
-use std::io::prelude::*;
 /// Represents parameters passed to easyinit via the kernel command line.
 #[derive(Debug)]
 #[non_exhaustive]
 pub struct Cmdline{
-    /// This uses the kernels `quite` and `easyinit.loglevel` options.
+    /// This uses the kernels `quiet` and `easyinit.loglevel` options.
     /// 
-    /// `quite` is an alias for `easyinit.loglevel=0`.
+    /// `quiet` is an alias for `easyinit.loglevel=0`.
     /// 
     /// `easyinit.loglevel` will take priority if both are present.
     /// 
-    /// Default is Warning level (3)
-    pub loglevel: LevelFilter,
-
+    /// Accepts either a number from `0` (off) to `5` (trace) or the name of the level, like `info`.
+    /// The full list of parameters is in [`params::PARAMETERS`].
+    /// 
+    /// Default is Warning level (2)
+    loglevel: IsSet<LevelFilter>,
+    /// Where easyinit logs to, uses the `easyinit.log-target` option.
+    /// 
+    /// Default is [`LogTarget::Console`]
+    log_target: IsSet<LogTarget>,
 
     /// An init system crashing is bad news. So a crash report should be generated if the is the case.
     /// 
@@ -30,37 +51,309 @@ pub struct Cmdline{
     /// 
     /// 
     /// [LHS]: https://refspecs.linuxfoundation.org/FHS_3.0/fhs/index.html
-    crash_report_prefix: PathBuf,
+    crash_report_prefix: IsSet<PathBuf>,
     /// The crash report is a system
     /// 
     /// Uses the `easyinit.crash-path` and preferred over the crash prefix option, 
     /// and if used it uses a set path and overwrites any existing file.
-    crash_report_file: LazyLock<PathBuf>
-    
+    /// 
+    /// [`IsSet::Lazily`] if not set, the name then gets generated when a crash happens.
+    crash_report_file: IsSet<PathBuf>,
+
+    /// How many generated crash reports are kept, uses the `easyinit.crash-max-count` option.
+    /// 
+    /// `0` keeps every report. Default is `10`
+    crash_max_count: IsSet<u64>,
+    /// Total size in bytes of the generated crash reports kept, uses the `easyinit.crash-max-size` option.
+    /// 
+    /// `0` is no limit, which is the default.
+    crash_max_size: IsSet<u64>,
+    /// Seconds a generated crash report is kept, uses the `easyinit.crash-max-age` option.
+    /// 
+    /// `0` is no limit, which is the default.
+    crash_max_age: IsSet<u64>,
+    /// If generated crash reports are compressed, uses the `easyinit.crash-compress` option.
+    /// 
+    /// Default is `false`
+    crash_compress: IsSet<bool>,
+
+    /// Restart policy of services that don't set one, uses the `easyinit.default-restart` option.
+    /// 
+    /// Default is [`RestartPolicy::No`]
+    default_restart: IsSet<RestartPolicy>,
+    /// Seconds services get to stop before they are killed, uses the `easyinit.default-stop-timeout` option.
+    /// 
+    /// Default is `90`
+    default_stop_timeout: IsSet<u64>,
+    /// Environment every service starts with, only set by the `[default-environment]` table of the
+    /// global configuration file.
+    default_environment: std::collections::BTreeMap<String, String>,
+
+    /// The hardware watchdog, uses the `easyinit.watchdog-device` option.
+    /// 
+    /// Default is `/dev/watchdog0`
+    watchdog_device: IsSet<PathBuf>,
+    /// Seconds the hardware watchdog is set to, uses the `easyinit.watchdog-timeout` option.
+    /// 
+    /// `0` disables the watchdog, which is the default.
+    watchdog_timeout: IsSet<u64>,
+
+    /// Seconds each generator gets to run, uses the `easyinit.generator-timeout` option.
+    /// 
+    /// Default is `5`
+    generator_timeout: IsSet<u64>,
+    /// What to do with files in `/tmp` or `/run` at boot, uses the `easyinit.nonempty-mountpoint` option.
+    /// 
+    /// Default is [`mountpoint::Policy::Refuse`] for every mount point
+    nonempty_mountpoint: IsSet<mountpoint::Policies>,
+    /// When the root filesystem is checked, uses the `easyinit.fsck-mode` or `fsck.mode` option.
+    /// 
+    /// Default is [`fsck::Mode::Auto`]
+    fsck_mode: IsSet<fsck::Mode>,
+    /// What the root filesystem check repairs, uses the `easyinit.fsck-repair` or `fsck.repair` option.
+    /// 
+    /// Default is [`fsck::Repair::Preen`]
+    fsck_repair: IsSet<fsck::Repair>,
+    /// If the root filesystem stays read only when `/etc/fstab` does not say, uses the `easyinit.root-mode` option.
+    /// 
+    /// The kernel's `ro` and `rw` only pick how the root is mounted first, so it can be checked. Default is `false`
+    root_read_only: IsSet<bool>,
+
+    /// What to boot into, uses the `easyinit.target` option.
+    /// 
+    /// `single`, `S`, `1` and `rescue` are aliases for `easyinit.target=rescue`,
+    /// and `emergency` is an alias for `easyinit.target=emergency`.
+    /// 
+    /// Default is [`Target::Default`]
+    target: IsSet<Target>,
+
+    /// Services that are not started this boot, uses the `easyinit.mask` option.
+    /// 
+    /// A comma separated list, repeating the option adds to the list. Default is empty.
+    mask: IsSet<Vec<String>>,
+    /// Services started this boot on top of what the target wants, uses the `easyinit.wants` option.
+    /// 
+    /// A comma separated list, repeating the option adds to the list. Default is empty.
+    wants: IsSet<Vec<String>>,
+    /// A command run once the target is booted, uses the `easyinit.run` option.
+    /// 
+    /// [`IsSet::Lazily`] if not set.
+    run: IsSet<String>,
+
+    /// Arguments after the `--` separator, the kernel hands these to init.
+    pub init_args: Vec<String>,
 }
 impl Cmdline{
     /// Reads the command line from `/proc/cmdline` and parses it.
     /// 
-    /// # Panics
-    /// 
-    /// Panics if `/proc/cmdline` cannot be opened.
-    pub fn new()->Self{
+    /// Fails if `/proc/cmdline` cannot be read, like when `/proc` is not mounted yet.
+    /// [`Cmdline::default`] then still boots with the defaults.
+    pub fn new()->std::io::Result<Self>{
         Cmdline::use_file("/proc/cmdline".as_ref())
     }
     /// Uses a specific file as the source of the command line.
     /// 
     /// Used for testing.
-    pub fn use_file(path:&std::path::Path)->Self{
-        let mut r = Cmdline::default();
+    pub fn use_file(path:&std::path::Path)->std::io::Result<Self>{
+        let buf = std::fs::read_to_string(path)?;
+        Ok(Cmdline::parse(&buf))
+    }
 
-        let buf = std::fs::read_to_string(path);
+    /// Parses a command line that has already been read.
+    /// 
+    /// Repeated parameters are allowed, the last one wins.
+    /// Unknown `easyinit.*` parameters and values that cannot be parsed are logged as a warning and ignored.
+    pub fn parse(line:&str)->Self{
+        let mut r = Cmdline::default();
+        let tokens = tokenizer::tokenize(line);
 
+        // Aliases are applied first so that the parameter they alias always wins
+        for param in tokens.params.iter().filter(|p| p.value.is_none()){
+            if let Some((target, value)) = params::find_alias(&param.key){
+                r.apply(target, &param.key, value);
+            }
+        }
+        for param in &tokens.params{
+            match (params::find(&param.key), &param.value){
+                (Some(target), Some(value)) => r.apply(target, &param.key, value),
+                (Some(target), None) => warn!("Kernel parameter `{}` needs a value, like `{}=<{}>`", param.key, target.name, target.kind.placeholder()),
+                (None, _) if param.key.starts_with(params::PREFIX) => warn!("Unknown kernel parameter `{}`, ignoring", param.key),
+                (None, _) => {}
+            }
+        }
+        r.init_args = tokens.init_args;
         r
     }
 
-    pub fn crash_report_path(&self) -> PathBuf{
-        todo!()
-        
+    /// Applies the global configuration file on top, view [`global`] for the format.
+    /// 
+    /// A file that does not exist is not an error. Values the kernel command line or
+    /// [`Cmdline::set`] already set with a higher precedence are kept.
+    pub fn read_config(&mut self, path:&std::path::Path)->Result<(), global::GlobalError>{
+        global::apply_file(self, path)
+    }
+
+    /// Reads the global configuration file again, view [`Cmdline::read_config`].
+    /// 
+    /// Settings that came from the file are reset first, so removing a line from the file
+    /// brings back the default. Settings from the kernel command line or runtime are kept.
+    /// If the file fails to parse nothing is changed.
+    pub fn reload_config(&mut self, path:&std::path::Path)->Result<(), global::GlobalError>{
+        let mut fresh = Cmdline::default();
+        fresh.read_config(path)?;
+        // Listing every field, so a new one can't be forgotten
+        let Cmdline{
+            loglevel, log_target, crash_report_prefix, crash_report_file, crash_max_count, crash_max_size,
+            crash_max_age, crash_compress, default_restart, default_stop_timeout, default_environment,
+            watchdog_device, watchdog_timeout, generator_timeout, nonempty_mountpoint,
+            fsck_mode, fsck_repair, root_read_only, target, mask, wants, run, init_args: _,
+        } = fresh;
+        self.loglevel.replace_config(loglevel);
+        self.log_target.replace_config(log_target);
+        self.crash_report_prefix.replace_config(crash_report_prefix);
+        self.crash_report_file.replace_config(crash_report_file);
+        self.crash_max_count.replace_config(crash_max_count);
+        self.crash_max_size.replace_config(crash_max_size);
+        self.crash_max_age.replace_config(crash_max_age);
+        self.crash_compress.replace_config(crash_compress);
+        self.default_restart.replace_config(default_restart);
+        self.default_stop_timeout.replace_config(default_stop_timeout);
+        self.default_environment = default_environment;
+        self.watchdog_device.replace_config(watchdog_device);
+        self.watchdog_timeout.replace_config(watchdog_timeout);
+        self.generator_timeout.replace_config(generator_timeout);
+        self.nonempty_mountpoint.replace_config(nonempty_mountpoint);
+        self.fsck_mode.replace_config(fsck_mode);
+        self.fsck_repair.replace_config(fsck_repair);
+        self.root_read_only.replace_config(root_read_only);
+        self.target.replace_config(target);
+        self.mask.replace_config(mask);
+        self.wants.replace_config(wants);
+        self.run.replace_config(run);
+        Ok(())
+    }
+
+    /// Applies a single kernel parameter, warning if the value is malformed
+    fn apply(&mut self, target:&params::Parameter, key:&str, value:&str){
+        if let Err(e) = target.apply(self, value, Source::KernelCmdline){
+            warn!("Ignoring kernel parameter `{key}={value}`: {e}");
+        }
+    }
+
+    /// Sets a parameter by its name, as if it was `name=value` on the command line.
+    /// 
+    /// The value is only kept if `source` has the same or higher precedence as where the
+    /// current value came from, view [`Source`].
+    pub fn set(&mut self, name:&str, value:&str, source:Source)->Result<(),String>{
+        let target = params::find(name).ok_or_else(|| format!("Unknown parameter `{name}`"))?;
+        target.apply(self, value, source)
+    }
+
+    /// Every parameter with its current value and where it came from
+    pub fn settings(&self)->Vec<Setting>{
+        params::PARAMETERS.iter().map(|p| {
+            let (value, source) = p.show(self);
+            Setting { name: p.name.to_string(), value, source }
+        }).collect()
+    }
+
+    /// The maximum level that should be logged
+    pub fn loglevel(&self)->LevelFilter{
+        self.loglevel.get().copied().unwrap_or(LevelFilter::Warn)
+    }
+
+    /// Where easyinit logs to
+    pub fn log_target(&self)->LogTarget{
+        self.log_target.get().copied().unwrap_or_default()
+    }
+
+    /// Defaults for settings a service definition leaves out
+    pub fn service_defaults(&self)->ServiceDefaults{
+        ServiceDefaults {
+            restart: self.default_restart.get().copied().unwrap_or_default(),
+            stop_timeout: std::time::Duration::from_secs(self.default_stop_timeout.get().copied().unwrap_or(90)),
+            environment: self.default_environment.clone(),
+        }
+    }
+
+    /// The hardware watchdog device and its timeout, `None` if it is disabled
+    pub fn watchdog(&self)->Option<(PathBuf, std::time::Duration)>{
+        let timeout = self.watchdog_timeout.get().copied().filter(|t| *t != 0)?;
+        let device = self.watchdog_device.get()?.clone();
+        Some((device, std::time::Duration::from_secs(timeout)))
+    }
+
+    /// How long each generator gets to run before it is killed
+    pub fn generator_timeout(&self)->std::time::Duration{
+        std::time::Duration::from_secs(self.generator_timeout.get().copied().unwrap_or(5))
+    }
+
+    /// What to do with mount points that are not empty at boot
+    pub fn nonempty_mountpoint(&self)->mountpoint::Policies{
+        self.nonempty_mountpoint.get().cloned().unwrap_or_default()
+    }
+
+    /// When the root filesystem is checked
+    pub fn fsck_mode(&self)->fsck::Mode{
+        self.fsck_mode.get().copied().unwrap_or_default()
+    }
+
+    /// What the root filesystem check repairs
+    pub fn fsck_repair(&self)->fsck::Repair{
+        self.fsck_repair.get().copied().unwrap_or_default()
+    }
+
+    /// If the root filesystem stays read only when `/etc/fstab` does not say
+    pub fn root_read_only(&self)->bool{
+        self.root_read_only.get().copied().unwrap_or(false)
+    }
+
+    /// The target to boot into
+    pub fn target(&self)->Target{
+        self.target.get().cloned().unwrap_or_default()
+    }
+
+    /// Services that are not started this boot
+    pub fn masked(&self)->&[String]{
+        self.mask.get().map_or(&[], Vec::as_slice)
+    }
+
+    /// Services started this boot on top of what the target wants
+    pub fn wants(&self)->&[String]{
+        self.wants.get().map_or(&[], Vec::as_slice)
+    }
+
+    /// The command to run once the target is booted
+    pub fn run(&self)->Option<service::CommandLine>{
+        let args = shell::split(self.run.get()?).ok()?;
+        service::CommandLine::try_from(args).ok()
+    }
+
+    /// Where the crash report should be written to.
+    /// 
+    /// This is `easyinit.crash-path` if it is set, otherwise a new file in `easyinit.crash-prefix`.
+    pub fn crash_report_path(&self) -> std::io::Result<PathBuf>{
+        self.crash_file().path()
+    }
+
+    /// How crash reports are named, view [`CrashFile`]
+    pub fn crash_file(&self)->CrashFile{
+        match (self.crash_report_file.get(), self.crash_report_prefix.get()){
+            (Some(path), _) => CrashFile::Fixed(path.clone()),
+            (None, Some(prefix)) => CrashFile::Generated(prefix.clone()),
+            (None, None) => CrashFile::Generated(PathBuf::from("/var/log/")),
+        }
+    }
+
+    /// The limits on generated crash reports
+    pub fn crash_retention(&self)->crash_retention::Retention{
+        let limit = |value:&IsSet<u64>| value.get().copied().filter(|v| *v != 0);
+        crash_retention::Retention {
+            max_count: limit(&self.crash_max_count),
+            max_size: limit(&self.crash_max_size),
+            max_age: limit(&self.crash_max_age).map(std::time::Duration::from_secs),
+            compress: self.crash_compress.get().copied().unwrap_or(false),
+        }
     }
 }
 impl Default for Cmdline{
@@ -69,23 +362,275 @@ impl Default for Cmdline{
         let prefix =  PathBuf::from("/var/log/");
         
         Cmdline {
-            loglevel: LevelFilter::Warn,
-            crash_report_prefix:prefix,
-            crash_report_file: todo!()
-            // crash_report_file: LazyLock::new(&||{crash_file_gen::gen_filename(&prefix)}),
-            
+            loglevel: IsSet::Implicit(LevelFilter::Warn),
+            log_target: IsSet::Implicit(LogTarget::Console),
+            crash_report_prefix: IsSet::Implicit(prefix),
+            crash_report_file: IsSet::Lazily,
+            crash_max_count: IsSet::Implicit(10),
+            crash_max_size: IsSet::Implicit(0),
+            crash_max_age: IsSet::Implicit(0),
+            crash_compress: IsSet::Implicit(false),
+            default_restart: IsSet::Implicit(RestartPolicy::No),
+            default_stop_timeout: IsSet::Implicit(90),
+            default_environment: Default::default(),
+            watchdog_device: IsSet::Implicit(PathBuf::from("/dev/watchdog0")),
+            watchdog_timeout: IsSet::Implicit(0),
+            generator_timeout: IsSet::Implicit(5),
+            nonempty_mountpoint: IsSet::Implicit(mountpoint::Policies::default()),
+            fsck_mode: IsSet::Implicit(fsck::Mode::Auto),
+            fsck_repair: IsSet::Implicit(fsck::Repair::Preen),
+            root_read_only: IsSet::Implicit(false),
+            target: IsSet::Implicit(Target::Default),
+            mask: IsSet::Implicit(Vec::new()),
+            wants: IsSet::Implicit(Vec::new()),
+            run: IsSet::Lazily,
+            init_args: Vec::new(),
         }
     }
 }
 
+/// Where easyinit logs to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogTarget{
+    /// `/dev/console`
+    #[default]
+    Console,
+    /// The kernel log, `/dev/kmsg`
+    Kmsg,
+}
+impl LogTarget{
+    /// The name used in the `easyinit.log-target` option
+    pub fn name(self)->&'static str{
+        match self{
+            LogTarget::Console => "console",
+            LogTarget::Kmsg => "kmsg",
+        }
+    }
+    /// The target from its name, `None` if there is no target with that name
+    pub fn from_name(name:&str)->Option<Self>{
+        match name{
+            "console" => Some(LogTarget::Console),
+            "kmsg" => Some(LogTarget::Kmsg),
+            _ => None,
+        }
+    }
+}
+
+/// What easyinit boots into
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Target{
+    /// The normal boot
+    #[default]
+    Default,
+    /// Mounts the local filesystems, then only starts a root shell on the console
+    Rescue,
+    /// Only starts a root shell on the console, nothing else is mounted or started
+    Emergency,
+    /// Any other target
+    Named(String),
+}
+impl From<&str> for Target{
+    fn from(name:&str)->Self{
+        match name{
+            "default" => Target::Default,
+            "rescue" => Target::Rescue,
+            "emergency" => Target::Emergency,
+            name => Target::Named(name.to_string()),
+        }
+    }
+}
+impl std::fmt::Display for Target{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self{
+            Target::Default => "default",
+            Target::Rescue => "rescue",
+            Target::Emergency => "emergency",
+            Target::Named(name) => name,
+        })
+    }
+}
+
+/// Where a crash report is written to
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CrashFile{
+    /// Always the same file, set with `easyinit.crash-path`, overwritten by every crash
+    Fixed(PathBuf),
+    /// A new `easyinit-YYYY-MM-DD-N.crash` for every crash, in the directory set with `easyinit.crash-prefix`
+    Generated(PathBuf),
+}
+impl CrashFile{
+    /// The file the next crash report is written to
+    pub fn path(&self)->std::io::Result<PathBuf>{
+        match self{
+            CrashFile::Fixed(path) => Ok(path.clone()),
+            CrashFile::Generated(dir) => crash_file_gen::gen_filename(dir),
+        }
+    }
 
+    /// Compresses and removes old generated reports, a fixed file is left alone
+    pub fn rotate(&self, retention:&crash_retention::Retention)->std::io::Result<crash_retention::Rotated>{
+        match self{
+            CrashFile::Fixed(_) => Ok(crash_retention::Rotated::default()),
+            CrashFile::Generated(dir) => crash_retention::rotate(dir, retention, std::time::SystemTime::now()),
+        }
+    }
 }
 /// The idea that is that, is it implicitly set in the command line, or 
+/// explicitly set by someone, and if so, where.
 #[derive(Debug,PartialEq, Eq, PartialOrd, Ord)]
 pub enum IsSet<T>{ // TODO: Better name
+    /// Not set, so the default is used
     Implicit(T),
-    Explicit(T),
+    /// Set on purpose, with where it was set
+    Explicit(T, Source),
     /// Find it out when needed
     Lazily
 }
+impl<T> IsSet<T>{
+    /// The value, `None` if it is [`IsSet::Lazily`]
+    pub fn get(&self)->Option<&T>{
+        match self{
+            IsSet::Implicit(v) | IsSet::Explicit(v, _) => Some(v),
+            IsSet::Lazily => None,
+        }
+    }
+    /// Where the value came from, [`Source::Default`] if it was never set
+    pub fn source(&self)->Source{
+        match self{
+            IsSet::Explicit(_, source) => *source,
+            IsSet::Implicit(_) | IsSet::Lazily => Source::Default,
+        }
+    }
+    /// Replaces the value with `fresh`, unless the current one came from a source with higher
+    /// precedence than [`Source::ConfigFile`]
+    fn replace_config(&mut self, fresh:IsSet<T>){
+        if self.source() <= Source::ConfigFile{
+            *self = fresh;
+        }
+    }
+    /// Sets the value, unless the current one came from a source with higher precedence.
+    /// 
+    /// Returns if the value was changed.
+    pub fn set(&mut self, value:T, source:Source)->bool{
+        if source < self.source(){
+            return false;
+        }
+        *self = IsSet::Explicit(value, source);
+        true
+    }
+}
+impl<T> IsSet<Vec<T>>{
+    /// Adds to the list if it was set from the same source, otherwise sets it like [`IsSet::set`]
+    pub fn extend(&mut self, values:Vec<T>, source:Source)->bool{
+        match self{
+            IsSet::Explicit(list, current) if *current == source => {
+                list.extend(values);
+                true
+            },
+            _ => self.set(values, source),
+        }
+    }
+}
+
+/// Where a setting came from.
+/// 
+/// Ordered by precedence, a setting from a later variant overrides one from an earlier variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source{
+    /// The built in default
+    Default,
+    /// The global configuration file
+    ConfigFile,
+    /// The kernel command line
+    KernelCmdline,
+    /// Changed while easyinit is running, like with `easyctl`
+    Runtime,
+}
+impl std::fmt::Display for Source{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self{
+            Source::Default => "default",
+            Source::ConfigFile => "config file",
+            Source::KernelCmdline => "kernel command line",
+            Source::Runtime => "runtime",
+        })
+    }
+}
+
+/// The current value of a parameter, as returned by [`Cmdline::settings`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Setting{
+    /// Name of the parameter, like `easyinit.loglevel`
+    pub name: String,
+    /// The value, `None` if it is not set or worked out when needed
+    pub value: Option<String>,
+    /// Where the value came from
+    pub source: Source,
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::path::Path;
+
+    fn fixture(name:&str)->Cmdline{
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/cmdline").join(name);
+        Cmdline::use_file(&path).unwrap()
+    }
+
+    #[test]
+    fn typical(){
+        let cmdline = fixture("typical.txt");
+        assert_eq!(cmdline.loglevel(), LevelFilter::Info);
+        assert_eq!(cmdline.log_target(), LogTarget::Kmsg);
+        assert_eq!(cmdline.target(), Target::Default);
+        assert!(cmdline.init_args.is_empty());
+    }
+
+    #[test]
+    fn quoted_values(){
+        let cmdline = fixture("quoted.txt");
+        assert_eq!(cmdline.crash_file(), CrashFile::Fixed(PathBuf::from("/var/log/easy init.crash")));
+        assert_eq!(cmdline.service_defaults().stop_timeout, std::time::Duration::from_secs(30));
+    }
+
+    #[test]
+    fn last_one_wins(){
+        let cmdline = fixture("repeated.txt");
+        assert_eq!(cmdline.loglevel(), LevelFilter::Trace);
+        assert_eq!(cmdline.target(), Target::Emergency);
+    }
+
+    #[test]
+    fn init_args_and_aliases(){
+        let cmdline = fixture("init-args.txt");
+        assert_eq!(cmdline.loglevel(), LevelFilter::Off);
+        assert_eq!(cmdline.watchdog(), Some((PathBuf::from("/dev/watchdog0"), std::time::Duration::from_secs(20))));
+        // `single` after `--` is for init, not the rescue alias
+        assert_eq!(cmdline.target(), Target::Default);
+        assert_eq!(cmdline.init_args, ["single", "--verbose", "a b"]);
+    }
+
+    #[test]
+    fn invalid_values_are_ignored(){
+        let cmdline = fixture("invalid.txt");
+        // The malformed `easyinit.loglevel` is dropped, so the `quiet` alias is kept
+        assert_eq!(cmdline.loglevel(), LevelFilter::Off);
+        assert_eq!(cmdline.log_target(), LogTarget::Console);
+        let loglevel = cmdline.settings().into_iter().find(|s| s.name == "easyinit.loglevel").unwrap();
+        assert_eq!(loglevel.source, Source::KernelCmdline);
+    }
+
+    #[test]
+    fn kernel_ro_is_not_the_root_mode(){
+        // `ro` is for the first mount of the root, fstab decides how it is remounted
+        assert!(!fixture("typical.txt").root_read_only());
+        assert!(Cmdline::parse("ro easyinit.root-mode=ro").root_read_only());
+    }
+
+    #[test]
+    fn missing_file(){
+        assert!(Cmdline::use_file(Path::new("/nonexistent/cmdline")).is_err());
+    }
+}
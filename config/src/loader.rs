@@ -0,0 +1,420 @@
+//! Finds service definitions in the layered service directories
+//!
+//! Definitions are looked up in these directories, from highest to lowest precedence:
+//!
+//! 1. `/run/easyinit/generator.early`, for generators that override the administrator
+//! 2. `/etc/easyinit/services`, for the administrator
+//! 3. `/run/easyinit/services`, for definitions that only last until the next boot
+//! 4. `/run/easyinit/generator`, written by generators
+//! 5. `/usr/lib/easyinit/services`, for definitions shipped by packages
+//! 6. `/run/easyinit/generator.late`, for generators that only fill in what is missing
+//!
+//! The `name.toml` in the directory with the highest precedence is used, the others are ignored.
+//!
+//! Drop-in fragments in `name.d/*.toml` are then applied on top of it, from every directory.
+//! They are applied in the order of their file name, no matter which directory they are in, and a
+//! fragment masks one with the same file name in a directory with lower precedence.
+//! A fragment only has to contain the keys it changes, tables are merged key by key and any other
+//! value, including arrays, is replaced as a whole.
+//!
+//! ```toml
+//! # /etc/easyinit/services/example.d/10-debug.toml
+//! [service.environment]
+//! EXAMPLE_DEBUG = "1"
+//! ```
+//!
+//! # Templates
+//!
+//! A definition named `name@.toml` is a template. It is never loaded on its own, only as an
+//! instance like `name@instance`, where `%i` in its strings is replaced by the instance, see
+//! [`crate::specifier`] for the other specifiers.
+//!
+//! ```toml
+//! # /etc/easyinit/services/getty@.toml, started as getty@tty1
+//! [service]
+//! exec = "/sbin/agetty --noclear %i linux"
+//! restart = "always"
+//! ```
+//!
+//! A `name@instance.toml` replaces the template for that instance. Drop-ins from both
+//! `name@.d` and `name@instance.d` are applied, a fragment in the instance's directory masks
+//! one with the same file name in the template's.
+use crate::service::{EXTENSION, LoadError, ServiceDefinition};
+use crate::specifier::Specifiers;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// The service directories, relative to the root
+const DIRS: &[&str] = &[
+    GENERATOR_EARLY_DIR,
+    "etc/easyinit/services",
+    "run/easyinit/services",
+    GENERATOR_DIR,
+    "usr/lib/easyinit/services",
+    GENERATOR_LATE_DIR,
+];
+
+/// Where generators write definitions, relative to the root
+pub const GENERATOR_DIR: &str = "run/easyinit/generator";
+/// Where generators write definitions that replace the administrator's, relative to the root
+pub const GENERATOR_EARLY_DIR: &str = "run/easyinit/generator.early";
+/// Where generators write definitions that are replaced by every other one, relative to the root
+pub const GENERATOR_LATE_DIR: &str = "run/easyinit/generator.late";
+
+/// A set of directories definitions are loaded from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceDirs {
+    /// Highest precedence first
+    dirs: Vec<PathBuf>,
+    /// Root that files for specifiers like `%H` are read under
+    root: PathBuf,
+}
+
+/// What changed when the definitions were loaded again
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReloadDiff {
+    /// Services that have a definition now, but did not before
+    pub added: Vec<String>,
+    /// Services that no longer have a definition
+    pub removed: Vec<String>,
+    /// Services whose definition changed
+    pub changed: Vec<String>,
+    /// Changed services that are running with the old definition until they are restarted, and
+    /// removed services that keep running until they stop
+    pub needs_restart: Vec<String>,
+    /// Definitions or configuration that failed to load, these keep their old value
+    pub errors: Vec<String>,
+}
+
+impl ReloadDiff {
+    /// If nothing changed and nothing failed
+    pub fn is_empty(&self) -> bool {
+        *self == ReloadDiff::default()
+    }
+}
+
+/// A definition with all its drop-ins applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadedService {
+    /// The merged definition
+    pub definition: ServiceDefinition,
+    /// The main file
+    pub path: PathBuf,
+    /// Drop-in fragments, in the order they were applied
+    pub dropins: Vec<PathBuf>,
+    /// The main file and drop-ins merged, before it was turned into [`LoadedService::definition`]
+    pub merged: toml::Table,
+}
+
+impl LoadedService {
+    /// The merged definition as TOML, for showing to the user
+    pub fn merged_toml(&self) -> String {
+        toml::to_string(&self.merged).unwrap_or_default()
+    }
+}
+
+impl ServiceDirs {
+    /// The standard directories
+    pub fn system() -> Self {
+        ServiceDirs::with_root(Path::new("/"))
+    }
+
+    /// The standard directories, under a different root
+    pub fn with_root(root: &Path) -> Self {
+        ServiceDirs { dirs: DIRS.iter().map(|d| root.join(d)).collect(), root: root.to_path_buf() }
+    }
+
+    /// A custom list of directories, highest precedence first
+    pub fn new(dirs: Vec<PathBuf>) -> Self {
+        ServiceDirs { dirs, root: PathBuf::from("/") }
+    }
+
+    /// The directories, highest precedence first
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    /// Names of every service that has a definition, without templates
+    pub fn names(&self) -> BTreeSet<String> {
+        self.stems().into_iter().filter(|name| !is_template(name)).collect()
+    }
+
+    /// Names of every template, like `getty@`
+    pub fn templates(&self) -> BTreeSet<String> {
+        self.stems().into_iter().filter(|name| is_template(name)).collect()
+    }
+
+    /// Every definition file name without the extension
+    fn stems(&self) -> BTreeSet<String> {
+        self.dirs
+            .iter()
+            .flat_map(|dir| toml_files(dir))
+            .filter_map(|path| path.file_stem().map(|s| s.to_string_lossy().into_owned()))
+            .collect()
+    }
+
+    /// Loads every service, errors are collected instead of stopping at the first one
+    pub fn load_all(&self) -> (Vec<LoadedService>, Vec<LoadError>) {
+        let mut loaded = Vec::new();
+        let mut errors = Vec::new();
+        for name in self.names() {
+            match self.load(&name) {
+                Ok(service) => loaded.push(service),
+                Err(e) => errors.push(e),
+            }
+        }
+        (loaded, errors)
+    }
+
+    /// Loads a single service and applies its drop-ins
+    ///
+    /// An instance like `getty@tty1` is loaded from its template if it has no definition of its own.
+    pub fn load(&self, name: &str) -> Result<LoadedService, LoadError> {
+        if is_template(name) || name.contains('/') {
+            return Err(LoadError::NotFound { name: name.to_string() });
+        }
+        let instance = split_instance(name);
+        // The instance's own definition first, then its template
+        let mut stems = vec![name.to_string()];
+        stems.extend(instance.map(|(template, _)| format!("{template}@")));
+        let path = stems
+            .iter()
+            .flat_map(|stem| self.dirs.iter().map(move |dir| dir.join(format!("{stem}.{EXTENSION}"))))
+            .find(|path| path.is_file())
+            .ok_or_else(|| LoadError::NotFound { name: name.to_string() })?;
+        // Lowest precedence first, so the instance's drop-ins mask the template's
+        let dropins = self.dropins(stems.iter().rev());
+
+        let mut merged = read_table(&path)?;
+        for dropin in &dropins {
+            merge(&mut merged, read_table(dropin)?);
+        }
+        self.expand(name, &mut merged).map_err(|(key, source)| LoadError::Specifier { path: path.clone(), key, source })?;
+        let mut definition: ServiceDefinition = toml::Value::Table(merged.clone())
+            .try_into()
+            .map_err(|e: toml::de::Error| LoadError::Invalid {
+                path: path.clone(),
+                message: e.message().to_string(),
+            })?;
+        definition.name = name.to_string();
+        Ok(LoadedService { definition, path, dropins, merged })
+    }
+
+    /// Expands the specifiers in every string of a definition
+    fn expand(&self, name: &str, table: &mut toml::Table) -> Result<(), (String, crate::specifier::SpecifierError)> {
+        // The user is needed for `%h`, so it is expanded first without one
+        let user = match table.get("service").and_then(|s| s.get("user")).and_then(toml::Value::as_str) {
+            Some(user) => Some(Specifiers::new(&self.root, name, None).expand(user).map_err(|e| ("service.user".to_string(), e))?),
+            None => None,
+        };
+        Specifiers::new(&self.root, name, user.as_deref()).expand_table(table)
+    }
+
+    /// Drop-in fragments from the `<stem>.d` directories, in the order they should be applied
+    ///
+    /// `stems` are lowest precedence first.
+    fn dropins<'a>(&self, stems: impl Iterator<Item = &'a String>) -> Vec<PathBuf> {
+        let mut found = BTreeMap::new();
+        for stem in stems {
+            let dir_name = format!("{stem}.d");
+            // Lowest precedence first, so fragments with the same name get replaced
+            for dir in self.dirs.iter().rev() {
+                for path in toml_files(&dir.join(&dir_name)) {
+                    if let Some(file_name) = path.file_name() {
+                        found.insert(file_name.to_os_string(), path);
+                    }
+                }
+            }
+        }
+        found.into_values().collect()
+    }
+}
+
+/// If `name` is a template, like `getty@`
+pub fn is_template(name: &str) -> bool {
+    name.ends_with('@')
+}
+
+/// Splits an instance like `getty@tty1` into the template name `getty` and the instance `tty1`
+///
+/// `None` if the name is not an instance.
+pub fn split_instance(name: &str) -> Option<(&str, &str)> {
+    name.split_once('@').filter(|(_, instance)| !instance.is_empty())
+}
+
+/// Reads a file as a table, checking it is a valid definition on its own so errors have a location
+fn read_table(path: &Path) -> Result<toml::Table, LoadError> {
+    let text = std::fs::read_to_string(path).map_err(|source| LoadError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    // Every key of a definition is optional, so a fragment is a valid definition too
+    toml::from_str::<ServiceDefinition>(&text).map_err(|e| LoadError::from_toml(path, &text, &e))?;
+    toml::from_str(&text).map_err(|e| LoadError::from_toml(path, &text, &e))
+}
+
+/// Merges `top` into `base`, tables are merged key by key and everything else replaced
+fn merge(base: &mut toml::Table, top: toml::Table) {
+    for (key, value) in top {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(top)) => merge(base, top),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Every `*.toml` file in a directory, ignoring hidden files and a directory that does not exist
+fn toml_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| {
+            path.extension().is_some_and(|ext| ext == EXTENSION)
+                && !path.file_name().is_some_and(|n| n.to_string_lossy().starts_with('.'))
+                && path.is_file()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::testing::TempDir;
+
+    /// Writes `text` as `file` in every directory in `dirs`, relative to the root
+    fn write_in(root: &TempDir, dirs: &[&str], file: &str, text: &str) {
+        for dir in dirs {
+            root.write(Path::new(dir).join(file), text);
+        }
+    }
+
+    /// The description of a definition written by [`write_in`], naming the directory it is in
+    fn described(dir: &str) -> String {
+        format!("description = \"{dir}\"\n")
+    }
+
+    #[test]
+    fn precedence() {
+        let root = TempDir::new("loader-precedence");
+        let dirs = ServiceDirs::with_root(root.path());
+        for i in 0..DIRS.len() {
+            // Every directory from this one on has a definition, so this one wins
+            for lower in &DIRS[i..] {
+                write_in(&root, &[lower], &format!("s{i}.toml"), &described(lower));
+            }
+        }
+        for (i, dir) in DIRS.iter().enumerate() {
+            let loaded = dirs.load(&format!("s{i}")).unwrap();
+            assert_eq!(loaded.definition.description.as_deref(), Some(*dir));
+            assert_eq!(loaded.path, root.join(dir).join(format!("s{i}.toml")));
+        }
+        assert_eq!(dirs.names(), (0..DIRS.len()).map(|i| format!("s{i}")).collect());
+        assert!(matches!(dirs.load("missing"), Err(LoadError::NotFound { .. })));
+    }
+
+    #[test]
+    fn dropins() {
+        let root = TempDir::new("loader-dropins");
+        let dirs = ServiceDirs::with_root(root.path());
+        root.write(
+            "usr/lib/easyinit/services/web.toml",
+            "description = \"Web\"\n[service]\nexec = \"/usr/bin/web\"\nenvironment = { MODE = \"production\", LEVEL = \"1\" }\n[dependencies]\nafter = [\"network\"]\n",
+        );
+        // Applied by file name, no matter the directory
+        root.write("etc/easyinit/services/web.d/10-mode.toml", "[service.environment]\nMODE = \"debug\"\n");
+        root.write("usr/lib/easyinit/services/web.d/20-level.toml", "[service.environment]\nLEVEL = \"2\"\n");
+        root.write("run/easyinit/services/web.d/30-level.toml", "[service.environment]\nLEVEL = \"3\"\n");
+        // Masked by the administrator's fragment with the same name
+        root.write("usr/lib/easyinit/services/web.d/10-mode.toml", "[service.environment]\nMODE = \"masked\"\n");
+        root.write("etc/easyinit/services/web.d/40-after.toml", "[dependencies]\nafter = [\"database\"]\n");
+        root.write("etc/easyinit/services/web.d/README", "not a fragment");
+
+        let loaded = dirs.load("web").unwrap();
+        assert_eq!(
+            loaded.dropins,
+            [
+                root.join("etc/easyinit/services/web.d/10-mode.toml"),
+                root.join("usr/lib/easyinit/services/web.d/20-level.toml"),
+                root.join("run/easyinit/services/web.d/30-level.toml"),
+                root.join("etc/easyinit/services/web.d/40-after.toml"),
+            ]
+        );
+        let definition = &loaded.definition;
+        assert_eq!(definition.description.as_deref(), Some("Web"));
+        assert_eq!(definition.service.exec.as_ref().unwrap().program(), "/usr/bin/web");
+        assert_eq!(definition.service.environment["MODE"], "debug");
+        assert_eq!(definition.service.environment["LEVEL"], "3");
+        // Arrays are replaced, not appended to
+        assert_eq!(definition.dependencies.after, ["database"]);
+        assert!(loaded.merged_toml().contains("LEVEL = \"3\""));
+    }
+
+    #[test]
+    fn overrides() {
+        let root = TempDir::new("loader-overrides");
+        let dirs = ServiceDirs::with_root(root.path());
+        root.write("usr/lib/easyinit/services/web.toml", "description = \"Packaged\"\n[service]\nexec = \"/usr/bin/web\"\n");
+        root.write("usr/lib/easyinit/services/web.d/10-env.toml", "[service.environment]\nMODE = \"packaged\"\n");
+        // A whole file replaces the packaged one, only drop-ins are still applied
+        root.write("etc/easyinit/services/web.toml", "description = \"Local\"\n");
+        let loaded = dirs.load("web").unwrap();
+        assert_eq!(loaded.path, root.join("etc/easyinit/services/web.toml"));
+        assert_eq!(loaded.definition.description.as_deref(), Some("Local"));
+        assert_eq!(loaded.definition.service.exec, None);
+        assert_eq!(loaded.definition.service.environment["MODE"], "packaged");
+
+        // An invalid fragment fails the service, with its own location
+        root.write("run/easyinit/services/web.d/20-bad.toml", "[service]\nrestart = 3\n");
+        match dirs.load("web") {
+            Err(LoadError::Parse { path, line, .. }) => {
+                assert_eq!((path, line), (root.join("run/easyinit/services/web.d/20-bad.toml"), 2));
+            }
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+        let (loaded, errors) = dirs.load_all();
+        assert!(loaded.is_empty());
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn templates() {
+        let root = TempDir::new("loader-templates");
+        let dirs = ServiceDirs::with_root(root.path());
+        root.write(
+            "usr/lib/easyinit/services/getty@.toml",
+            "description = \"Getty on %i\"\n[service]\nexec = \"/sbin/agetty --noclear %i linux\"\n[dependencies]\nafter = [\"console-%i\"]\n",
+        );
+        root.write("usr/lib/easyinit/services/getty@.d/10-env.toml", "[service.environment]\nTTY = \"/dev/%i\"\n");
+        root.write("etc/easyinit/services/getty@tty2.d/10-env.toml", "[service.environment]\nTTY = \"/dev/serial\"\n");
+        assert!(dirs.names().is_empty());
+        assert_eq!(dirs.templates(), BTreeSet::from(["getty@".to_string()]));
+
+        let loaded = dirs.load("getty@tty1").unwrap();
+        assert_eq!(loaded.path, root.join("usr/lib/easyinit/services/getty@.toml"));
+        let definition = &loaded.definition;
+        assert_eq!(definition.name, "getty@tty1");
+        assert_eq!(definition.description.as_deref(), Some("Getty on tty1"));
+        assert_eq!(definition.service.exec.as_ref().unwrap().args(), ["--noclear", "tty1", "linux"]);
+        assert_eq!(definition.dependencies.after, ["console-tty1"]);
+        assert_eq!(definition.service.environment["TTY"], "/dev/tty1");
+        // The instance's fragment masks the template's
+        assert_eq!(dirs.load("getty@tty2").unwrap().definition.service.environment["TTY"], "/dev/serial");
+
+        // An instance's own definition replaces the template
+        root.write("etc/easyinit/services/getty@tty3.toml", "description = \"Serial %i\"\n");
+        let loaded = dirs.load("getty@tty3").unwrap();
+        assert_eq!(loaded.path, root.join("etc/easyinit/services/getty@tty3.toml"));
+        assert_eq!(loaded.definition.description.as_deref(), Some("Serial tty3"));
+        assert_eq!(dirs.names(), BTreeSet::from(["getty@tty3".to_string()]));
+
+        assert!(matches!(dirs.load("getty@"), Err(LoadError::NotFound { .. })));
+        assert!(matches!(dirs.load("worker@1"), Err(LoadError::NotFound { .. })));
+    }
+}
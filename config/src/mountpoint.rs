@@ -0,0 +1,100 @@
+//! What happens when `/tmp` or `/run` already has files in it at boot
+//!
+//! These usually are leftovers written to the root filesystem while nothing was mounted. The
+//! `easyinit.nonempty-mountpoint` option picks a [`Policy`] for every mount point, or for single
+//! ones with `path=policy`:
+//!
+//! ```text
+//! easyinit.nonempty-mountpoint=overmount,/run=refuse
+//! ```
+//!
+//! Mount points that are not named get the policy without a path, or [`Policy::Refuse`].
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// What to do with a mount point that is not empty
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Policy {
+    /// Do not mount, the mount fails
+    #[default]
+    Refuse,
+    /// Mount anyway, hiding the files until it is unmounted
+    Overmount,
+    /// Mount and move the files into the new filesystem
+    Move,
+}
+
+impl Policy {
+    /// Every policy by name
+    pub const NAMES: &[&str] = &["refuse", "overmount", "move"];
+}
+
+impl std::str::FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "refuse" => Ok(Policy::Refuse),
+            "overmount" => Ok(Policy::Overmount),
+            "move" => Ok(Policy::Move),
+            other => Err(format!("unknown policy `{other}`, expected one of {}", Policy::NAMES.join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Policy::Refuse => "refuse",
+            Policy::Overmount => "overmount",
+            Policy::Move => "move",
+        })
+    }
+}
+
+/// The policies for every mount point
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policies {
+    /// Policy of mount points that are not named
+    pub default: Policy,
+    /// Policies of single mount points
+    pub paths: BTreeMap<PathBuf, Policy>,
+}
+
+impl Policies {
+    /// The policy of a mount point
+    pub fn get(&self, path: &Path) -> Policy {
+        self.paths.get(path).copied().unwrap_or(self.default)
+    }
+}
+
+impl std::str::FromStr for Policies {
+    type Err = String;
+
+    /// `overmount,/run=refuse`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policies = Policies::default();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.split_once('=') {
+                Some((path, policy)) if path.starts_with('/') => {
+                    policies.paths.insert(PathBuf::from(path), policy.parse()?);
+                }
+                Some((path, _)) => return Err(format!("`{path}` is not an absolute path")),
+                None => policies.default = entry.parse()?,
+            }
+        }
+        Ok(policies)
+    }
+}
+
+impl std::fmt::Display for Policies {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.default)?;
+        for (path, policy) in &self.paths {
+            write!(f, ",{}={policy}", path.display())?;
+        }
+        Ok(())
+    }
+}
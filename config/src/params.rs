@@ -0,0 +1,559 @@
+//! Registry of the kernel command line parameters easyinit understands
+//!
+//! Every parameter is described once in [`PARAMETERS`], which is used both to fill in
+//! [`Cmdline`] and to list the parameters with `easyctl boot-params`.
+// cSpell:words Cmdline
+use crate::service::RestartPolicy;
+use crate::{Cmdline, IsSet, LogTarget, Source, Target};
+use log::LevelFilter;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The prefix every easyinit specific parameter has
+pub const PREFIX: &str = "easyinit.";
+
+/// The type of value a [`Parameter`] takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Kind {
+    /// A number from `0` (off) to `5` (trace) or the name of a level, like `info`
+    LogLevel,
+    /// An absolute path
+    Path,
+    /// Name of a service or target
+    Name,
+    /// A whole number, `0` for no limit
+    Count,
+    /// A size in bytes, with an optional `K`, `M` or `G` suffix, `0` for no limit
+    Size,
+    /// A time span like `30d` or `1h 30min`, plain numbers are seconds, `0` for no limit
+    Duration,
+    /// `yes` or `no`, `true`, `false`, `on`, `off`, `1` and `0` work too
+    Bool,
+    /// One of a fixed list of words
+    Choice(&'static [&'static str]),
+    /// Names of services, separated by commas
+    List,
+    /// A command line, quoted if it has spaces, like `easyinit.run="/bin/sh -c 'ls /'"`
+    Command,
+    /// A policy for every mount point and `path=policy` for single ones, separated by commas
+    Policies,
+}
+
+impl Kind {
+    /// Short name of the type, used as the placeholder in help output
+    pub fn placeholder(self) -> &'static str {
+        match self {
+            Kind::LogLevel => "level",
+            Kind::Path => "path",
+            Kind::Name => "name",
+            Kind::Count => "count",
+            Kind::Size => "size",
+            Kind::Duration => "duration",
+            Kind::Bool => "bool",
+            Kind::Choice(_) => "choice",
+            Kind::List => "names",
+            Kind::Command => "command",
+            Kind::Policies => "policies",
+        }
+    }
+
+    /// The values a [`Kind::Choice`] accepts, empty for every other kind
+    pub fn choices(self) -> &'static [&'static str] {
+        match self {
+            Kind::Choice(choices) => choices,
+            _ => &[],
+        }
+    }
+}
+
+/// A bare flag that is a shorthand for setting a [`Parameter`] to a value
+///
+/// The parameter itself always takes priority over an alias, no matter the order.
+#[derive(Debug)]
+pub struct Alias {
+    /// The flag as it appears on the command line, like `quiet`
+    pub flag: &'static str,
+    /// The value the parameter is set to
+    pub value: &'static str,
+}
+
+/// A parameter that can be passed on the kernel command line
+#[derive(Debug)]
+pub struct Parameter {
+    /// Full name, including the `easyinit.` prefix
+    pub name: &'static str,
+    /// The type of the value
+    pub kind: Kind,
+    /// The value used when the parameter is not given, empty if it is unset by default
+    pub default: &'static str,
+    /// Flags that set this parameter
+    pub aliases: &'static [Alias],
+    /// If it only changes a single boot, it is then not read from the [global configuration file](crate::global)
+    pub per_boot: bool,
+    /// A one line description
+    pub help: &'static str,
+    /// Parses the value and stores it in [`Cmdline`]
+    apply: fn(&mut Cmdline, &str, Source) -> Result<(), String>,
+    /// The current value in [`Cmdline`] and where it came from
+    show: fn(&Cmdline) -> (Option<String>, Source),
+}
+
+impl Parameter {
+    /// Parses `value` and stores it in `cmdline`, if `source` has a high enough precedence
+    pub(crate) fn apply(&self, cmdline: &mut Cmdline, value: &str, source: Source) -> Result<(), String> {
+        (self.apply)(cmdline, value, source)
+    }
+    /// The current value in `cmdline` and where it came from
+    pub(crate) fn show(&self, cmdline: &Cmdline) -> (Option<String>, Source) {
+        (self.show)(cmdline)
+    }
+}
+
+/// Every parameter easyinit understands
+pub static PARAMETERS: &[Parameter] = &[
+    Parameter {
+        name: "easyinit.loglevel",
+        kind: Kind::LogLevel,
+        default: "warn",
+        aliases: &[Alias { flag: "quiet", value: "0" }],
+        per_boot: false,
+        help: "How verbose easyinit is on the console",
+        apply: |c, v, s| {
+            c.loglevel.set(parse_loglevel(v)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.loglevel, |l| l.as_str().to_lowercase()),
+    },
+    Parameter {
+        name: "easyinit.log-target",
+        kind: Kind::Choice(&["console", "kmsg"]),
+        default: "console",
+        aliases: &[],
+        per_boot: false,
+        help: "Where easyinit logs to, `kmsg` is the kernel log",
+        apply: |c, v, s| {
+            c.log_target.set(parse_choice(v, LogTarget::from_name)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.log_target, |t| t.name().to_string()),
+    },
+    Parameter {
+        name: "easyinit.crash-prefix",
+        kind: Kind::Path,
+        default: "/var/log/",
+        aliases: &[],
+        per_boot: false,
+        help: "Directory crash reports are created in, ignored if easyinit.crash-path is set",
+        apply: |c, v, s| {
+            c.crash_report_prefix.set(parse_path(v)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.crash_report_prefix, |p| p.display().to_string()),
+    },
+    Parameter {
+        name: "easyinit.crash-path",
+        kind: Kind::Path,
+        default: "",
+        aliases: &[],
+        per_boot: false,
+        help: "Fixed file a crash report is written to, overwriting the previous one",
+        apply: |c, v, s| {
+            c.crash_report_file.set(parse_path(v)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.crash_report_file, |p| p.display().to_string()),
+    },
+    Parameter {
+        name: "easyinit.crash-max-count",
+        kind: Kind::Count,
+        default: "10",
+        aliases: &[],
+        per_boot: false,
+        help: "Number of generated crash reports kept, the oldest are removed at boot",
+        apply: |c, v, s| {
+            c.crash_max_count.set(parse_count(v)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.crash_max_count, u64::to_string),
+    },
+    Parameter {
+        name: "easyinit.crash-max-size",
+        kind: Kind::Size,
+        default: "0",
+        aliases: &[],
+        per_boot: false,
+        help: "Total size of the generated crash reports kept, the oldest are removed at boot",
+        apply: |c, v, s| {
+            c.crash_max_size.set(parse_size(v)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.crash_max_size, u64::to_string),
+    },
+    Parameter {
+        name: "easyinit.crash-max-age",
+        kind: Kind::Duration,
+        default: "0",
+        aliases: &[],
+        per_boot: false,
+        help: "Generated crash reports older than this are removed at boot",
+        apply: |c, v, s| {
+            c.crash_max_age.set(parse_duration(v)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.crash_max_age, |d| format!("{d}s")),
+    },
+    Parameter {
+        name: "easyinit.crash-compress",
+        kind: Kind::Bool,
+        default: "no",
+        aliases: &[],
+        per_boot: false,
+        help: "Compress generated crash reports with gzip at boot",
+        apply: |c, v, s| {
+            c.crash_compress.set(parse_bool(v)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.crash_compress, |b| if *b { "yes" } else { "no" }.to_string()),
+    },
+    Parameter {
+        name: "easyinit.default-restart",
+        kind: Kind::Choice(&["no", "on-failure", "always"]),
+        default: "no",
+        aliases: &[],
+        per_boot: false,
+        help: "Restart policy of services that don't set one",
+        apply: |c, v, s| {
+            c.default_restart.set(parse_choice(v, restart_from_name)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.default_restart, |r| restart_name(*r).to_string()),
+    },
+    Parameter {
+        name: "easyinit.default-stop-timeout",
+        kind: Kind::Duration,
+        default: "90s",
+        aliases: &[],
+        per_boot: false,
+        help: "How long services that don't set a stop timeout get to stop before they are killed",
+        apply: |c, v, s| {
+            c.default_stop_timeout.set(parse_duration(v)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.default_stop_timeout, |d| format!("{d}s")),
+    },
+    Parameter {
+        name: "easyinit.watchdog-device",
+        kind: Kind::Path,
+        default: "/dev/watchdog0",
+        aliases: &[],
+        per_boot: false,
+        help: "Hardware watchdog easyinit keeps alive",
+        apply: |c, v, s| {
+            c.watchdog_device.set(parse_path(v)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.watchdog_device, |p| p.display().to_string()),
+    },
+    Parameter {
+        name: "easyinit.watchdog-timeout",
+        kind: Kind::Duration,
+        default: "0",
+        aliases: &[],
+        per_boot: false,
+        help: "Timeout the hardware watchdog is set to, the system is reset if easyinit hangs this long",
+        apply: |c, v, s| {
+            c.watchdog_timeout.set(parse_duration(v)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.watchdog_timeout, |d| format!("{d}s")),
+    },
+    Parameter {
+        name: "easyinit.generator-timeout",
+        kind: Kind::Duration,
+        default: "5s",
+        aliases: &[],
+        per_boot: false,
+        help: "How long each generator gets to run before it is killed, `0` for no limit",
+        apply: |c, v, s| {
+            c.generator_timeout.set(parse_duration(v)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.generator_timeout, |d| format!("{d}s")),
+    },
+    Parameter {
+        name: "easyinit.nonempty-mountpoint",
+        kind: Kind::Policies,
+        default: "refuse",
+        aliases: &[],
+        per_boot: false,
+        help: "What to do when /tmp or /run has files in it at boot: `refuse`, `overmount` or `move`, like `overmount,/run=refuse`",
+        apply: |c, v, s| {
+            c.nonempty_mountpoint.set(v.parse()?, s);
+            Ok(())
+        },
+        show: |c| show(&c.nonempty_mountpoint, ToString::to_string),
+    },
+    Parameter {
+        name: "easyinit.fsck-mode",
+        kind: Kind::Choice(crate::fsck::Mode::NAMES),
+        default: "auto",
+        aliases: &[],
+        per_boot: false,
+        help: "When the root filesystem is checked at boot, also read from `fsck.mode=`",
+        apply: |c, v, s| {
+            c.fsck_mode.set(parse_choice(v, crate::fsck::Mode::from_name)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.fsck_mode, |m| m.name().to_string()),
+    },
+    Parameter {
+        name: "easyinit.fsck-repair",
+        kind: Kind::Choice(crate::fsck::Repair::NAMES),
+        default: "preen",
+        aliases: &[],
+        per_boot: false,
+        help: "What the root filesystem check repairs, also read from `fsck.repair=`",
+        apply: |c, v, s| {
+            c.fsck_repair.set(parse_choice(v, crate::fsck::Repair::from_name)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.fsck_repair, |r| r.name().to_string()),
+    },
+    Parameter {
+        name: "easyinit.root-mode",
+        kind: Kind::Choice(&["ro", "rw"]),
+        default: "rw",
+        // Not `ro` and `rw`, bootloaders pass `ro` so the root can be checked before it is remounted
+        aliases: &[],
+        per_boot: false,
+        help: "If the root filesystem stays read only after the boot when /etc/fstab does not say",
+        apply: |c, v, s| {
+            c.root_read_only.set(parse_choice(v, read_only_from_name)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.root_read_only, |ro| if *ro { "ro" } else { "rw" }.to_string()),
+    },
+    Parameter {
+        name: "easyinit.target",
+        kind: Kind::Name,
+        default: "default",
+        aliases: &[
+            Alias { flag: "single", value: "rescue" },
+            Alias { flag: "S", value: "rescue" },
+            Alias { flag: "1", value: "rescue" },
+            Alias { flag: "rescue", value: "rescue" },
+            Alias { flag: "emergency", value: "emergency" },
+        ],
+        per_boot: true,
+        help: "Target to boot into, `rescue` and `emergency` boot into a root shell",
+        apply: |c, v, s| {
+            c.target.set(Target::from(parse_name(v)?), s);
+            Ok(())
+        },
+        show: |c| show(&c.target, Target::to_string),
+    },
+    Parameter {
+        name: "easyinit.mask",
+        kind: Kind::List,
+        default: "",
+        aliases: &[],
+        per_boot: true,
+        help: "Services that are not started this boot, even if something wants or requires them",
+        apply: |c, v, s| {
+            c.mask.extend(parse_names(v)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.mask, |names| names.join(",")),
+    },
+    Parameter {
+        name: "easyinit.wants",
+        kind: Kind::List,
+        default: "",
+        aliases: &[],
+        per_boot: true,
+        help: "Services started this boot on top of what the target wants",
+        apply: |c, v, s| {
+            c.wants.extend(parse_names(v)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.wants, |names| names.join(",")),
+    },
+    Parameter {
+        name: "easyinit.run",
+        kind: Kind::Command,
+        default: "",
+        aliases: &[],
+        per_boot: true,
+        help: "Command run once the target is booted, with its output on the console",
+        apply: |c, v, s| {
+            c.run.set(parse_command(v)?, s);
+            Ok(())
+        },
+        show: |c| show(&c.run, String::clone),
+    },
+];
+
+/// Kernel parameters of other init systems that easyinit reads, with the parameter they set
+const FOREIGN: &[(&str, &str)] = &[("fsck.mode", "easyinit.fsck-mode"), ("fsck.repair", "easyinit.fsck-repair")];
+
+/// Finds a parameter by its name, `_` and `-` are treated the same
+pub fn find(name: &str) -> Option<&'static Parameter> {
+    let name = FOREIGN.iter().find(|(foreign, _)| *foreign == name).map_or(name, |(_, own)| own);
+    let name = name.replace('_', "-");
+    PARAMETERS.iter().find(|p| p.name == name)
+}
+
+/// Finds the parameter and value a bare flag is an alias for
+pub fn find_alias(flag: &str) -> Option<(&'static Parameter, &'static str)> {
+    PARAMETERS.iter().find_map(|p| {
+        p.aliases.iter().find(|a| a.flag == flag).map(|a| (p, a.value))
+    })
+}
+
+/// Formats a setting for [`Parameter::show`]
+fn show<T>(value: &IsSet<T>, fmt: fn(&T) -> String) -> (Option<String>, Source) {
+    (value.get().map(fmt), value.source())
+}
+
+fn parse_loglevel(value: &str) -> Result<LevelFilter, String> {
+    match value.parse::<usize>() {
+        Ok(n) => LevelFilter::iter()
+            .nth(n)
+            .ok_or_else(|| format!("log level {n} is out of range, expected 0 to 5")),
+        Err(_) => LevelFilter::from_str(value).map_err(|_| format!("`{value}` is not a log level")),
+    }
+}
+
+fn parse_path(value: &str) -> Result<PathBuf, String> {
+    if value.starts_with('/') {
+        Ok(PathBuf::from(value))
+    } else {
+        Err(format!("`{value}` is not an absolute path"))
+    }
+}
+
+fn parse_name(value: &str) -> Result<&str, String> {
+    if value.is_empty() || value.contains('/') {
+        Err(format!("`{value}` is not a valid name"))
+    } else {
+        Ok(value)
+    }
+}
+
+fn parse_names(value: &str) -> Result<Vec<String>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| parse_name(name).map(str::to_string))
+        .collect()
+}
+
+fn parse_command(value: &str) -> Result<String, String> {
+    let args = crate::shell::split(value)?;
+    crate::service::CommandLine::try_from(args)?;
+    Ok(value.to_string())
+}
+
+fn parse_count(value: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| format!("`{value}` is not a whole number"))
+}
+
+pub(crate) fn parse_size(value: &str) -> Result<u64, String> {
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&value[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&value[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    let number: u64 = number.parse().map_err(|_| format!("`{value}` is not a size"))?;
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("`{value}` is too large"))
+}
+
+fn parse_choice<T>(value: &str, from_name: fn(&str) -> Option<T>) -> Result<T, String> {
+    from_name(value).ok_or_else(|| format!("`{value}` is not one of the choices"))
+}
+
+fn read_only_from_name(name: &str) -> Option<bool> {
+    match name {
+        "ro" => Some(true),
+        "rw" => Some(false),
+        _ => None,
+    }
+}
+
+fn restart_from_name(name: &str) -> Option<RestartPolicy> {
+    match name {
+        "no" => Some(RestartPolicy::No),
+        "on-failure" => Some(RestartPolicy::OnFailure),
+        "always" => Some(RestartPolicy::Always),
+        _ => None,
+    }
+}
+
+fn restart_name(policy: RestartPolicy) -> &'static str {
+    match policy {
+        RestartPolicy::No => "no",
+        RestartPolicy::OnFailure => "on-failure",
+        RestartPolicy::Always => "always",
+    }
+}
+
+pub(crate) fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "yes" | "true" | "on" | "1" => Ok(true),
+        "no" | "false" | "off" | "0" => Ok(false),
+        _ => Err(format!("`{value}` is not yes or no")),
+    }
+}
+
+/// Parses a time span like systemd does, like `1h 30min` or `90`, into seconds
+pub(crate) fn parse_duration(value: &str) -> Result<u64, String> {
+    let mut total = 0u64;
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return Err("time span is empty".to_string());
+    }
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let number: u64 = rest[..digits].parse().map_err(|_| format!("`{value}` is not a time span"))?;
+        rest = rest[digits..].trim_start();
+        let unit_len = rest.find(|c: char| c.is_ascii_digit() || c.is_whitespace()).unwrap_or(rest.len());
+        let multiplier = match &rest[..unit_len] {
+            "" | "s" | "sec" | "second" | "seconds" => 1,
+            "m" | "min" | "minute" | "minutes" => 60,
+            "h" | "hr" | "hour" | "hours" => 60 * 60,
+            "d" | "day" | "days" => 24 * 60 * 60,
+            "w" | "week" | "weeks" => 7 * 24 * 60 * 60,
+            unit => return Err(format!("unknown time unit `{unit}`")),
+        };
+        total = total.saturating_add(number.saturating_mul(multiplier));
+        rest = rest[unit_len..].trim_start();
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_by_name() {
+        assert_eq!(find("easyinit.crash_max_count").map(|p| p.name), Some("easyinit.crash-max-count"));
+        assert_eq!(find("fsck.mode").map(|p| p.name), Some("easyinit.fsck-mode"));
+        assert!(find("easyinit.unknown").is_none());
+        assert_eq!(find_alias("quiet").map(|(p, value)| (p.name, value)), Some(("easyinit.loglevel", "0")));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Ok(90));
+        assert_eq!(parse_duration("1h 30min"), Ok(5400));
+        assert_eq!(parse_duration("2m5s"), Ok(125));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("5 fortnights").is_err());
+    }
+}
@@ -0,0 +1,441 @@
+//! The service definition format
+//!
+//! Services are defined in TOML files, the name of the service is the name of the file
+//! without the `.toml` extension.
+//!
+//! ```toml
+//! description = "Example daemon"
+//!
+//! [service]
+//! kind = "simple"
+//! exec = "/usr/bin/exampled --foreground"
+//! working-directory = "/var/lib/example"
+//! user = "example"
+//! group = "example"
+//! restart = "on-failure"
+//! environment = { EXAMPLE_MODE = "production" }
+//! environment-files = ["-/etc/default/example"]
+//!
+//! [dependencies]
+//! requires = ["network"]
+//! wants = ["syslog"]
+//! after = ["network", "syslog"]
+//!
+//! [install]
+//! wanted-by = ["default"]
+//!
+//! [conditions]
+//! path-exists = ["/etc/example.conf"]
+//! ```
+//!
+//! [`crate::condition`] describes `[conditions]` and `[assertions]`.
+//!
+//! Every key is optional. A definition without `exec` does not run anything, it only
+//! groups its dependencies, like a target.
+use crate::condition::Conditions;
+use crate::environment::{EnvironmentError, EnvironmentFile};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// File extension of service definitions
+pub const EXTENSION: &str = "toml";
+
+/// A service, as loaded from a definition file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ServiceDefinition {
+    /// Name of the service, taken from the file name
+    #[serde(skip)]
+    pub name: String,
+    /// A short human readable description
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// How the service is run
+    #[serde(default)]
+    pub service: Service,
+    /// Ordering and requirements on other services
+    #[serde(default)]
+    pub dependencies: Dependencies,
+    /// What the service is enabled as part of
+    #[serde(default)]
+    pub install: Install,
+    /// What else starts the service
+    #[serde(default, skip_serializing_if = "Activation::is_empty")]
+    pub activation: Activation,
+    /// The service is skipped unless these hold
+    #[serde(default, skip_serializing_if = "Conditions::is_empty")]
+    pub conditions: Conditions,
+    /// The service fails unless these hold
+    #[serde(default, skip_serializing_if = "Conditions::is_empty")]
+    pub assertions: Conditions,
+}
+
+/// The `[service]` section, how the service is run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Service {
+    /// How easyinit decides the service has started
+    #[serde(default)]
+    pub kind: ServiceKind,
+    /// The command to run, either as a single string split like a shell would, or an array of arguments
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exec: Option<CommandLine>,
+    /// Command run to stop the service, if not set the process is sent `SIGTERM`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exec_stop: Option<CommandLine>,
+    /// Directory the command is run in, `/` if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_directory: Option<PathBuf>,
+    /// Extra environment variables
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub environment: BTreeMap<String, String>,
+    /// Files with more environment variables, see [`crate::environment`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub environment_files: Vec<EnvironmentFile>,
+    /// User name or id the command is run as, `root` if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Group name or id the command is run as, the primary group of [`Service::user`] if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// When the service is started again after it exits, the manager default if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart: Option<RestartPolicy>,
+    /// Seconds to wait for the service to stop before it is killed, the manager default if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_timeout: Option<u64>,
+}
+
+impl Service {
+    /// The environment the command is run with, view [`crate::environment`] for the order it is merged in
+    pub fn resolve_environment(&self, defaults: &ServiceDefaults) -> Result<BTreeMap<String, String>, EnvironmentError> {
+        let mut base = defaults.environment.clone();
+        base.extend(self.environment.iter().map(|(k, v)| (k.clone(), v.clone())));
+        crate::environment::merge(&base, &self.environment_files)
+    }
+
+    /// [`Service::restart`], or the manager default
+    pub fn restart_policy(&self, defaults: &ServiceDefaults) -> RestartPolicy {
+        self.restart.unwrap_or(defaults.restart)
+    }
+
+    /// [`Service::stop_timeout`], or the manager default
+    pub fn stop_timeout(&self, defaults: &ServiceDefaults) -> Duration {
+        self.stop_timeout.map_or(defaults.stop_timeout, Duration::from_secs)
+    }
+}
+
+/// Manager wide defaults for settings a definition leaves out, from [`crate::Cmdline::service_defaults`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceDefaults {
+    /// Used when [`Service::restart`] is not set
+    pub restart: RestartPolicy,
+    /// Used when [`Service::stop_timeout`] is not set
+    pub stop_timeout: Duration,
+    /// Environment every service starts with, before its own
+    pub environment: BTreeMap<String, String>,
+}
+
+/// How easyinit decides the service has started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServiceKind {
+    /// Started as soon as the process is running
+    #[default]
+    Simple,
+    /// Started once the process exits successfully, for setup tasks
+    ///
+    /// If [`Service::exec_stop`] is set, the service stays active after the process exits
+    /// until it is stopped, like a SysV init script.
+    Oneshot,
+}
+
+/// When a service is started again after it exits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Never restarted
+    #[default]
+    No,
+    /// Restarted if it exits with a non-zero status or is killed by a signal
+    OnFailure,
+    /// Always restarted, unless it was stopped on purpose
+    Always,
+}
+
+/// The command line of a service, after splitting
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawCommandLine", into = "Vec<String>")]
+pub struct CommandLine(pub Vec<String>);
+
+impl CommandLine {
+    /// The program that is run
+    pub fn program(&self) -> &str {
+        &self.0[0]
+    }
+    /// The arguments after the program
+    pub fn args(&self) -> &[String] {
+        &self.0[1..]
+    }
+}
+
+impl From<CommandLine> for Vec<String> {
+    fn from(value: CommandLine) -> Self {
+        value.0
+    }
+}
+
+/// What [`CommandLine`] is written as in a definition
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawCommandLine {
+    Line(String),
+    Args(Vec<String>),
+}
+
+impl TryFrom<RawCommandLine> for CommandLine {
+    type Error = String;
+    fn try_from(value: RawCommandLine) -> Result<Self, Self::Error> {
+        match value {
+            RawCommandLine::Line(line) => CommandLine::try_from(crate::shell::split(&line)?),
+            RawCommandLine::Args(args) => CommandLine::try_from(args),
+        }
+    }
+}
+
+impl TryFrom<Vec<String>> for CommandLine {
+    type Error = String;
+    fn try_from(args: Vec<String>) -> Result<Self, Self::Error> {
+        match args.first() {
+            None => Err("command line is empty".to_string()),
+            // A program starting with a specifier, like `%h/bin/example`, is checked again once it is expanded
+            Some(program) if !program.starts_with('/') && !program.starts_with('%') => {
+                Err(format!("`{program}` is not an absolute path"))
+            }
+            Some(_) => Ok(CommandLine(args)),
+        }
+    }
+}
+
+/// The `[dependencies]` section
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Dependencies {
+    /// Started together with this service, this service fails if they fail
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub requires: Vec<String>,
+    /// Started together with this service, but failing does not affect this service
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wants: Vec<String>,
+    /// This service is started after these, if they are started at all
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<String>,
+    /// This service is started before these, if they are started at all
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub before: Vec<String>,
+}
+
+/// The `[install]` section
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Install {
+    /// Targets or services that want this service when it is enabled
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wanted_by: Vec<String>,
+    /// Targets or services that require this service when it is enabled
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub required_by: Vec<String>,
+}
+
+/// The `[activation]` section, what starts the service besides being wanted or required
+///
+/// Socket and timer activation is not done by easyinit yet, these are kept so definitions
+/// imported from other init systems don't lose them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Activation {
+    /// Sockets that start the service on the first connection
+    ///
+    /// A path is a unix socket, otherwise it is prefixed with the protocol, like `tcp:0.0.0.0:80` or `udp:[::]:53`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sockets: Vec<String>,
+    /// Calendar events that start the service, like `daily` or `Mon *-*-* 04:00:00`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_calendar: Vec<String>,
+    /// Seconds after boot the service is started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_boot_sec: Option<u64>,
+    /// Seconds after the service last started that it is started again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_active_sec: Option<u64>,
+}
+
+impl Activation {
+    /// If nothing is set
+    pub fn is_empty(&self) -> bool {
+        *self == Activation::default()
+    }
+}
+
+impl ServiceDefinition {
+    /// Loads a definition from a file, the name is the file name without the extension
+    pub fn from_file(path: &Path) -> Result<Self, LoadError> {
+        let text = std::fs::read_to_string(path).map_err(|source| LoadError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self::parse(&name, &text).map_err(|e| e.in_file(path))
+    }
+
+    /// Parses a definition that has already been read
+    ///
+    /// Errors have an empty path, use [`ServiceDefinition::from_file`] to have it filled in.
+    pub fn parse(name: &str, text: &str) -> Result<Self, LoadError> {
+        let mut definition: ServiceDefinition =
+            toml::from_str(text).map_err(|e| LoadError::from_toml(Path::new(""), text, &e))?;
+        definition.name = name.to_string();
+        Ok(definition)
+    }
+}
+
+/// Errors while loading a definition
+#[derive(thiserror::Error, Debug)]
+pub enum LoadError {
+    /// The file could not be read
+    #[error("Failed to read {}: {source}", path.display())]
+    Io {
+        /// The file that failed to be read
+        path: PathBuf,
+        /// What went wrong
+        source: std::io::Error,
+    },
+    /// The file is not a valid definition
+    #[error("{}:{line}:{column}: {message}", path.display())]
+    Parse {
+        /// The file with the error
+        path: PathBuf,
+        /// Line of the error, starting at 1
+        line: usize,
+        /// Column of the error in characters, starting at 1
+        column: usize,
+        /// What went wrong
+        message: String,
+    },
+    /// The file and its drop-ins are valid on their own, but not after merging them
+    #[error("{}: {message}, after applying drop-ins", path.display())]
+    Invalid {
+        /// The main file
+        path: PathBuf,
+        /// What went wrong
+        message: String,
+    },
+    /// A value has a specifier that cannot be expanded
+    #[error("{}: `{key}`: {source}", path.display())]
+    Specifier {
+        /// The main file
+        path: PathBuf,
+        /// The dotted key of the value, like `service.exec`
+        key: String,
+        /// What went wrong
+        source: crate::specifier::SpecifierError,
+    },
+    /// There is no definition with this name
+    #[error("No definition found for `{name}`")]
+    NotFound {
+        /// The name that was looked up
+        name: String,
+    },
+}
+
+impl LoadError {
+    /// Converts a TOML error, working out the line and column from the span
+    pub(crate) fn from_toml(path: &Path, text: &str, error: &toml::de::Error) -> Self {
+        let (line, column) = toml_position(text, error);
+        LoadError::Parse {
+            path: path.to_path_buf(),
+            line,
+            column,
+            message: error.message().to_string(),
+        }
+    }
+
+    /// Sets the path of the file the error is in
+    fn in_file(mut self, file: &Path) -> Self {
+        match &mut self {
+            LoadError::Io { path, .. }
+            | LoadError::Parse { path, .. }
+            | LoadError::Invalid { path, .. }
+            | LoadError::Specifier { path, .. } => {
+                *path = file.to_path_buf()
+            }
+            LoadError::NotFound { .. } => {}
+        }
+        self
+    }
+}
+
+/// Line and column of a TOML error, both starting at 1, worked out from the span
+pub(crate) fn toml_position(text: &str, error: &toml::de::Error) -> (usize, usize) {
+    let offset = error.span().map_or(0, |s| s.start).min(text.len());
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::testing::TempDir;
+
+    /// Where the error is and its message, from [`LoadError::Parse`]
+    fn position(error: LoadError) -> (PathBuf, usize, usize, String) {
+        match error {
+            LoadError::Parse { path, line, column, message } => (path, line, column, message),
+            other => panic!("expected a parse error, got {other}"),
+        }
+    }
+
+    #[test]
+    fn valid() {
+        let text = "description = \"Web server\"\n\n[service]\nexec = \"/usr/bin/httpd -f '/etc/httpd/main conf'\"\nrestart = \"always\"\n";
+        let definition = ServiceDefinition::parse("httpd", text).unwrap();
+        assert_eq!(definition.name, "httpd");
+        assert_eq!(definition.service.exec.unwrap().args(), ["-f", "/etc/httpd/main conf"]);
+        assert_eq!(definition.service.restart, Some(RestartPolicy::Always));
+    }
+
+    #[test]
+    fn bad_toml() {
+        let dir = TempDir::new("service-bad-toml");
+        dir.write("web.toml", "description = \"Web\"\n[service\nexec = \"/usr/bin/httpd\"\n");
+        let error = ServiceDefinition::from_file(&dir.join("web.toml")).unwrap_err();
+        let display = error.to_string();
+        let (path, line, column, _) = position(error);
+        assert_eq!((path, line, column), (dir.join("web.toml"), 2, 9));
+        assert!(display.starts_with(&format!("{}:2:9: ", dir.join("web.toml").display())), "{display}");
+    }
+
+    #[test]
+    fn bad_field_value() {
+        let text = "[service]\nexec = \"/usr/bin/app\"\nrestart = \"sometimes\"\n";
+        let (_, line, column, message) = position(ServiceDefinition::parse("app", text).unwrap_err());
+        assert_eq!((line, column), (3, 11));
+        assert!(message.contains("sometimes"), "{message}");
+
+        // Checked values point at the value too, with the reason
+        let (_, line, column, message) = position(ServiceDefinition::parse("app", "[service]\n\nexec = \"bin/app\"\n").unwrap_err());
+        assert_eq!((line, column), (3, 8));
+        assert!(message.contains("`bin/app` is not an absolute path"), "{message}");
+
+        let (_, line, column, message) = position(ServiceDefinition::parse("app", "[service]\nexecute = \"/usr/bin/app\"\n").unwrap_err());
+        assert_eq!((line, column), (2, 1));
+        assert!(message.contains("execute"), "{message}");
+    }
+}
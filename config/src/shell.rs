@@ -0,0 +1,114 @@
+//! Shell compatible quoting, without any expansion
+//!
+//! Follows the POSIX shell rules for quotes:
+//!
+//! * Inside single quotes everything is literal, there is no way to escape a `'`.
+//! * Inside double quotes a backslash only escapes `"`, `\`, `$`, `` ` `` and a newline.
+//! * Outside of quotes a backslash escapes any character.
+//!
+//! Variables, globs and other expansions are not done, `$HOME` stays as is.
+
+/// Splits a command line into its arguments, like `sh` would
+pub fn split(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(args);
+        }
+        let mut arg = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+            unquote_char(c, &mut chars, &mut arg)?;
+        }
+        args.push(arg);
+    }
+}
+
+/// Handles a single character, reading the rest of a quoted section or escape from `chars`
+///
+/// The result is pushed to `out`.
+pub(crate) fn unquote_char(
+    c: char,
+    chars: &mut impl Iterator<Item = char>,
+    out: &mut String,
+) -> Result<(), String> {
+    match c {
+        '\'' => loop {
+            match chars.next() {
+                Some('\'') => return Ok(()),
+                Some(c) => out.push(c),
+                None => return Err("unterminated single quote".to_string()),
+            }
+        },
+        '"' => loop {
+            match chars.next() {
+                Some('"') => return Ok(()),
+                Some('\\') => match chars.next() {
+                    // A escaped newline continues the line
+                    Some('\n') => {}
+                    Some(c @ ('"' | '\\' | '$' | '`')) => out.push(c),
+                    Some(c) => {
+                        out.push('\\');
+                        out.push(c);
+                    }
+                    None => return Err("unterminated double quote".to_string()),
+                },
+                Some(c) => out.push(c),
+                None => return Err("unterminated double quote".to_string()),
+            }
+        },
+        '\\' => match chars.next() {
+            Some('\n') => Ok(()),
+            Some(c) => {
+                out.push(c);
+                Ok(())
+            }
+            None => Err("trailing backslash".to_string()),
+        },
+        c => {
+            out.push(c);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoting() {
+        assert_eq!(split("  /bin/echo  a\tb \n").unwrap(), ["/bin/echo", "a", "b"]);
+        assert_eq!(split("echo 'a b' \"c d\" e\\ f").unwrap(), ["echo", "a b", "c d", "e f"]);
+        assert_eq!(split("echo 'it'\\''s' \"a\"'b'c").unwrap(), ["echo", "it's", "abc"]);
+        assert_eq!(split("echo '' \"\"").unwrap(), ["echo", "", ""]);
+        assert!(split(" \t").unwrap().is_empty());
+    }
+
+    #[test]
+    fn escapes() {
+        // Single quotes keep backslashes
+        assert_eq!(split(r"'a\b\'").unwrap(), [r"a\b\"]);
+        // Double quotes only remove them before special characters
+        assert_eq!(split(r#""\" \\ \$ \` \a""#).unwrap(), [r#"" \ $ ` \a"#]);
+        assert_eq!(split(r"\a\ \'\\").unwrap(), [r"a '\"]);
+        // Nothing is expanded
+        assert_eq!(split("echo $HOME ~ *").unwrap(), ["echo", "$HOME", "~", "*"]);
+    }
+
+    #[test]
+    fn continuations() {
+        assert_eq!(split("echo a\\\nb").unwrap(), ["echo", "ab"]);
+        assert_eq!(split("echo \"a\\\nb\"").unwrap(), ["echo", "ab"]);
+        assert_eq!(split("echo 'a\\\nb'").unwrap(), ["echo", "a\\\nb"]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(split("echo 'open"), Err("unterminated single quote".to_string()));
+        assert_eq!(split("echo \"open"), Err("unterminated double quote".to_string()));
+        assert_eq!(split("echo \"open\\"), Err("unterminated double quote".to_string()));
+        assert_eq!(split("echo \\"), Err("trailing backslash".to_string()));
+    }
+}
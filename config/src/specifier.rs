@@ -0,0 +1,231 @@
+//! Specifiers in service definitions, like `%i` for the instance
+//!
+//! Every string in a definition is expanded when it is loaded, so the manager and `easyctl show`
+//! see the same values.
+//!
+//! | Specifier | Meaning |
+//! |-----------|---------|
+//! | `%i` | Instance, `tty1` for `getty@tty1`, empty if the service is not an instance |
+//! | `%p` | Prefix, `getty` for `getty@tty1`, the whole name if the service is not an instance |
+//! | `%n` | Full name of the service, `getty@tty1` |
+//! | `%t` | Runtime directory, `/run` |
+//! | `%S` | State directory, `/var/lib` |
+//! | `%H` | Hostname |
+//! | `%m` | Machine ID, from `/etc/machine-id` |
+//! | `%b` | Boot ID, without dashes |
+//! | `%h` | Home directory of the service's `user`, `/root` if it has none |
+//! | `%%` | A literal `%` |
+//!
+//! Any other character after `%`, or a `%` at the end of a string, is an error instead of being
+//! left as it is, so a typo does not end up on a command line. Expanded values are not expanded
+//! again.
+use crate::loader;
+use std::path::{Path, PathBuf};
+
+/// Runtime directory, `%t`
+pub const RUNTIME_DIR: &str = "/run";
+
+/// State directory, `%S`
+pub const STATE_DIR: &str = "/var/lib";
+
+/// Every specifier, the characters that may follow a `%`
+const SPECIFIERS: &[char] = &['i', 'p', 'n', 't', 'S', 'H', 'm', 'b', 'h', '%'];
+
+/// Errors while expanding specifiers
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum SpecifierError {
+    /// `%` is followed by a character that is not a specifier
+    #[error("Unknown specifier `%{0}`, write `%%` for a literal `%`")]
+    Unknown(char),
+    /// The string ends with a single `%`
+    #[error("`%` at the end, write `%%` for a literal `%`")]
+    Trailing,
+    /// The value of the specifier could not be found out
+    #[error("`%{specifier}` is not available: {reason}")]
+    Unavailable {
+        /// The specifier
+        specifier: char,
+        /// Why
+        reason: String,
+    },
+}
+
+/// What specifiers expand to for a single service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Specifiers {
+    root: PathBuf,
+    name: String,
+    user: Option<String>,
+}
+
+impl Specifiers {
+    /// Specifiers for the service `name` running as `user`, with system files read under `root`
+    pub fn new(root: &Path, name: &str, user: Option<&str>) -> Self {
+        Specifiers { root: root.to_path_buf(), name: name.to_string(), user: user.map(str::to_string) }
+    }
+
+    /// Checks that every `%` in `text` is a specifier, without expanding them
+    pub fn check(text: &str) -> Result<(), SpecifierError> {
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                continue;
+            }
+            match chars.next() {
+                Some(specifier) if SPECIFIERS.contains(&specifier) => {}
+                Some(other) => return Err(SpecifierError::Unknown(other)),
+                None => return Err(SpecifierError::Trailing),
+            }
+        }
+        Ok(())
+    }
+
+    /// Expands every specifier in `text`
+    pub fn expand(&self, text: &str) -> Result<String, SpecifierError> {
+        if !text.contains('%') {
+            return Ok(text.to_string());
+        }
+        let mut out = String::with_capacity(text.len());
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some(specifier) => out.push_str(&self.value(specifier)?),
+                None => return Err(SpecifierError::Trailing),
+            }
+        }
+        Ok(out)
+    }
+
+    /// Expands every string in a table, on error also returns the dotted key of the value
+    pub(crate) fn expand_table(&self, table: &mut toml::Table) -> Result<(), (String, SpecifierError)> {
+        fn walk(specifiers: &Specifiers, key: &str, value: &mut toml::Value) -> Result<(), (String, SpecifierError)> {
+            match value {
+                toml::Value::String(s) => *s = specifiers.expand(s).map_err(|e| (key.to_string(), e))?,
+                toml::Value::Array(values) => {
+                    for value in values {
+                        walk(specifiers, key, value)?;
+                    }
+                }
+                toml::Value::Table(table) => {
+                    for (name, value) in table.iter_mut() {
+                        walk(specifiers, &format!("{key}.{name}"), value)?;
+                    }
+                }
+                _ => {}
+            }
+            Ok(())
+        }
+        for (key, value) in table.iter_mut() {
+            walk(self, key, value)?;
+        }
+        Ok(())
+    }
+
+    /// The value of a single specifier
+    fn value(&self, specifier: char) -> Result<String, SpecifierError> {
+        let instance = loader::split_instance(&self.name);
+        let unavailable = |reason: String| SpecifierError::Unavailable { specifier, reason };
+        Ok(match specifier {
+            '%' => "%".to_string(),
+            'i' => instance.map_or("", |(_, instance)| instance).to_string(),
+            'p' => instance.map_or(self.name.as_str(), |(prefix, _)| prefix).to_string(),
+            'n' => self.name.clone(),
+            't' => RUNTIME_DIR.to_string(),
+            'S' => STATE_DIR.to_string(),
+            'H' => self.read_first(&["proc/sys/kernel/hostname", "etc/hostname"]).map_err(unavailable)?,
+            'm' => {
+                let id = self.read_first(&["etc/machine-id"]).map_err(unavailable)?;
+                if id.len() != 32 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(unavailable("/etc/machine-id is not a valid machine ID".to_string()));
+                }
+                id
+            }
+            'b' => self.read_first(&["proc/sys/kernel/random/boot_id"]).map_err(unavailable)?.replace('-', ""),
+            'h' => self.home().map_err(unavailable)?,
+            other => return Err(SpecifierError::Unknown(other)),
+        })
+    }
+
+    /// The trimmed contents of the first of `paths` under the root that is not empty
+    fn read_first(&self, paths: &[&str]) -> Result<String, String> {
+        let mut error = String::new();
+        for path in paths {
+            match std::fs::read_to_string(self.root.join(path)) {
+                Ok(text) if !text.trim().is_empty() => return Ok(text.trim().to_string()),
+                Ok(_) => error = format!("/{path} is empty"),
+                Err(e) => error = format!("Failed to read /{path}: {e}"),
+            }
+        }
+        Err(error)
+    }
+
+    /// Home directory of the user from `/etc/passwd`
+    fn home(&self) -> Result<String, String> {
+        let user = match self.user.as_deref() {
+            None | Some("root" | "0") => return Ok("/root".to_string()),
+            Some(user) => user,
+        };
+        let passwd = std::fs::read_to_string(self.root.join("etc/passwd"))
+            .map_err(|e| format!("Failed to read /etc/passwd: {e}"))?;
+        passwd
+            .lines()
+            .map(|line| line.split(':').collect::<Vec<_>>())
+            .find(|fields| fields.len() >= 7 && (fields[0] == user || fields[2] == user))
+            .map(|fields| fields[5].to_string())
+            .ok_or_else(|| format!("No user `{user}` in /etc/passwd"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::testing::TempDir;
+
+    fn expand(name: &str, text: &str) -> Result<String, SpecifierError> {
+        Specifiers::new(Path::new("/nonexistent"), name, None).expand(text)
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(expand("app", "100%%"), Ok("100%".to_string()));
+        // An expanded `%%` is not expanded again
+        assert_eq!(expand("app", "%%i"), Ok("%i".to_string()));
+        assert_eq!(expand("app", "no specifiers"), Ok("no specifiers".to_string()));
+        assert_eq!(expand("app", "100%"), Err(SpecifierError::Trailing));
+        assert_eq!(expand("app", "%x"), Err(SpecifierError::Unknown('x')));
+        assert_eq!(expand("app", "%I"), Err(SpecifierError::Unknown('I')));
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(expand("getty@tty1", "%p %i %n"), Ok("getty tty1 getty@tty1".to_string()));
+        assert_eq!(expand("sshd", "%p [%i] %n"), Ok("sshd [] sshd".to_string()));
+        assert_eq!(expand("sshd", "%t/%p.pid %S/%p"), Ok("/run/sshd.pid /var/lib/sshd".to_string()));
+    }
+
+    #[test]
+    fn system_files() {
+        let root = TempDir::new("specifier-root");
+        root.write("etc/hostname", "example\n").write("etc/machine-id", "0123456789abcdef0123456789abcdef\n");
+        root.write("etc/passwd", "root:x:0:0::/root:/bin/sh\nweb:x:33:33::/srv/web:/usr/sbin/nologin\n");
+        let specifiers = Specifiers::new(root.path(), "web", Some("web"));
+        assert_eq!(specifiers.expand("%H %m %h"), Ok("example 0123456789abcdef0123456789abcdef /srv/web".to_string()));
+        assert!(matches!(specifiers.expand("%b"), Err(SpecifierError::Unavailable { specifier: 'b', .. })));
+        root.write("etc/machine-id", "uninitialized\n");
+        assert!(matches!(specifiers.expand("%m"), Err(SpecifierError::Unavailable { specifier: 'm', .. })));
+    }
+
+    #[test]
+    fn check_knows_every_specifier() {
+        for specifier in SPECIFIERS {
+            assert_eq!(Specifiers::check(&format!("%{specifier}")), Ok(()));
+            assert_ne!(expand("app", &format!("%{specifier}")), Err(SpecifierError::Unknown(*specifier)));
+        }
+        assert_eq!(Specifiers::check("%f"), Err(SpecifierError::Unknown('f')));
+        assert_eq!(Specifiers::check("%"), Err(SpecifierError::Trailing));
+    }
+}
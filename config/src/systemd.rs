@@ -0,0 +1,608 @@
+//! Imports systemd unit files as service definitions
+//!
+//! `.service` and `.target` units become a [`ServiceDefinition`] of the same name.
+//! `.socket` and `.timer` units become the [`Activation`] of the service they start, which
+//! [`import_dir`] merges into that service.
+//!
+//! Anything easyinit has no equivalent for is skipped and reported as a [`Warning`], so the
+//! result can be checked by hand. That includes [specifiers](crate::specifier) easyinit does not
+//! have, like `%I`, and commands using `$MAINPID`. Other variables on a command line are left to
+//! `/bin/sh -c`, as easyinit does not expand them.
+//!
+//! Unit names lose their suffix, and the standard targets are renamed to the easyinit ones:
+//!
+//! | systemd                                                     | easyinit    |
+//! |-------------------------------------------------------------|-------------|
+//! | `default.target`, `multi-user.target`, `graphical.target`   | `default`   |
+//! | `rescue.target`                                             | `rescue`    |
+//! | `emergency.target`                                          | `emergency` |
+//!
+//! [`Activation`]: crate::service::Activation
+// cSpell:words oneshot timespan
+use crate::condition::Threshold;
+use crate::environment::EnvironmentFile;
+use crate::service::{CommandLine, RestartPolicy, ServiceDefinition, ServiceKind};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Variables systemd sets for the commands of a unit, easyinit has no equivalent
+const MANAGER_VARIABLES: &[&str] = &["MAINPID", "MANAGERPID"];
+
+/// The kind of unit, from the file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UnitKind {
+    /// `.service`
+    Service,
+    /// `.socket`
+    Socket,
+    /// `.timer`
+    Timer,
+    /// `.target`
+    Target,
+}
+
+impl UnitKind {
+    fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "service" => Some(UnitKind::Service),
+            "socket" => Some(UnitKind::Socket),
+            "timer" => Some(UnitKind::Timer),
+            "target" => Some(UnitKind::Target),
+            _ => None,
+        }
+    }
+}
+
+/// Something in a unit file that could not be imported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// The unit file
+    pub path: PathBuf,
+    /// Line of the directive, starting at 1
+    pub line: usize,
+    /// The section the directive is in, like `Service`
+    pub section: String,
+    /// The directive, like `ExecReload`
+    pub directive: String,
+    /// Why it was not imported
+    pub kind: WarningKind,
+}
+
+/// Why a directive was not imported, or not imported as is
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WarningKind {
+    /// easyinit has no equivalent for the directive, it was skipped
+    UnsupportedDirective,
+    /// easyinit has no equivalent for the section, every directive in it was skipped
+    UnsupportedSection,
+    /// The directive is supported, but not with this value
+    UnsupportedValue(String),
+    /// The directive was imported as something close to, but not the same as, the original
+    Approximated(String),
+    /// The value could not be parsed
+    Malformed(String),
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: [{}] {}: ", self.path.display(), self.line, self.section, self.directive)?;
+        match &self.kind {
+            WarningKind::UnsupportedDirective => f.write_str("not supported, skipped"),
+            WarningKind::UnsupportedSection => f.write_str("section not supported, skipped"),
+            WarningKind::UnsupportedValue(v) => write!(f, "value `{v}` is not supported, skipped"),
+            WarningKind::Approximated(how) => write!(f, "imported as {how}"),
+            WarningKind::Malformed(why) => write!(f, "malformed, skipped: {why}"),
+        }
+    }
+}
+
+/// A single imported unit
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    /// The unit file
+    pub path: PathBuf,
+    /// What kind of unit it was
+    pub kind: UnitKind,
+    /// The definition, for sockets and timers this is the service they start with only
+    /// [`ServiceDefinition::activation`] filled in
+    pub definition: ServiceDefinition,
+    /// Everything that could not be imported as is
+    pub warnings: Vec<Warning>,
+}
+
+/// Errors that stop a unit from being imported at all
+#[derive(thiserror::Error, Debug)]
+pub enum ImportError {
+    /// The file could not be read
+    #[error("Failed to read {}: {source}", path.display())]
+    Io {
+        /// The unit file
+        path: PathBuf,
+        /// What went wrong
+        source: std::io::Error,
+    },
+    /// The file extension is not a supported unit kind
+    #[error("{}: not a .service, .socket, .timer or .target unit", path.display())]
+    UnsupportedKind {
+        /// The unit file
+        path: PathBuf,
+    },
+}
+
+/// Imports a single unit file
+pub fn import_file(path: &Path) -> Result<Import, ImportError> {
+    let text = std::fs::read_to_string(path).map_err(|source| ImportError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    import_str(path, &text)
+}
+
+/// Imports a unit file that has already been read, `path` is used for the name and kind
+pub fn import_str(path: &Path, text: &str) -> Result<Import, ImportError> {
+    let unsupported = || ImportError::UnsupportedKind { path: path.to_path_buf() };
+    let file_name = path.file_name().ok_or_else(unsupported)?.to_string_lossy();
+    let (unit_name, extension) = file_name.rsplit_once('.').ok_or_else(unsupported)?;
+    let kind = UnitKind::from_extension(extension).ok_or_else(unsupported)?;
+
+    let mut importer = Importer {
+        path,
+        kind,
+        definition: ServiceDefinition { name: unit_name.to_string(), ..Default::default() },
+        warnings: Vec::new(),
+    };
+    for directive in parse(text) {
+        importer.directive(&directive);
+    }
+    let mut import = importer.finish();
+    if import.kind == UnitKind::Target {
+        import.definition.name = rename(&file_name);
+    }
+    Ok(import)
+}
+
+/// Imports every unit in a directory, merging sockets and timers into the services they start
+///
+/// Units that only have a socket or timer, but no service, are still returned.
+pub fn import_dir(dir: &Path) -> std::io::Result<(Vec<Import>, Vec<ImportError>)> {
+    let mut paths: Vec<_> = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().and_then(|e| UnitKind::from_extension(&e.to_string_lossy())).is_some())
+        .collect();
+    paths.sort();
+
+    let mut services: BTreeMap<String, Import> = BTreeMap::new();
+    let mut activations = Vec::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        match import_file(&path) {
+            Ok(import) if matches!(import.kind, UnitKind::Socket | UnitKind::Timer) => activations.push(import),
+            Ok(import) => {
+                services.insert(import.definition.name.clone(), import);
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+    for activation in activations {
+        match services.get_mut(&activation.definition.name) {
+            Some(service) => {
+                let from = activation.definition.activation;
+                let into = &mut service.definition.activation;
+                into.sockets.extend(from.sockets);
+                into.on_calendar.extend(from.on_calendar);
+                into.on_boot_sec = from.on_boot_sec.or(into.on_boot_sec);
+                into.on_active_sec = from.on_active_sec.or(into.on_active_sec);
+                service.warnings.extend(activation.warnings);
+            }
+            None => {
+                services.insert(activation.definition.name.clone(), activation);
+            }
+        }
+    }
+    Ok((services.into_values().collect(), errors))
+}
+
+/// A `Key=Value` line of a unit file
+struct Directive {
+    line: usize,
+    section: String,
+    key: String,
+    value: String,
+}
+
+/// Splits a unit file into directives, handling comments and line continuations
+fn parse(text: &str) -> Vec<Directive> {
+    let mut directives = Vec::new();
+    let mut section = String::new();
+    let mut lines = text.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let mut line = line.trim().to_string();
+        // A trailing backslash continues on the next line, comments in between are skipped
+        while line.ends_with('\\') {
+            line.pop();
+            line.push(' ');
+            match lines.next() {
+                Some((_, next)) if next.trim_start().starts_with(['#', ';']) => {}
+                Some((_, next)) => line.push_str(next.trim()),
+                None => break,
+            }
+        }
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.to_string();
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            directives.push(Directive {
+                line: index + 1,
+                section: section.clone(),
+                key: key.trim().to_string(),
+                value: value.trim().to_string(),
+            });
+        }
+    }
+    directives
+}
+
+struct Importer<'a> {
+    path: &'a Path,
+    kind: UnitKind,
+    definition: ServiceDefinition,
+    warnings: Vec<Warning>,
+}
+
+impl Importer<'_> {
+    fn finish(self) -> Import {
+        Import { path: self.path.to_path_buf(), kind: self.kind, definition: self.definition, warnings: self.warnings }
+    }
+
+    fn directive(&mut self, d: &Directive) {
+        let specifiers = crate::specifier::Specifiers::check(&d.value).map_err(|e| match e {
+            crate::specifier::SpecifierError::Unknown(specifier) => WarningKind::UnsupportedValue(format!("%{specifier}")),
+            e => WarningKind::Malformed(e.to_string()),
+        });
+        let result = specifiers.and_then(|()| match (d.section.as_str(), self.kind) {
+            ("Unit", _) => self.unit(d),
+            ("Install", _) => self.install(d),
+            ("Service", UnitKind::Service) => self.service(d),
+            ("Socket", UnitKind::Socket) => self.socket(d),
+            ("Timer", UnitKind::Timer) => self.timer(d),
+            _ => Err(WarningKind::UnsupportedSection),
+        });
+        if let Err(kind) = result {
+            self.warnings.push(Warning {
+                path: self.path.to_path_buf(),
+                line: d.line,
+                section: d.section.clone(),
+                directive: d.key.clone(),
+                kind,
+            });
+        }
+    }
+
+    fn unit(&mut self, d: &Directive) -> Result<(), WarningKind> {
+        let deps = &mut self.definition.dependencies;
+        match d.key.as_str() {
+            "Description" => self.definition.description = Some(d.value.clone()),
+            // Only informational, nothing to warn about
+            "Documentation" | "DefaultDependencies" => {}
+            "Requires" => set_names(&mut deps.requires, &d.value),
+            "Wants" => set_names(&mut deps.wants, &d.value),
+            "After" => set_names(&mut deps.after, &d.value),
+            "Before" => set_names(&mut deps.before, &d.value),
+            "BindsTo" | "Requisite" => {
+                set_names(&mut deps.requires, &d.value);
+                return Err(WarningKind::Approximated("`requires`".to_string()));
+            }
+            key if key.starts_with("Condition") || key.starts_with("Assert") => return self.condition(d),
+            _ => return Err(WarningKind::UnsupportedDirective),
+        }
+        Ok(())
+    }
+
+    /// `ConditionPathExists=` and friends, `Assert` ones become assertions
+    fn condition(&mut self, d: &Directive) -> Result<(), WarningKind> {
+        let (conditions, check) = match d.key.strip_prefix("Condition") {
+            Some(check) => (&mut self.definition.conditions, check),
+            None => (&mut self.definition.assertions, d.key.trim_start_matches("Assert")),
+        };
+        // A `|` makes the check one of several where any has to hold, easyinit needs all of them
+        if d.value.starts_with('|') {
+            return Err(WarningKind::UnsupportedValue(d.value.clone()));
+        }
+        let value = d.value.clone();
+        match check {
+            "PathExists" => conditions.path_exists.push(value),
+            "PathIsDirectory" => conditions.path_is_directory.push(value),
+            "PathIsMountPoint" => conditions.path_is_mountpoint.push(value),
+            "FileNotEmpty" => conditions.file_not_empty.push(value),
+            "KernelCommandLine" => conditions.kernel_command_line.push(value),
+            "Architecture" => conditions.architecture = Some(value),
+            "Virtualization" => conditions.virtualization = Some(value),
+            "FirstBoot" => {
+                conditions.first_boot = Some(crate::params::parse_bool(&value).map_err(WarningKind::Malformed)?)
+            }
+            "Memory" => conditions.memory = Some(Threshold::try_from(value).map_err(WarningKind::Malformed)?),
+            "CPUs" => conditions.cpus = Some(Threshold::try_from(value).map_err(WarningKind::Malformed)?),
+            _ => return Err(WarningKind::UnsupportedDirective),
+        }
+        Ok(())
+    }
+
+    fn install(&mut self, d: &Directive) -> Result<(), WarningKind> {
+        let install = &mut self.definition.install;
+        match d.key.as_str() {
+            "WantedBy" => set_names(&mut install.wanted_by, &d.value),
+            "RequiredBy" => set_names(&mut install.required_by, &d.value),
+            _ => return Err(WarningKind::UnsupportedDirective),
+        }
+        Ok(())
+    }
+
+    fn service(&mut self, d: &Directive) -> Result<(), WarningKind> {
+        let service = &mut self.definition.service;
+        match d.key.as_str() {
+            "Type" => match d.value.as_str() {
+                "simple" | "exec" => service.kind = ServiceKind::Simple,
+                "oneshot" => service.kind = ServiceKind::Oneshot,
+                other => return Err(WarningKind::UnsupportedValue(other.to_string())),
+            },
+            "ExecStart" => return exec(d, &mut service.exec),
+            "ExecStop" => return exec(d, &mut service.exec_stop),
+            "WorkingDirectory" => {
+                // A leading `-` means it is fine if it does not exist
+                let dir = d.value.trim_start_matches('-');
+                service.working_directory = (!dir.is_empty()).then(|| PathBuf::from(dir));
+            }
+            "User" => service.user = Some(d.value.clone()),
+            "Group" => service.group = Some(d.value.clone()),
+            "Environment" => {
+                for assignment in crate::shell::split(&d.value).map_err(WarningKind::Malformed)? {
+                    let (key, value) = assignment
+                        .split_once('=')
+                        .ok_or_else(|| WarningKind::Malformed(format!("`{assignment}` is not KEY=value")))?;
+                    service.environment.insert(key.to_string(), value.to_string());
+                }
+            }
+            "EnvironmentFile" => {
+                let file = EnvironmentFile::try_from(d.value.clone()).map_err(WarningKind::Malformed)?;
+                service.environment_files.push(file);
+            }
+            "TimeoutStopSec" => {
+                service.stop_timeout = Some(crate::params::parse_duration(&d.value).map_err(WarningKind::Malformed)?);
+            }
+            "Restart" => match d.value.as_str() {
+                "no" => service.restart = Some(RestartPolicy::No),
+                "on-failure" => service.restart = Some(RestartPolicy::OnFailure),
+                "always" => service.restart = Some(RestartPolicy::Always),
+                "on-abnormal" | "on-abort" | "on-watchdog" => {
+                    service.restart = Some(RestartPolicy::OnFailure);
+                    return Err(WarningKind::Approximated("`restart = \"on-failure\"`".to_string()));
+                }
+                other => return Err(WarningKind::UnsupportedValue(other.to_string())),
+            },
+            _ => return Err(WarningKind::UnsupportedDirective),
+        }
+        Ok(())
+    }
+
+    fn socket(&mut self, d: &Directive) -> Result<(), WarningKind> {
+        let protocol = match d.key.as_str() {
+            "ListenStream" => "tcp",
+            "ListenDatagram" => "udp",
+            "Service" => {
+                self.definition.name = rename(&d.value);
+                return Ok(());
+            }
+            _ => return Err(WarningKind::UnsupportedDirective),
+        };
+        let address = if d.value.starts_with('/') {
+            d.value.clone()
+        } else if d.value.chars().all(|c| c.is_ascii_digit()) {
+            // A port on its own listens on every address
+            format!("{protocol}:[::]:{}", d.value)
+        } else {
+            format!("{protocol}:{}", d.value)
+        };
+        self.definition.activation.sockets.push(address);
+        Ok(())
+    }
+
+    fn timer(&mut self, d: &Directive) -> Result<(), WarningKind> {
+        let activation = &mut self.definition.activation;
+        match d.key.as_str() {
+            "OnCalendar" => activation.on_calendar.push(d.value.clone()),
+            "OnBootSec" | "OnStartupSec" => {
+                activation.on_boot_sec = Some(crate::params::parse_duration(&d.value).map_err(WarningKind::Malformed)?);
+            }
+            "OnUnitActiveSec" => {
+                activation.on_active_sec = Some(crate::params::parse_duration(&d.value).map_err(WarningKind::Malformed)?);
+            }
+            "Unit" => self.definition.name = rename(&d.value),
+            _ => return Err(WarningKind::UnsupportedDirective),
+        }
+        Ok(())
+    }
+}
+
+/// `ExecStart=` or `ExecStop=`, easyinit runs a single command for each
+fn exec(d: &Directive, command_line: &mut Option<CommandLine>) -> Result<(), WarningKind> {
+    if command_line.is_some() {
+        return Err(WarningKind::UnsupportedValue("more than one command".to_string()));
+    }
+    // Prefixes change how the command is run, easyinit only supports running it as is
+    let command = d.value.trim_start_matches(['-', '@', ':', '+', '!']);
+    // Only systemd knows these, the command would get the name instead of the value
+    if let Some(variable) = MANAGER_VARIABLES.iter().find(|v| command.contains(&format!("${v}")) || command.contains(&format!("${{{v}}}"))) {
+        return Err(WarningKind::UnsupportedValue(format!("${variable}")));
+    }
+    let mut approximations = Vec::new();
+    if command.len() != d.value.len() {
+        let prefix = &d.value[..d.value.len() - command.len()];
+        approximations.push(format!("the command without the `{prefix}` prefix"));
+    }
+    let args = if command.contains('$') {
+        approximations.push("a command run by `/bin/sh -c`, which expands the variables".to_string());
+        vec!["/bin/sh".to_string(), "-c".to_string(), format!("exec {command}")]
+    } else {
+        crate::shell::split(command).map_err(WarningKind::Malformed)?
+    };
+    *command_line = Some(CommandLine::try_from(args).map_err(WarningKind::Malformed)?);
+    if approximations.is_empty() { Ok(()) } else { Err(WarningKind::Approximated(approximations.join(" and "))) }
+}
+
+/// Sets a list of unit names, an empty value clears the list like systemd does
+fn set_names(list: &mut Vec<String>, value: &str) {
+    if value.is_empty() {
+        list.clear();
+    }
+    list.extend(value.split_whitespace().map(rename));
+}
+
+/// Turns a systemd unit name into a easyinit name
+fn rename(unit: &str) -> String {
+    match unit {
+        "default.target" | "multi-user.target" | "graphical.target" => "default".to_string(),
+        "rescue.target" => "rescue".to_string(),
+        "emergency.target" => "emergency".to_string(),
+        unit => match unit.rsplit_once('.') {
+            Some((name, extension)) if UnitKind::from_extension(extension).is_some() => name.to_string(),
+            _ => unit.to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixtures() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/systemd")
+    }
+
+    /// The directive and kind of every warning
+    fn warnings(import: &Import) -> Vec<(&str, &WarningKind)> {
+        import.warnings.iter().map(|w| (w.directive.as_str(), &w.kind)).collect()
+    }
+
+    #[test]
+    fn service() {
+        let import = import_file(&fixtures().join("sshd.service")).unwrap();
+        let definition = &import.definition;
+        assert_eq!(definition.name, "sshd");
+        assert_eq!(definition.dependencies.requires, ["network"]);
+        assert_eq!(definition.install.wanted_by, ["default"]);
+        // easyinit does not expand variables, the shell does for those of the environment
+        let exec = vec!["/bin/sh".into(), "-c".into(), "exec /usr/sbin/sshd -D $SSHD_OPTS".into()];
+        assert_eq!(definition.service.exec, Some(CommandLine(exec)));
+        // `$MAINPID` has no value outside of systemd, stopping falls back to the default
+        assert_eq!(definition.service.exec_stop, None);
+        assert_eq!(definition.service.environment.get("SSHD_OPTS").map(String::as_str), Some("-e"));
+        assert_eq!(definition.service.stop_timeout, Some(90));
+        assert_eq!(definition.service.restart, Some(RestartPolicy::OnFailure));
+        assert_eq!(
+            warnings(&import),
+            [
+                ("BindsTo", &WarningKind::Approximated("`requires`".to_string())),
+                ("Type", &WarningKind::UnsupportedValue("notify".to_string())),
+                ("ExecStart", &WarningKind::Approximated("a command run by `/bin/sh -c`, which expands the variables".to_string())),
+                ("ExecStop", &WarningKind::UnsupportedValue("$MAINPID".to_string())),
+                ("ExecReload", &WarningKind::UnsupportedDirective),
+                ("Restart", &WarningKind::Approximated("`restart = \"on-failure\"`".to_string())),
+            ]
+        );
+        assert_eq!(import.warnings[3].line, 10);
+        assert_eq!(import.warnings[3].section, "Service");
+    }
+
+    #[test]
+    fn variables_and_prefixes() {
+        let text = "[Service]\nExecStart=-/usr/bin/app ${ARGS}\nExecStop=/bin/kill -HUP ${MAINPID}\n";
+        let import = import_str(Path::new("app.service"), text).unwrap();
+        assert_eq!(
+            warnings(&import),
+            [
+                (
+                    "ExecStart",
+                    &WarningKind::Approximated(
+                        "the command without the `-` prefix and a command run by `/bin/sh -c`, which expands the variables".to_string()
+                    )
+                ),
+                ("ExecStop", &WarningKind::UnsupportedValue("$MAINPID".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_specifiers() {
+        let text = "[Unit]\nDescription=Getty on %I\nConditionPathExists=/dev/%i\n\n\
+                    [Service]\nExecStart=/sbin/agetty --noclear %i %f\nEnvironment=PROGRESS=100%\n";
+        let import = import_str(Path::new("getty@.service"), text).unwrap();
+        // Known specifiers are kept for the loader to expand
+        assert_eq!(import.definition.conditions.path_exists, ["/dev/%i"]);
+        assert_eq!(import.definition.description, None);
+        assert_eq!(import.definition.service.exec, None);
+        assert_eq!(
+            warnings(&import),
+            [
+                ("Description", &WarningKind::UnsupportedValue("%I".to_string())),
+                ("ExecStart", &WarningKind::UnsupportedValue("%f".to_string())),
+                ("Environment", &WarningKind::Malformed("`%` at the end, write `%%` for a literal `%`".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn conditions_and_continuations() {
+        let import = import_file(&fixtures().join("backup.service")).unwrap();
+        let definition = &import.definition;
+        assert_eq!(definition.service.kind, ServiceKind::Oneshot);
+        assert_eq!(definition.service.exec, Some(CommandLine(vec!["/usr/bin/backup".into(), "--all".into()])));
+        assert_eq!(definition.conditions.path_is_directory, ["/srv/backup"]);
+        assert!(definition.conditions.path_exists.is_empty());
+        assert!(definition.conditions.memory.is_some());
+        assert_eq!(
+            warnings(&import),
+            [
+                ("ConditionPathExists", &WarningKind::UnsupportedValue("|/etc/backup.conf".to_string())),
+                ("ExecStart", &WarningKind::UnsupportedValue("more than one command".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn socket() {
+        let import = import_file(&fixtures().join("web.socket")).unwrap();
+        assert_eq!(import.kind, UnitKind::Socket);
+        assert_eq!(import.definition.name, "httpd");
+        assert_eq!(import.definition.activation.sockets, ["tcp:[::]:8080", "udp:127.0.0.1:53"]);
+        assert_eq!(warnings(&import), [("Key", &WarningKind::UnsupportedSection)]);
+    }
+
+    #[test]
+    fn directory_merges_timers() {
+        let (imports, errors) = import_dir(&fixtures()).unwrap();
+        assert!(errors.is_empty());
+        let names: Vec<_> = imports.iter().map(|i| i.definition.name.as_str()).collect();
+        assert_eq!(names, ["backup", "httpd", "sshd"]);
+        let backup = &imports[0];
+        assert_eq!(backup.kind, UnitKind::Service);
+        assert_eq!(backup.definition.activation.on_calendar, ["daily"]);
+        assert_eq!(backup.definition.activation.on_boot_sec, Some(900));
+        assert!(warnings(backup).contains(&("Persistent", &WarningKind::UnsupportedDirective)));
+    }
+
+    #[test]
+    fn targets_are_renamed() {
+        let import = import_str(Path::new("multi-user.target"), "[Unit]\nWants=sshd.service\n").unwrap();
+        assert_eq!(import.definition.name, "default");
+        assert_eq!(import.definition.dependencies.wants, ["sshd"]);
+        assert!(matches!(import_str(Path::new("dev-sda.mount"), ""), Err(ImportError::UnsupportedKind { .. })));
+    }
+}
@@ -0,0 +1,562 @@
+//! Compatibility with SysV init, `/etc/inittab` and LSB `/etc/init.d` scripts
+//!
+//! Both are turned into service definitions, so they boot under easyinit without being rewritten.
+//!
+//! Runlevels become targets:
+//!
+//! | Runlevel                      | Target       |
+//! |-------------------------------|--------------|
+//! | the `initdefault` runlevel    | `default`    |
+//! | `0`                           | `poweroff`   |
+//! | `1`, `S`, `s`                 | `rescue`     |
+//! | `6`                           | `reboot`     |
+//! | any other, like `3`           | `runlevel3`  |
+//!
+//! Entries with the `sysinit`, `boot` and `bootwait` actions are wanted by the `sysinit` target,
+//! which every other entry is ordered after.
+//!
+//! LSB scripts are only wanted by the runlevels in their `Default-Start`, none if it is empty.
+//! `S` is the `rcS.d` of the boot before any runlevel, so it is the `sysinit` target as well, and
+//! `0` and `6` are left out, scripts are only stopped there.
+//!
+//! Entries for events are wanted by a target that easyinit starts when the kernel tells it about
+//! the event:
+//!
+//! | Action                        | Target              | Started on                          |
+//! |-------------------------------|---------------------|-------------------------------------|
+//! | `ctrlaltdel`                  | `ctrl-alt-del`      | `SIGINT`, Ctrl+Alt+Del was pressed  |
+//! | `powerfail`, `powerwait`      | `powerfail`         | `SIGPWR`, see [`power_target`]      |
+//! | `powerfailnow`                | `powerfail-now`     | `SIGPWR`                            |
+//! | `powerokwait`                 | `powerok`           | `SIGPWR`                            |
+//! | `kbrequest`                   | `kbrequest`         | `SIGWINCH`, the keyboard request    |
+// cSpell:words inittab initdefault sysinit bootwait respawn powerwait powerfail powerokwait powerfailnow ctrlaltdel kbrequest ondemand runlevel runlevels
+use crate::service::{CommandLine, RestartPolicy, ServiceDefinition, ServiceKind};
+use std::path::{Path, PathBuf};
+
+/// The runlevel used when inittab does not have an `initdefault` entry
+pub const DEFAULT_RUNLEVEL: char = '3';
+
+/// Target the `sysinit`, `boot` and `bootwait` entries are wanted by
+pub const SYSINIT_TARGET: &str = "sysinit";
+
+/// Target the `ctrlaltdel` entries are wanted by
+pub const CTRL_ALT_DEL_TARGET: &str = "ctrl-alt-del";
+
+/// Target the `kbrequest` entries are wanted by
+pub const KBREQUEST_TARGET: &str = "kbrequest";
+
+/// Files a UPS daemon writes the power status to before it sends `SIGPWR`, the first one found is used
+pub const POWER_STATUS: &[&str] = &["/run/powerstatus", "/var/run/powerstatus", "/etc/powerstatus"];
+
+/// What init does with an inittab entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Started again whenever it exits
+    Respawn,
+    /// Run once when the runlevel is entered, init waits for it to exit
+    Wait,
+    /// Run once when the runlevel is entered
+    Once,
+    /// Run during boot, ignoring the runlevel
+    Boot,
+    /// Run during boot, init waits for it to exit
+    BootWait,
+    /// Run during boot before any `boot` or `bootwait` entries, init waits for it to exit
+    SysInit,
+    /// Does nothing
+    Off,
+    /// Only used by `telinit a`, `b` or `c`
+    OnDemand,
+    /// The runlevel entered after boot, the process is ignored
+    InitDefault,
+    /// Run when the power is failing, init waits for it to exit
+    PowerWait,
+    /// Run when the power is failing
+    PowerFail,
+    /// Run when the power is restored, init waits for it to exit
+    PowerOkWait,
+    /// Run when the power is failing and the battery is almost empty
+    PowerFailNow,
+    /// Run when Ctrl+Alt+Del is pressed
+    CtrlAltDel,
+    /// Run when the special key combination on the keyboard is pressed
+    KbRequest,
+}
+
+impl std::str::FromStr for Action {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "respawn" => Action::Respawn,
+            "wait" => Action::Wait,
+            "once" => Action::Once,
+            "boot" => Action::Boot,
+            "bootwait" => Action::BootWait,
+            "sysinit" => Action::SysInit,
+            "off" => Action::Off,
+            "ondemand" => Action::OnDemand,
+            "initdefault" => Action::InitDefault,
+            "powerwait" => Action::PowerWait,
+            "powerfail" => Action::PowerFail,
+            "powerokwait" => Action::PowerOkWait,
+            "powerfailnow" => Action::PowerFailNow,
+            "ctrlaltdel" => Action::CtrlAltDel,
+            "kbrequest" => Action::KbRequest,
+            other => return Err(format!("unknown action `{other}`")),
+        })
+    }
+}
+
+/// A line of `/etc/inittab`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InittabEntry {
+    /// Line the entry is on, starting at 1
+    pub line: usize,
+    /// Unique id of the entry
+    pub id: String,
+    /// Runlevels the entry is started in, empty means every runlevel
+    pub runlevels: String,
+    /// What init does with the entry
+    pub action: Action,
+    /// The command, run with `/bin/sh -c` if it uses anything a shell has to handle
+    pub process: String,
+}
+
+/// The parsed `/etc/inittab`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Inittab {
+    /// The file it was read from
+    pub path: PathBuf,
+    /// Every entry, in the order of the file
+    pub entries: Vec<InittabEntry>,
+}
+
+/// Errors while reading SysV init files
+#[derive(thiserror::Error, Debug)]
+pub enum SysvError {
+    /// The file could not be read
+    #[error("Failed to read {}: {source}", path.display())]
+    Io {
+        /// The file
+        path: PathBuf,
+        /// What went wrong
+        source: std::io::Error,
+    },
+    /// A line could not be parsed
+    #[error("{}:{line}: {message}", path.display())]
+    Parse {
+        /// The file
+        path: PathBuf,
+        /// Line of the error, starting at 1
+        line: usize,
+        /// What went wrong
+        message: String,
+    },
+    /// An init script has no `### BEGIN INIT INFO` block
+    #[error("{}: no LSB header", path.display())]
+    NoHeader {
+        /// The script
+        path: PathBuf,
+    },
+}
+
+impl Inittab {
+    /// Reads and parses an inittab file
+    pub fn from_file(path: &Path) -> Result<Self, SysvError> {
+        let text = std::fs::read_to_string(path).map_err(|source| SysvError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Inittab::parse(path, &text)
+    }
+
+    /// Parses an inittab that has already been read from `path`
+    pub fn parse(path: &Path, text: &str) -> Result<Self, SysvError> {
+        let mut entries = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| SysvError::Parse { path: path.to_path_buf(), line: line_number, message };
+            // The process is the rest of the line, and may contain `:` itself
+            let mut fields = trimmed.splitn(4, ':');
+            let (Some(id), Some(runlevels), Some(action), Some(process)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(error("expected `id:runlevels:action:process`".to_string()));
+            };
+            if id.is_empty() {
+                return Err(error("the id is empty".to_string()));
+            }
+            entries.push(InittabEntry {
+                line: line_number,
+                id: id.to_string(),
+                runlevels: runlevels.to_string(),
+                action: action.parse().map_err(error)?,
+                process: process.trim().to_string(),
+            });
+        }
+        Ok(Inittab { path: path.to_path_buf(), entries })
+    }
+
+    /// The runlevel from the `initdefault` entry, [`DEFAULT_RUNLEVEL`] if there is none
+    pub fn default_runlevel(&self) -> char {
+        self.entries
+            .iter()
+            .find(|e| e.action == Action::InitDefault)
+            .and_then(|e| e.runlevels.chars().next())
+            .unwrap_or(DEFAULT_RUNLEVEL)
+    }
+
+    /// Turns every entry into a definition named `inittab-<id>`, plus the [`SYSINIT_TARGET`]
+    ///
+    /// `off`, `ondemand` and `initdefault` entries don't have a definition. Entries with a process
+    /// that can't be parsed are skipped, their errors are collected.
+    pub fn definitions(&self) -> (Vec<ServiceDefinition>, Vec<SysvError>) {
+        let default = self.default_runlevel();
+        let mut definitions = vec![sysinit_target()];
+        let mut errors = Vec::new();
+        for entry in &self.entries {
+            let targets = match entry.action {
+                Action::Off | Action::OnDemand | Action::InitDefault => continue,
+                Action::SysInit | Action::Boot | Action::BootWait => vec![SYSINIT_TARGET.to_string()],
+                Action::PowerWait | Action::PowerFail => vec![power_target(Some('F')).to_string()],
+                Action::PowerFailNow => vec![power_target(Some('L')).to_string()],
+                Action::PowerOkWait => vec![power_target(Some('O')).to_string()],
+                Action::CtrlAltDel => vec![CTRL_ALT_DEL_TARGET.to_string()],
+                Action::KbRequest => vec![KBREQUEST_TARGET.to_string()],
+                Action::Respawn | Action::Wait | Action::Once => runlevel_targets(&entry.runlevels, default),
+            };
+            let (kind, restart) = match entry.action {
+                Action::Respawn => (ServiceKind::Simple, RestartPolicy::Always),
+                Action::Once | Action::Boot | Action::PowerFail | Action::KbRequest => {
+                    (ServiceKind::Simple, RestartPolicy::No)
+                }
+                _ => (ServiceKind::Oneshot, RestartPolicy::No),
+            };
+            let mut definition = ServiceDefinition {
+                name: format!("inittab-{}", entry.id),
+                description: Some(format!("inittab entry {}", entry.id)),
+                ..Default::default()
+            };
+            definition.service.kind = kind;
+            definition.service.restart = Some(restart);
+            match process_command(&entry.process) {
+                Ok(command) => definition.service.exec = Some(command),
+                Err(message) => {
+                    errors.push(SysvError::Parse { path: self.path.clone(), line: entry.line, message });
+                    continue;
+                }
+            }
+            if targets.iter().any(|t| t == SYSINIT_TARGET) {
+                definition.dependencies.before.push(SYSINIT_TARGET.to_string());
+            } else {
+                definition.dependencies.after.push(SYSINIT_TARGET.to_string());
+            }
+            definition.install.wanted_by = targets;
+            definitions.push(definition);
+        }
+        (definitions, errors)
+    }
+}
+
+/// The target to start on `SIGPWR`, from the first letter of the power status file
+///
+/// `O` is power restored, `L` is a failing power with the battery almost empty. Anything else,
+/// or no status file at all, is a failing power like sysvinit assumes.
+pub fn power_target(status: Option<char>) -> &'static str {
+    match status {
+        Some('O') => "powerok",
+        Some('L') => "powerfail-now",
+        _ => "powerfail",
+    }
+}
+
+/// The LSB header of an `/etc/init.d` script
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InitScript {
+    /// The script
+    pub path: PathBuf,
+    /// `Provides`, the names the script can be depended on by
+    pub provides: Vec<String>,
+    /// `Required-Start`, needed before the script starts
+    pub required_start: Vec<String>,
+    /// `Required-Stop`, needed until the script has stopped
+    pub required_stop: Vec<String>,
+    /// `Should-Start`, started before the script if they are started at all
+    pub should_start: Vec<String>,
+    /// `Should-Stop`, stopped after the script if they are started at all
+    pub should_stop: Vec<String>,
+    /// `Default-Start`, runlevels the script is started in
+    pub default_start: Vec<String>,
+    /// `Default-Stop`, runlevels the script is stopped in
+    pub default_stop: Vec<String>,
+    /// `Short-Description`
+    pub short_description: Option<String>,
+}
+
+impl InitScript {
+    /// Reads the LSB header of a script
+    pub fn from_file(path: &Path) -> Result<Self, SysvError> {
+        let text = std::fs::read_to_string(path).map_err(|source| SysvError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        InitScript::parse(path, &text)
+    }
+
+    /// Parses the LSB header of a script that has already been read
+    pub fn parse(path: &Path, text: &str) -> Result<Self, SysvError> {
+        let mut script = InitScript { path: path.to_path_buf(), ..Default::default() };
+        let mut in_header = false;
+        let mut found = false;
+        for line in text.lines() {
+            match line.trim_end() {
+                "### BEGIN INIT INFO" => {
+                    in_header = true;
+                    found = true;
+                }
+                "### END INIT INFO" => break,
+                line if in_header => {
+                    let Some((key, value)) = line.trim_start_matches('#').split_once(':') else {
+                        continue;
+                    };
+                    let list = || value.split_whitespace().map(facility).collect::<Vec<_>>();
+                    match key.trim() {
+                        "Provides" => script.provides = list(),
+                        "Required-Start" => script.required_start = list(),
+                        "Required-Stop" => script.required_stop = list(),
+                        "Should-Start" => script.should_start = list(),
+                        "Should-Stop" => script.should_stop = list(),
+                        "Default-Start" => script.default_start = value.split_whitespace().map(str::to_string).collect(),
+                        "Default-Stop" => script.default_stop = value.split_whitespace().map(str::to_string).collect(),
+                        "Short-Description" => script.short_description = Some(value.trim().to_string()),
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !found {
+            return Err(SysvError::NoHeader { path: path.to_path_buf() });
+        }
+        // `$all` means after everything else, which can not be expressed as a dependency
+        for list in [&mut script.required_start, &mut script.should_start, &mut script.required_stop, &mut script.should_stop] {
+            list.retain(|name| name != "all");
+        }
+        Ok(script)
+    }
+
+    /// Name of the service, the file name of the script
+    pub fn name(&self) -> String {
+        self.path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+    }
+
+    /// Turns the script into a definition that runs `script start` and `script stop`
+    pub fn definition(&self, default_runlevel: char) -> ServiceDefinition {
+        let script = self.path.to_string_lossy().into_owned();
+        let mut definition = ServiceDefinition {
+            name: self.name(),
+            description: self.short_description.clone(),
+            ..Default::default()
+        };
+        definition.service.kind = ServiceKind::Oneshot;
+        definition.service.exec = Some(CommandLine(vec![script.clone(), "start".to_string()]));
+        definition.service.exec_stop = Some(CommandLine(vec![script, "stop".to_string()]));
+        definition.dependencies.requires = self.required_start.clone();
+        definition.dependencies.wants = self.should_start.clone();
+        definition.dependencies.after = self.required_start.iter().chain(&self.should_start).cloned().collect();
+        definition.install.wanted_by = start_targets(&self.default_start.concat(), default_runlevel);
+        if definition.install.wanted_by.iter().any(|t| t == SYSINIT_TARGET) {
+            definition.dependencies.before.push(SYSINIT_TARGET.to_string());
+        } else {
+            definition.dependencies.after.push(SYSINIT_TARGET.to_string());
+        }
+        definition
+    }
+}
+
+/// Definitions for every script with a LSB header in a directory, like `/etc/init.d`
+///
+/// Scripts without a header are skipped, any other error is collected.
+pub fn init_d_definitions(dir: &Path, default_runlevel: char) -> (Vec<ServiceDefinition>, Vec<SysvError>) {
+    let mut definitions = Vec::new();
+    let mut errors = Vec::new();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return (definitions, errors);
+    };
+    let mut paths: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_file()).collect();
+    paths.sort();
+    for path in paths {
+        match InitScript::from_file(&path) {
+            Ok(script) => definitions.push(script.definition(default_runlevel)),
+            Err(SysvError::NoHeader { .. }) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+    (definitions, errors)
+}
+
+/// The [`SYSINIT_TARGET`], wanted by the `default` and `rescue` targets
+pub fn sysinit_target() -> ServiceDefinition {
+    let mut sysinit = ServiceDefinition {
+        name: SYSINIT_TARGET.to_string(),
+        description: Some("SysV system initialization".to_string()),
+        ..Default::default()
+    };
+    sysinit.install.wanted_by = vec!["default".to_string(), "rescue".to_string()];
+    sysinit
+}
+
+/// Targets a LSB script is started in, from its `Default-Start` runlevels, see the [module](self)
+fn start_targets(runlevels: &str, default: char) -> Vec<String> {
+    let mut targets = Vec::new();
+    for level in runlevels.chars() {
+        let target = match level {
+            'S' | 's' => SYSINIT_TARGET.to_string(),
+            '0' | '6' => continue,
+            level if level == default => "default".to_string(),
+            '1' => "rescue".to_string(),
+            level => format!("runlevel{level}"),
+        };
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+    targets
+}
+
+/// Targets for a list of inittab runlevels, every runlevel if it is empty
+fn runlevel_targets(runlevels: &str, default: char) -> Vec<String> {
+    let runlevels = if runlevels.is_empty() { "123456" } else { runlevels };
+    let mut targets: Vec<String> = runlevels
+        .chars()
+        .map(|level| match level {
+            level if level == default => "default".to_string(),
+            '0' => "poweroff".to_string(),
+            '1' | 'S' | 's' => "rescue".to_string(),
+            '6' => "reboot".to_string(),
+            level => format!("runlevel{level}"),
+        })
+        .collect();
+    targets.dedup();
+    targets
+}
+
+/// The command of a inittab entry, with `/bin/sh -c` if it needs a shell like sysvinit does
+fn process_command(process: &str) -> Result<CommandLine, String> {
+    // A leading `+` only stops init from writing utmp entries
+    let process = process.strip_prefix('+').unwrap_or(process);
+    if process.contains(['~', '`', '!', '$', '^', '&', '*', '(', ')', '=', '|', '\\', '{', '}', '[', ']', ';', '"', '\'', '<', '>', '?']) {
+        Ok(CommandLine(vec!["/bin/sh".to_string(), "-c".to_string(), format!("exec {process}")]))
+    } else {
+        CommandLine::try_from(process.split_whitespace().map(str::to_string).collect::<Vec<_>>())
+    }
+}
+
+/// Turns a LSB facility like `$local_fs` into a easyinit name like `local-fs`
+fn facility(name: &str) -> String {
+    match name.strip_prefix('$') {
+        Some(facility) => facility.replace('_', "-"),
+        None => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INITTAB: &str = "\
+id:2:initdefault:
+si::sysinit:/etc/init.d/rcS
+1:2345:respawn:/sbin/getty 38400 tty1
+ca::ctrlaltdel:/sbin/shutdown -t3 -r now
+pf::powerwait:/etc/init.d/powerfail start
+po::powerokwait:/etc/init.d/powerfail stop
+kb::kbrequest:
+";
+
+    fn wanted_by<'a>(definitions: &'a [ServiceDefinition], name: &str) -> &'a [String] {
+        &definitions.iter().find(|d| d.name == name).unwrap().install.wanted_by
+    }
+
+    #[test]
+    fn entries() {
+        let inittab = Inittab::parse(Path::new("/etc/inittab"), INITTAB).unwrap();
+        assert_eq!(inittab.default_runlevel(), '2');
+        let (definitions, errors) = inittab.definitions();
+        assert_eq!(wanted_by(&definitions, "inittab-si"), [SYSINIT_TARGET]);
+        assert_eq!(wanted_by(&definitions, "inittab-1"), ["default", "runlevel3", "runlevel4", "runlevel5"]);
+        assert_eq!(wanted_by(&definitions, "inittab-ca"), [CTRL_ALT_DEL_TARGET]);
+        assert_eq!(wanted_by(&definitions, "inittab-pf"), ["powerfail"]);
+        assert_eq!(wanted_by(&definitions, "inittab-po"), ["powerok"]);
+        let getty = definitions.iter().find(|d| d.name == "inittab-1").unwrap();
+        assert_eq!(getty.service.restart, Some(RestartPolicy::Always));
+        // The `kbrequest` entry has no process, only it is skipped
+        assert!(definitions.iter().all(|d| d.name != "inittab-kb"));
+        assert!(matches!(errors.as_slice(), [SysvError::Parse { line: 7, .. }]));
+    }
+
+    #[test]
+    fn malformed_lines() {
+        assert!(matches!(Inittab::parse(Path::new("inittab"), "x:1:respawn"), Err(SysvError::Parse { line: 1, .. })));
+        assert!(matches!(Inittab::parse(Path::new("inittab"), "\nx:1:sometimes:/bin/sh"), Err(SysvError::Parse { line: 2, .. })));
+    }
+
+    #[test]
+    fn power_status() {
+        assert_eq!(power_target(Some('O')), "powerok");
+        assert_eq!(power_target(Some('L')), "powerfail-now");
+        assert_eq!(power_target(Some('F')), "powerfail");
+        assert_eq!(power_target(None), "powerfail");
+    }
+
+    #[test]
+    fn shell_commands() {
+        assert_eq!(process_command("+/sbin/getty tty1"), Ok(CommandLine(vec!["/sbin/getty".into(), "tty1".into()])));
+        assert_eq!(
+            process_command("echo $HOME > /dev/null"),
+            Ok(CommandLine(vec!["/bin/sh".into(), "-c".into(), "exec echo $HOME > /dev/null".into()]))
+        );
+    }
+
+    #[test]
+    fn lsb_header() {
+        let text = "#!/bin/sh\n### BEGIN INIT INFO\n# Provides: ntp\n# Required-Start: $network $remote_fs\n\
+                    # Should-Start: $all\n# Default-Start: 2 3 4 5\n# Short-Description: NTP\n### END INIT INFO\n";
+        let script = InitScript::parse(Path::new("/etc/init.d/ntp"), text).unwrap();
+        let definition = script.definition('2');
+        assert_eq!(definition.name, "ntp");
+        assert_eq!(definition.dependencies.requires, ["network", "remote-fs"]);
+        assert!(definition.dependencies.wants.is_empty());
+        assert_eq!(definition.install.wanted_by, ["default", "runlevel3", "runlevel4", "runlevel5"]);
+        assert!(matches!(InitScript::parse(Path::new("plain"), "#!/bin/sh\n"), Err(SysvError::NoHeader { .. })));
+    }
+
+    fn script(name: &str, default_start: &str) -> ServiceDefinition {
+        let text = format!("### BEGIN INIT INFO\n# Provides: {name}\n# Default-Start: {default_start}\n# Default-Stop: 0 6\n### END INIT INFO\n");
+        InitScript::parse(&Path::new("/etc/init.d").join(name), &text).unwrap().definition('2')
+    }
+
+    #[test]
+    fn rcs_scripts_start_at_boot() {
+        let udev = script("udev", "S");
+        assert_eq!(udev.install.wanted_by, [SYSINIT_TARGET]);
+        // Ordered before the target that wants it, not after
+        assert_eq!(udev.dependencies.before, [SYSINIT_TARGET]);
+        assert!(udev.dependencies.after.is_empty());
+        assert_eq!(sysinit_target().install.wanted_by, ["default", "rescue"]);
+        assert_eq!(script("ntp", "2 3 4 5").dependencies.after, [SYSINIT_TARGET]);
+    }
+
+    #[test]
+    fn scripts_without_start_runlevels() {
+        assert!(script("sendsigs", "").install.wanted_by.is_empty());
+        assert!(script("halt", "0").install.wanted_by.is_empty());
+        assert_eq!(script("single", "1 6").install.wanted_by, ["rescue"]);
+        // Inittab entries without runlevels are in every runlevel, unlike scripts
+        assert_eq!(runlevel_targets("", '2'), ["rescue", "default", "runlevel3", "runlevel4", "runlevel5", "reboot"]);
+    }
+}
@@ -0,0 +1,122 @@
+//! Splits the kernel command line into parameters
+//!
+//! Follows the same rules the kernel uses in `next_arg()`:
+//!
+//! * Parameters are separated by whitespace.
+//! * Double quotes group whitespace into a single parameter and are removed, so both
+//!   `key="a b"` and `"key=a b"` give the key `key` with the value `a b`.
+//! * The first `=` separates the key from the value, anything without one is a bare flag.
+//! * Everything after a lone `--` is not a parameter, but an argument handed to init.
+//!
+//! Unlike the kernel, a unterminated quote is not an error, it runs till the end of the line.
+
+/// A single parameter from the kernel command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    /// Everything before the first `=`
+    pub key: String,
+    /// Everything after the first `=`, `None` if it is a bare flag like `quiet`
+    pub value: Option<String>,
+}
+
+/// The result of [`tokenize`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Tokens {
+    /// Parameters in the order they appear on the command line.
+    ///
+    /// Repeated keys are kept, the consumer decides that the last one wins.
+    pub params: Vec<Param>,
+    /// Arguments after the `--` separator
+    pub init_args: Vec<String>,
+}
+
+/// Splits `line` into parameters, view [module level documentation] for the rules.
+///
+/// [module level documentation]: self
+pub fn tokenize(line: &str) -> Tokens {
+    let mut tokens = Tokens::default();
+    let mut words = Words { chars: line.chars() };
+
+    while let Some((word, quoted)) = words.next_word() {
+        // A quoted `"--"` is a parameter, not the separator
+        if word == "--" && !quoted {
+            tokens.init_args.extend(std::iter::from_fn(|| words.next_word().map(|(w, _)| w)));
+            break;
+        }
+        let param = match word.split_once('=') {
+            Some((key, value)) => Param { key: key.to_string(), value: Some(value.to_string()) },
+            None => Param { key: word, value: None },
+        };
+        tokens.params.push(param);
+    }
+    tokens
+}
+
+/// Iterator over whitespace separated words, with quotes removed
+struct Words<'a> {
+    chars: std::str::Chars<'a>,
+}
+
+impl Words<'_> {
+    /// Returns the next word, and if any part of it was quoted
+    fn next_word(&mut self) -> Option<(String, bool)> {
+        let mut word = String::new();
+        let mut in_quote = false;
+        let mut quoted = false;
+        let mut started = false;
+
+        for c in self.chars.by_ref() {
+            match c {
+                '"' => {
+                    in_quote = !in_quote;
+                    quoted = true;
+                    started = true;
+                }
+                c if c.is_whitespace() && !in_quote => {
+                    if started {
+                        break;
+                    }
+                }
+                c => {
+                    word.push(c);
+                    started = true;
+                }
+            }
+        }
+        started.then_some((word, quoted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(key: &str, value: Option<&str>) -> Param {
+        Param { key: key.to_string(), value: value.map(str::to_string) }
+    }
+
+    #[test]
+    fn flags_and_values() {
+        let tokens = tokenize("  ro quiet root=/dev/sda1\tinit=/sbin/init=x \n");
+        assert_eq!(
+            tokens.params,
+            [param("ro", None), param("quiet", None), param("root", Some("/dev/sda1")), param("init", Some("/sbin/init=x"))]
+        );
+    }
+
+    #[test]
+    fn quotes() {
+        let tokens = tokenize(r#"key="a b" "other=c d" empty="" "unterminated=e f"#);
+        assert_eq!(
+            tokens.params,
+            [param("key", Some("a b")), param("other", Some("c d")), param("empty", Some("")), param("unterminated", Some("e f"))]
+        );
+    }
+
+    #[test]
+    fn separator() {
+        let tokens = tokenize(r#"quiet "--" -- single "a b""#);
+        assert_eq!(tokens.params, [param("quiet", None), param("--", None)]);
+        assert_eq!(tokens.init_args, ["single", "a b"]);
+    }
+}
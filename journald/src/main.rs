@@ -24,9 +24,11 @@ fn add_lock()->Result<(),SetupError>{
     Ok(())
 }
 
+/// The journal, where log entries are kept
 pub struct Journal{
 
 }
+/// A single log entry of the journal
 pub struct Entry{
     
 }
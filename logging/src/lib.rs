@@ -11,6 +11,7 @@ pub fn init()-> Result<(),log::SetLoggerError>{
     todo!()
     
 }
+/// The logger used by easyinit
 #[derive(Debug)]
 pub struct Logger{
 
@@ -21,6 +22,7 @@ impl Logger{
 }
 
 
+/// The logging macros, so crates don't need to depend on `log` directly
 pub mod prelude{
     pub use log::{info,debug,error,warn,trace};
 }
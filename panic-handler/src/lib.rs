@@ -1,12 +1,19 @@
 //! Panic handler for easyinit
 //! 
-//! Unwinds the stack to show an error message
+//! Unwinds the stack to show an error message, and writes a [crash report](report)
 // use std::sync::atomic::{AtomicPtr,Ordering};
 // static PANIC_INNER: AtomicPtr<fn(&std::panic::PanicHookInfo) -> !> = AtomicPtr::new(std::ptr::null_mut());
 
+pub mod report;
+
+use report::{Context, CrashReport};
 use std::io::{stderr, stdout, Write};
+use std::sync::OnceLock;
 // use std::ffi::{OsStr, OsString};
 
+/// Provides what easyinit knows for the crash report, set with [`set_context`]
+type ContextProvider = Box<dyn Fn() -> Context + Send + Sync>;
+static CONTEXT: OnceLock<ContextProvider> = OnceLock::new();
 
 thread_local! {
     static PANIC_PROTECTION:std::cell::RefCell<u8> = const {std::cell::RefCell::new(0)}
@@ -15,6 +22,13 @@ thread_local! {
 pub fn switch_panic(){
     std::panic::set_hook(Box::new(panic_handler));
 }
+/// Sets what provides the command line, service states and report path for crash reports.
+/// 
+/// It is called from the panicking thread, so it must not wait on a lock that thread may hold.
+/// Only the first call has an effect.
+pub fn set_context(provider: impl Fn() -> Context + Send + Sync + 'static){
+    let _ = CONTEXT.set(Box::new(provider));
+}
 /// The panic handler
 #[cold]
 fn panic_handler(info: &std::panic::PanicHookInfo) {
@@ -35,53 +49,35 @@ fn panic_handler(info: &std::panic::PanicHookInfo) {
             *v += 1;
         }
     });
-    
-    // BACKTRACING NOW
-    // Currently info returns only Some varient, but stated it may return None in future
-    let plocation = info.location().expect("Location cannot be determined");
-    
-    
-    let _ = err.write_all(format!("Panic occurred at {} on line {}\n",
-                                 plocation.file(),
-                                plocation.line(),
-                            ).as_bytes());
-    
-    // let mut 
-    backtrace::trace(|frame| {
-        let ip = frame.ip();
-        let addr = frame.symbol_address();
-        backtrace::resolve_frame(frame, |sym|{
-            let name: String = format!("{}", 
-                sym.name()
-                .unwrap_or(
-                    backtrace::SymbolName::new(b"<unknown symbol>")
-                )
-            );
-    
-            let filename= match sym.filename() {
-                Some(f) => {
-                    let path_str = f.display();
-                    if let Some(l) = sym.lineno() {
-                        format!("{} at line {}",path_str,l)
-                    } else {
-                        format!("{} at <unknown line>",path_str)
-                    }
-                },
-                None => "<unknown file>".to_string(),
-            };
-            let _ = err.write_all(format!("{}",filename).as_bytes());
-        });
-        
-        
 
-        true // continue tracing
-
-    });
+    let context = CONTEXT.get().map(|provider| provider()).unwrap_or_default();
+    let path = context.path.clone();
+    let report = CrashReport::new(info, context);
 
-}
-/// When using aborting panic
-fn abort_panic(){
+    let _ = err.write_all(format!("easyinit panicked: {}\n", report.message.as_deref().unwrap_or("<no message>")).as_bytes());
+    if let Some(location) = &report.location {
+        let _ = err.write_all(format!("Panic occurred at {} on line {}\n", location.file, location.line).as_bytes());
+    }
+    for frame in &report.backtrace {
+        let function = frame.function.as_deref().unwrap_or("<unknown symbol>");
+        let file = match (&frame.file, frame.line) {
+            (Some(file), Some(line)) => format!("{file} at line {line}"),
+            (Some(file), None) => format!("{file} at <unknown line>"),
+            (None, _) => "<unknown file>".to_string(),
+        };
+        let _ = err.write_all(format!("  {function}\n    {file}\n").as_bytes());
+    }
 
+    let json = report.to_json();
+    match path {
+        Some(path) => match std::fs::write(&path, &json) {
+            Ok(()) => { let _ = err.write_all(format!("Crash report written to {}\n", path.display()).as_bytes()); }
+            Err(e) => {
+                let _ = err.write_all(format!("Failed to write crash report to {}: {e}\n{json}\n", path.display()).as_bytes());
+            }
+        },
+        None => { let _ = err.write_all(format!("{json}\n").as_bytes()); }
+    }
 }
 /// Wrapper around [`std::process::abort`] to avoid having to use [expect attribute] with [`clippy::disallowed_methods`]
 /// 
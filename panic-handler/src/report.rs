@@ -0,0 +1,205 @@
+//! The crash report written when easyinit panics
+//!
+//! The report is a single JSON object. Fields are only ever added, never renamed or removed,
+//! and [`FORMAT`] is raised when the meaning of a field changes.
+//!
+//! ```json
+//! {
+//!   "format": 1,
+//!   "signature": "3f5c0e7a9b12d4e8",
+//!   "version": "0.0.0",
+//!   "time": 1760000000,
+//!   "boot-id": "0b7e6f1c-...",
+//!   "uptime": 12.5,
+//!   "thread": "main",
+//!   "message": "called `Option::unwrap()` on a `None` value",
+//!   "location": { "file": "src/boot.rs", "line": 42, "column": 10 },
+//!   "backtrace": [{ "function": "easyinit::boot::target", "file": "src/boot.rs", "line": 42, "address": "0x55d0c0a1b2c3" }],
+//!   "cmdline": [{ "name": "easyinit.loglevel", "value": "warn", "source": "default" }],
+//!   "init-args": [],
+//!   "services": { "sshd": "active" }
+//! }
+//! ```
+use config::Setting;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Version of the report layout
+pub const FORMAT: u32 = 1;
+
+/// Number of frames the [`CrashReport::signature`] is made from
+const SIGNATURE_FRAMES: usize = 8;
+
+/// Frames from these are part of panicking itself, so they are left out of the signature
+const IGNORED_FRAMES: &[&str] = &[
+    "std::",
+    "core::",
+    "alloc::",
+    "backtrace::",
+    "easyinit_panic_handler::",
+    "rust_begin_unwind",
+    "__rust",
+    "__libc",
+    "_start",
+];
+
+/// A crash report
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CrashReport {
+    /// Version of the layout, [`FORMAT`]
+    pub format: u32,
+    /// Identifies the crash, crashes with the same cause have the same signature
+    ///
+    /// A hash of the panic location and the easyinit functions on the stack, so it stays the
+    /// same across boots and machines running the same build.
+    pub signature: String,
+    /// Version of easyinit
+    pub version: &'static str,
+    /// When the crash happened, in seconds since the Unix epoch
+    pub time: u64,
+    /// `/proc/sys/kernel/random/boot_id`
+    pub boot_id: Option<String>,
+    /// Seconds since boot, from `/proc/uptime`
+    pub uptime: Option<f64>,
+    /// Name of the thread that panicked
+    pub thread: Option<String>,
+    /// The panic message
+    pub message: Option<String>,
+    /// Where the panic happened
+    pub location: Option<Location>,
+    /// The stack, innermost frame first
+    pub backtrace: Vec<Frame>,
+    /// Every parameter from the kernel command line, with where it came from
+    pub cmdline: Vec<Setting>,
+    /// Arguments after `--` on the kernel command line
+    pub init_args: Vec<String>,
+    /// State of every service
+    pub services: BTreeMap<String, String>,
+}
+
+/// A place in the source code
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Location {
+    /// The source file
+    pub file: String,
+    /// Line, starting at 1
+    pub line: u32,
+    /// Column, starting at 1
+    pub column: u32,
+}
+
+/// A frame of the backtrace, resolved to a function and source location if possible
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Frame {
+    /// The demangled function name
+    pub function: Option<String>,
+    /// The source file
+    pub file: Option<String>,
+    /// Line, starting at 1
+    pub line: Option<u32>,
+    /// The instruction pointer, changes between boots with address space randomization
+    pub address: String,
+}
+
+/// What easyinit knows at the time of the crash, from the provider set with [`set_context`]
+///
+/// [`set_context`]: crate::set_context
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    /// Where the report is written, it is only printed if this is `None`
+    pub path: Option<PathBuf>,
+    /// Every parameter from the kernel command line, from [`config::Cmdline::settings`]
+    pub cmdline: Vec<Setting>,
+    /// [`config::Cmdline::init_args`]
+    pub init_args: Vec<String>,
+    /// State of every service
+    pub services: BTreeMap<String, String>,
+}
+
+impl CrashReport {
+    /// Collects a report for a panic
+    pub fn new(info: &std::panic::PanicHookInfo, context: Context) -> Self {
+        let location = info.location().map(|l| Location {
+            file: l.file().to_string(),
+            line: l.line(),
+            column: l.column(),
+        });
+        let backtrace = backtrace();
+        CrashReport {
+            format: FORMAT,
+            signature: signature(location.as_ref(), &backtrace),
+            version: env!("CARGO_PKG_VERSION"),
+            time: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            boot_id: std::fs::read_to_string("/proc/sys/kernel/random/boot_id")
+                .ok()
+                .map(|id| id.trim().to_string()),
+            uptime: std::fs::read_to_string("/proc/uptime")
+                .ok()
+                .and_then(|u| u.split_whitespace().next()?.parse().ok()),
+            thread: std::thread::current().name().map(str::to_string),
+            message: crate::payload_as_str(info).map(str::to_string),
+            location,
+            backtrace,
+            cmdline: context.cmdline,
+            init_args: context.init_args,
+            services: context.services,
+        }
+    }
+
+    /// The report as pretty printed JSON
+    pub fn to_json(&self) -> String {
+        // Serializing only fails for maps with keys that aren't strings, which there are none of
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+/// Resolves the current stack
+fn backtrace() -> Vec<Frame> {
+    let mut frames = Vec::new();
+    backtrace::trace(|frame| {
+        let address = format!("{:#x}", frame.ip() as usize);
+        let mut resolved = false;
+        backtrace::resolve_frame(frame, |symbol| {
+            resolved = true;
+            frames.push(Frame {
+                function: symbol.name().map(|n| format!("{n:#}")),
+                file: symbol.filename().map(|f| f.display().to_string()),
+                line: symbol.lineno(),
+                address: address.clone(),
+            });
+        });
+        if !resolved {
+            frames.push(Frame { function: None, file: None, line: None, address });
+        }
+        true // continue tracing
+    });
+    frames
+}
+
+/// A FNV-1a hash of the location and the first easyinit functions on the stack, as hex
+///
+/// FNV is used instead of [`std::hash::DefaultHasher`], as that may change between Rust versions.
+fn signature(location: Option<&Location>, backtrace: &[Frame]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut write = |bytes: &[u8]| {
+        for byte in bytes.iter().chain([&0]) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    };
+    if let Some(location) = location {
+        write(location.file.as_bytes());
+        write(&location.line.to_le_bytes());
+    }
+    backtrace
+        .iter()
+        .filter_map(|frame| frame.function.as_deref())
+        .filter(|function| !IGNORED_FRAMES.iter().any(|prefix| function.starts_with(prefix)))
+        .take(SIGNATURE_FRAMES)
+        .for_each(|function| write(function.as_bytes()));
+    format!("{hash:016x}")
+}
@@ -0,0 +1,257 @@
+//! Decides what gets started, based on the target from the kernel command line
+use crate::control;
+use logging::prelude::*;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use system::transaction::Transaction;
+
+/// Name of the service running the `easyinit.run` command
+const RUN_SERVICE: &str = "kernel-command-line-run";
+
+/// How often the state of a oneshot service is checked while the boot waits for it
+const ONESHOT_POLL: Duration = Duration::from_millis(50);
+
+/// Shells tried in order for the rescue and emergency targets
+///
+/// `sulogin` asks for the root password first, so it is preferred over a plain shell.
+const SHELLS: &[&str] = &["/sbin/sulogin", "/usr/sbin/sulogin", "/bin/sh"];
+
+/// Only starts a root shell on the console, nothing but `/proc` is mounted
+pub fn emergency()->!{
+    console_message("Entering emergency mode, only /proc has been mounted and nothing has been started.");
+    shell_loop()
+}
+
+/// Mounts the local filesystems, then starts a root shell on the console
+pub fn rescue(state:&control::Shared)->!{
+    mount_needed_fs(state);
+    prepare_root(state);
+    system::startup::mount_local_filesystems(system::fstab::PATH.as_ref());
+    rotate_crash_reports(state);
+    load_services(state);
+    if let Err(e) = control::spawn(state.clone()){
+        error!("Failed to start the api socket, easyctl will not work: {e}");
+    }
+    console_message("Entering rescue mode, local filesystems are mounted but no services are started.");
+    shell_loop()
+}
+
+/// Boots the named target, the normal boot
+pub fn target(state:&control::Shared, name:&str)->!{
+    mount_needed_fs(state);
+    prepare_root(state);
+    let failed = system::startup::mount_local_filesystems(system::fstab::PATH.as_ref());
+    if !failed.is_empty(){
+        let mount_points: Vec<_> = failed.iter().map(|(path, _)| path.display().to_string()).collect();
+        console_message(&format!("Failed to mount {}, entering emergency mode.", mount_points.join(", ")));
+        shell_loop()
+    }
+    rotate_crash_reports(state);
+    load_services(state);
+    if let Err(e) = control::spawn(state.clone()){
+        error!("Failed to start the api socket, easyctl will not work: {e}");
+    }
+    info!("Booting target {name}");
+    let transaction = plan(state, name);
+    for (service, reason) in &transaction.skipped{
+        warn!("Not starting {service}: {reason}");
+    }
+    for service in &transaction.order{
+        start_and_wait(state, service);
+    }
+    info!("Reached target {name}");
+    run_command(state);
+    loop{
+        std::thread::park();
+    }
+}
+
+/// Starts the target of an event the kernel sent a signal for, view [`config::sysv`]
+///
+/// Ctrl+Alt+Del reboots when nothing is wanted by its target, like it does without easyinit.
+pub fn event(state:&control::Shared, signal:i32){
+    let target = match signal{
+        nix::libc::SIGINT => config::sysv::CTRL_ALT_DEL_TARGET,
+        nix::libc::SIGPWR => config::sysv::power_target(power_status()),
+        nix::libc::SIGWINCH => config::sysv::KBREQUEST_TARGET,
+        _ => return,
+    };
+    let transaction = {
+        let state = state.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        Transaction::plan(&state.manager, target, &[], state.manager.masked())
+    };
+    if transaction.order.is_empty(){
+        if signal == nix::libc::SIGINT{
+            console_message("Ctrl+Alt+Del was pressed, rebooting.");
+            crate::util::shutdown(crate::util::ShutdownReason::Reboot)
+        }
+        warn!("Nothing is wanted by {target}, ignoring the event");
+        return;
+    }
+    info!("Starting target {target}");
+    for service in &transaction.order{
+        start_and_wait(state, service);
+    }
+}
+
+/// First letter of the power status a UPS daemon wrote, `None` if there is none
+fn power_status()->Option<char>{
+    config::sysv::POWER_STATUS.iter().find_map(|path| std::fs::read_to_string(path).ok()).and_then(|status| status.chars().next())
+}
+
+/// Works out what the target starts, with the overrides from the kernel command line
+fn plan(state:&control::Shared, name:&str)->Transaction{
+    let mut state = state.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+    let masked = state.cmdline.masked().to_vec();
+    if !masked.is_empty(){
+        info!("Masked for this boot: {}", masked.join(", "));
+    }
+    state.manager.mask(masked);
+    let wants = state.cmdline.wants().to_vec();
+    Transaction::plan(&state.manager, name, &wants, state.manager.masked())
+}
+
+/// Starts a service, and waits for it to finish if it is a oneshot, so what is ordered after it sees its work done
+fn start_and_wait(state:&control::Shared, name:&str){
+    if let Err(e) = control::start(state, name){
+        error!("Failed to start {name}: {e}");
+        return;
+    }
+    loop{
+        {
+            let state = state.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+            match state.manager.service(name){
+                Some(service) if service.definition.service.kind == config::service::ServiceKind::Oneshot && service.pid.is_some() => {},
+                _ => return,
+            }
+        }
+        std::thread::sleep(ONESHOT_POLL);
+    }
+}
+
+/// Runs the `easyinit.run` command from the kernel command line, as a oneshot service
+fn run_command(state:&control::Shared){
+    let Some(command) = state.lock().unwrap_or_else(std::sync::PoisonError::into_inner).cmdline.run() else{
+        return;
+    };
+    info!("Running `{}` from the kernel command line", command.0.join(" "));
+    let mut definition = config::service::ServiceDefinition{
+        name: RUN_SERVICE.to_string(),
+        description: Some("easyinit.run from the kernel command line".to_string()),
+        ..Default::default()
+    };
+    definition.service.kind = config::service::ServiceKind::Oneshot;
+    definition.service.exec = Some(command);
+    state.lock().unwrap_or_else(std::sync::PoisonError::into_inner).manager.add_transient(definition);
+    start_and_wait(state, RUN_SERVICE);
+}
+
+/// Mounts the API filesystems, or goes to the emergency shell if a required one fails
+fn mount_needed_fs(state:&control::Shared){
+    let policies = state.lock().unwrap_or_else(std::sync::PoisonError::into_inner).cmdline.nonempty_mountpoint();
+    if let Err(e) = system::startup::mount_needed_fs(&policies){
+        error!("{e}");
+        console_message(&format!("{e}, entering emergency mode."));
+        shell_loop()
+    }
+}
+
+/// Checks the root filesystem and remounts it, rebooting or going to the emergency shell if the check says so
+fn prepare_root(state:&control::Shared){
+    let settings = {
+        let state = state.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        system::root::Settings{
+            mode: state.cmdline.fsck_mode(),
+            repair: state.cmdline.fsck_repair(),
+            read_only: state.cmdline.root_read_only(),
+        }
+    };
+    match system::root::check_and_remount(&settings, system::fstab::PATH.as_ref()){
+        system::root::Outcome::Ready => {},
+        system::root::Outcome::Reboot => {
+            console_message("Errors on the root filesystem were corrected, rebooting.");
+            crate::util::shutdown(crate::util::ShutdownReason::Reboot)
+        },
+        system::root::Outcome::Emergency(reason) => {
+            error!("{reason}");
+            console_message(&format!("{reason}, entering emergency mode."));
+            shell_loop()
+        },
+    }
+}
+
+/// Runs the generators and loads every service definition for the first time
+fn load_services(state:&control::Shared){
+    let timeout = state.lock().unwrap_or_else(std::sync::PoisonError::into_inner).cmdline.generator_timeout();
+    control::run_generators(timeout);
+    let mut state = state.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+    let diff = state.manager.reload();
+    info!("Loaded {} service definitions, {} failed to load", diff.added.len(), diff.errors.len());
+}
+
+/// Applies the crash report retention limits, once the directory is mounted
+fn rotate_crash_reports(state:&control::Shared){
+    let (file, retention) = {
+        let state = state.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        (state.cmdline.crash_file(), state.cmdline.crash_retention())
+    };
+    match file.rotate(&retention){
+        Ok(rotated) => {
+            for path in rotated.compressed { info!("Compressed crash report {}", path.display()) }
+            for path in rotated.removed { info!("Removed old crash report {}", path.display()) }
+        },
+        Err(e) => warn!("Failed to rotate crash reports in {file:?}: {e}"),
+    }
+}
+
+/// Starts a root shell on the console, starting it again whenever it exits
+fn shell_loop()->!{
+    let Some(shell) = SHELLS.iter().copied().find(|s| Path::new(s).exists()) else{
+        panic!("No shell found for the console, tried {SHELLS:?}");
+    };
+    loop{
+        match spawn_console_shell(shell).and_then(|mut c| c.wait()){
+            Ok(status) => info!("Console shell exited with {status}, starting it again"),
+            Err(e) => {
+                error!("Failed to start {shell} on the console: {e}");
+                // Prevents spinning when the shell cannot be started at all
+                std::thread::sleep(std::time::Duration::from_secs(5));
+            }
+        }
+    }
+}
+
+fn spawn_console_shell(shell:&str)->std::io::Result<std::process::Child>{
+    let console = std::fs::OpenOptions::new().read(true).write(true).open("/dev/console")?;
+    let mut command = Command::new(shell);
+    command
+        .stdin(Stdio::from(console.try_clone()?))
+        .stdout(Stdio::from(console.try_clone()?))
+        .stderr(Stdio::from(console))
+        .env_clear()
+        .env("HOME", "/root")
+        .env("PATH", "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin")
+        .env("TERM", "linux");
+    // SAFETY: setsid and ioctl are async-signal-safe, and nothing is allocated in the closure
+    unsafe {
+        command.pre_exec(||{
+            // A new session with the console as the controlling terminal, so job control works
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 1) == -1{
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    command.spawn()
+}
+
+/// Writes directly to the console, the logger may not be set up for it
+fn console_message(message:&str){
+    use std::io::Write;
+    if let Ok(mut console) = std::fs::OpenOptions::new().write(true).open("/dev/console"){
+        let _ = writeln!(console, "easyinit: {message}");
+    }
+    warn!("{message}");
+}
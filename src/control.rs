@@ -0,0 +1,196 @@
+//! Serves the api socket that `easyctl` talks to
+//!
+//! Every connection gets its own thread, requests are handled one at a time per connection.
+use api::{ReloadDiff, Request, Response};
+use logging::prelude::*;
+use std::io::BufReader;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// How long to wait before restarting a service, so a service that fails right away does not spin
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Everything the api can see and change
+#[derive(Debug)]
+pub struct State{
+    /// The parsed kernel command line
+    pub cmdline: config::Cmdline,
+    /// The service definitions and their state
+    pub manager: system::manager::Manager,
+}
+
+/// [`State`] shared between the main thread and the api threads
+pub type Shared = Arc<Mutex<State>>;
+
+/// Starts listening on [`api::SOCKET_PATH`] in a background thread
+pub fn spawn(state:Shared)->std::io::Result<()>{
+    let path = Path::new(api::SOCKET_PATH);
+    if let Some(dir) = path.parent(){
+        std::fs::create_dir_all(dir)?;
+    }
+    // A socket left over from a previous run makes bind fail
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    // Only root may control init
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+
+    std::thread::Builder::new().name("api".to_string()).spawn(move ||{
+        for stream in listener.incoming(){
+            match stream{
+                Ok(stream) => {
+                    let state = state.clone();
+                    if let Err(e) = std::thread::Builder::new().name("api-client".to_string()).spawn(move || serve(stream, &state)){
+                        error!("Failed to spawn thread for api connection: {e}");
+                    }
+                },
+                Err(e) => error!("Failed to accept api connection: {e}"),
+            }
+        }
+    })?;
+    Ok(())
+}
+
+/// Handles requests on a single connection until it is closed
+fn serve(stream:UnixStream, state:&Shared){
+    let mut writer = match stream.try_clone(){
+        Ok(w) => w,
+        Err(e) => {
+            error!("Failed to clone api connection: {e}");
+            return;
+        }
+    };
+    let mut reader = BufReader::new(stream);
+    loop{
+        let response = match api::read_message::<Request>(&mut reader){
+            Ok(Some(request)) => handle(request, state),
+            Ok(None) => return,
+            Err(e) => {
+                debug!("Bad api request: {e}");
+                // The connection is in a unknown state, so it gets closed after the error
+                let _ = api::write_message(&mut writer, &Response::Error(e.to_string()));
+                return;
+            }
+        };
+        if let Err(e) = api::write_message(&mut writer, &response){
+            debug!("Failed to send api response: {e}");
+            return;
+        }
+    }
+}
+
+fn handle(request:Request, shared:&Shared)->Response{
+    let mut state = shared.lock().unwrap_or_else(PoisonError::into_inner);
+    match request{
+        Request::Settings => Response::Settings(state.cmdline.settings()),
+        Request::SetSetting { name, value } => {
+            match state.cmdline.set(&name, &value, config::Source::Runtime){
+                Ok(()) => {
+                    log::set_max_level(state.cmdline.loglevel());
+                    info!("`{name}` set to `{value}` at runtime");
+                    Response::Ok
+                },
+                Err(e) => Response::Error(e),
+            }
+        },
+        Request::Reload => {
+            drop(state);
+            Response::Reloaded(reload(shared))
+        },
+        Request::List => Response::Services(state.manager.list()),
+        Request::Show { name } => match state.manager.definition(&name){
+            Ok(definition) => Response::Definition(Box::new(definition)),
+            Err(e) => Response::Error(e.to_string()),
+        },
+        Request::Start { name } => {
+            drop(state);
+            match start(shared, &name){
+                Ok(()) => Response::Ok,
+                Err(e) => Response::Error(e.to_string()),
+            }
+        },
+        _ => Response::Error("Unsupported request".to_string()),
+    }
+}
+
+/// Reads the global configuration and every service definition again, used for `SIGHUP` and the api
+/// 
+/// Changed services are not restarted, they are marked as needing a restart instead.
+pub fn reload(state:&Shared)->ReloadDiff{
+    info!("Reloading configuration");
+    // Generators run without the lock, so the api keeps answering while they do
+    let timeout = state.lock().unwrap_or_else(PoisonError::into_inner).cmdline.generator_timeout();
+    let failed = run_generators(timeout);
+    let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut diff = state.manager.reload();
+    diff.errors.extend(failed);
+    if let Err(e) = state.cmdline.reload_config(config::global::PATH.as_ref()){
+        warn!("Keeping the previous global configuration: {e}");
+        diff.errors.push(e.to_string());
+    }
+    log::set_max_level(state.cmdline.loglevel());
+    for name in &diff.added { info!("Service {name} added"); }
+    for name in &diff.removed { info!("Service {name} removed"); }
+    for name in &diff.changed { info!("Service {name} changed"); }
+    for name in &diff.needs_restart{
+        if diff.removed.contains(name){
+            warn!("Service {name} was removed, it keeps running until it stops");
+        } else{
+            warn!("Service {name} is running with its old definition until it is restarted");
+        }
+    }
+    diff
+}
+
+/// Runs the generators, returning why each one that failed did
+pub fn run_generators(timeout:std::time::Duration)->Vec<String>{
+    match system::generator::Generators::system(timeout).run(){
+        Ok(report) => report.failed.into_iter().map(|(name, e)| format!("Generator {name} failed: {e}")).collect(),
+        Err(e) => {
+            error!("Not running generators: {e}");
+            vec![e.to_string()]
+        },
+    }
+}
+
+/// Starts a service and waits for its process in a background thread, restarting it as its policy says
+pub fn start(shared:&Shared, name:&str)->Result<(), system::exec::StartError>{
+    let mut state = shared.lock().unwrap_or_else(PoisonError::into_inner);
+    let defaults = state.cmdline.service_defaults();
+    let Some(mut child) = state.manager.start(name, &defaults)? else{
+        return Ok(());
+    };
+    let pid = child.id();
+    info!("Started {name} as {pid}");
+    let (shared, service) = (shared.clone(), name.to_string());
+    let waiter = std::thread::Builder::new().name(format!("wait-{pid}")).spawn(move ||{
+        let name = service;
+        let status = match child.wait(){
+            Ok(status) => status,
+            Err(e) => {
+                error!("Failed to wait for {name} ({pid}): {e}");
+                return;
+            }
+        };
+        let restart = {
+            let mut state = shared.lock().unwrap_or_else(PoisonError::into_inner);
+            let defaults = state.cmdline.service_defaults();
+            state.manager.exited(&name, pid, status, &defaults)
+        };
+        if status.success() { info!("{name} exited"); } else { warn!("{name} exited with {status}"); }
+        if restart{
+            std::thread::sleep(RESTART_DELAY);
+            info!("Restarting {name}");
+            if let Err(e) = start(&shared, &name){
+                error!("Failed to restart {name}: {e}");
+            }
+        }
+    });
+    if let Err(e) = waiter{
+        // The process still runs, but its exit will not be noticed
+        error!("Failed to spawn thread waiting for {name}: {e}");
+    }
+    Ok(())
+}
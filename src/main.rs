@@ -1,4 +1,9 @@
 pub mod util;
+mod boot;
+mod control;
+
+use config::Target;
+use std::sync::{Arc, Mutex};
 
 
 fn main() -> ! {
@@ -10,11 +15,65 @@ fn main() -> ! {
     // SAFETY: No logging implementation is called previously
     unsafe { logging::init().unwrap_unchecked() }
 
-    todo!()
+    // Until the command line is parsed the default level is used, so its warnings are not dropped
+    log::set_max_level(config::Cmdline::default().loglevel());
+    // Without an initramfs nothing is mounted yet, the command line is read from /proc
+    let proc_error = system::startup::mount_proc().err();
+    // If /proc could not be mounted the boot goes on with the defaults
+    let (mut cmdline, cmdline_error) = match config::Cmdline::new(){
+        Ok(cmdline) => (cmdline, None),
+        Err(e) => (config::Cmdline::default(), Some(e)),
+    };
+    if let Err(e) = cmdline.read_config(config::global::PATH.as_ref()){
+        log::error!("Failed to read the global configuration, using the defaults: {e}");
+    }
+    log::set_max_level(cmdline.loglevel());
+    if let Some(e) = proc_error{
+        log::error!("Failed to mount /proc before reading the kernel command line: {e}");
+    }
+    if let Some(e) = cmdline_error{
+        log::error!("Failed to read the kernel command line from /proc/cmdline, using the defaults: {e}");
+    }
+    let target = cmdline.target();
+
+    let startup_crash_file = cmdline.crash_file();
+    let state = Arc::new(Mutex::new(control::State { cmdline, manager: system::manager::Manager::system() }));
+    set_crash_context(state.clone(), startup_crash_file);
+    let reload_state = state.clone();
+    if let Err(e) = utils::signals::on_sighup(move ||{ control::reload(&reload_state); }){
+        log::error!("Failed to handle SIGHUP, reloading only works through easyctl: {e}");
+    }
+    let event_state = state.clone();
+    let events = [nix::libc::SIGINT, nix::libc::SIGPWR, nix::libc::SIGWINCH];
+    match utils::signals::on_signals(&events, move |signal| boot::event(&event_state, signal)){
+        // The kernel then sends SIGINT on Ctrl+Alt+Del instead of rebooting right away
+        Ok(()) => if let Err(e) = nix::sys::reboot::set_cad_enabled(false){
+            log::warn!("Failed to handle Ctrl+Alt+Del, the kernel reboots right away: {e}");
+        },
+        Err(e) => log::error!("Failed to handle SIGINT, SIGPWR and SIGWINCH, inittab events will not work: {e}"),
+    }
+    match target{
+        Target::Emergency => boot::emergency(),
+        Target::Rescue => boot::rescue(&state),
+        Target::Default => boot::target(&state, "default"),
+        Target::Named(name) => boot::target(&state, &name),
+    }
     
 }
 
-
-
-
-
+/// Gives the panic handler what goes in the crash report
+///
+/// The panicking thread may hold the state lock, then only the crash file known at startup is used.
+fn set_crash_context(state:control::Shared, startup_crash_file:config::CrashFile){
+    panic_handler::set_context(move ||{
+        let Ok(state) = state.try_lock() else{
+            return panic_handler::report::Context { path: startup_crash_file.path().ok(), ..Default::default() };
+        };
+        panic_handler::report::Context {
+            path: state.cmdline.crash_report_path().ok(),
+            cmdline: state.cmdline.settings(),
+            init_args: state.cmdline.init_args.clone(),
+            services: state.manager.services().iter().map(|(name, service)| (name.clone(), service.state.to_string())).collect(),
+        }
+    });
+}
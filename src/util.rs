@@ -55,10 +55,10 @@ pub fn shutdown(reason:ShutdownReason)->!{
                 ){
                     Ok(_) => {
 
-                        shutdown_branch(reason);
+                        shutdown_branch(&reason);
                         SHUTING_DOWN.store(true, Ordering::Release);
-                        // We should now actually tell the kernel to shutdown
-                        todo!()
+                        // SAFETY: The branch stopped every process and synced the filesystems
+                        unsafe { kernel_shutdown(&reason) }
 
                     }
                     Err(_) => {
@@ -72,11 +72,15 @@ pub fn shutdown(reason:ShutdownReason)->!{
 
 }
 
-fn shutdown_branch(reason:ShutdownReason){
+fn shutdown_branch(reason:&ShutdownReason){
     match reason{
         ShutdownReason::Reboot => {
-            // Reboot the system
-            todo!()
+            // Nothing is started this early that needs more than a second to stop
+            sysrq(SysRqCommand::TerminateAllProcesses);
+            std::thread::sleep(std::time::Duration::from_secs(1));
+            sysrq(SysRqCommand::KillAllProcesses);
+            nix::unistd::sync();
+            sysrq(SysRqCommand::RemountReadOnly);
         }
         ShutdownReason::User => {
             // Gracefully shutdown the system
@@ -102,10 +106,12 @@ fn shutdown_branch(reason:ShutdownReason){
 enum SysRqCommand{
     Reboot,
     /// Immediately sync all filesystems
+    #[expect(dead_code, reason = "syncing goes through sync(2), which waits for it")]
     Sync,
     /// Immediately remount all filesystems as read-only
     RemountReadOnly,
     /// Immediately trigger a kernel panic
+    #[expect(dead_code, reason = "for the shutdown reasons that are not implemented yet")]
     Panic,
     /// Immediately shutdown the system, does not sync
     Shutdown,
@@ -138,12 +144,23 @@ fn sysrq(cmd:SysRqCommand){
 
 /// Tells the kernel to shutdown the system
 /// 
+/// Reboots for [`ShutdownReason::Reboot`] and [`ShutdownReason::KExec`], otherwise powers off.
+/// If the kernel refuses, SysRq is used instead.
+/// 
 /// # Safety
 /// This does not sync files, umount filesystems, or anything else.
 /// It just tells the kernel to shutdown. Use with caution.
-unsafe fn kernel_shutdown()->!{
-
-    use std::io::prelude::*;
-    // std::io::
-    todo!()
-}
\ No newline at end of file
+unsafe fn kernel_shutdown(reason:&ShutdownReason)->!{
+    use nix::sys::reboot::{RebootMode,reboot};
+    let (mode, fallback) = match reason{
+        ShutdownReason::Reboot => (RebootMode::RB_AUTOBOOT, SysRqCommand::Reboot),
+        ShutdownReason::KExec => (RebootMode::RB_KEXEC, SysRqCommand::Reboot),
+        _ => (RebootMode::RB_POWER_OFF, SysRqCommand::Shutdown),
+    };
+    let Err(e) = reboot(mode);
+    log::error!("The kernel refused to {mode:?}, using SysRq: {e}");
+    sysrq(fallback);
+    loop{
+        std::thread::park();
+    }
+}
@@ -0,0 +1,243 @@
+//! Checks the [conditions and assertions](config::condition) of a service
+use crate::mountinfo;
+use crate::virt::Detector;
+use config::condition::{Conditions, negation};
+use config::service::ServiceDefinition;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Why a service was not started
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CheckFailure {
+    /// An assertion failed, so the service failed, instead of a condition that skipped it
+    pub assertion: bool,
+    /// The check that failed, like `path-exists /etc/example.conf`
+    pub reason: String,
+}
+
+impl std::fmt::Display for CheckFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.assertion { "assertion" } else { "condition" };
+        write!(f, "{kind} failed: {}", self.reason)
+    }
+}
+
+/// Checks a single path under the root
+type PathCheck = fn(&Host, &Path) -> bool;
+
+/// The system the checks look at, with every file read under a root so they can be tested
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Host {
+    root: PathBuf,
+    detector: Detector,
+}
+
+impl Host {
+    /// The running system
+    pub fn system() -> Self {
+        Host { root: PathBuf::from("/"), detector: Detector::system() }
+    }
+
+    /// Files under a different root
+    pub fn with_root(root: &Path) -> Self {
+        Host { root: root.to_path_buf(), detector: Detector::with_root(root) }
+    }
+
+    /// Checks the assertions and then the conditions of a service
+    pub fn check(&self, definition: &ServiceDefinition) -> Result<(), CheckFailure> {
+        self.holds(&definition.assertions).map_err(|reason| CheckFailure { assertion: true, reason })?;
+        self.holds(&definition.conditions).map_err(|reason| CheckFailure { assertion: false, reason })
+    }
+
+    /// The first check that does not hold
+    fn holds(&self, conditions: &Conditions) -> Result<(), String> {
+        if conditions.is_empty() {
+            return Ok(());
+        }
+        let paths: [(&str, &[String], PathCheck); 4] = [
+            ("path-exists", &conditions.path_exists, |_, path| path.exists()),
+            ("path-is-directory", &conditions.path_is_directory, |_, path| path.is_dir()),
+            ("path-is-mountpoint", &conditions.path_is_mountpoint, Host::is_mountpoint),
+            ("file-not-empty", &conditions.file_not_empty, |_, path| {
+                path.metadata().is_ok_and(|m| m.is_file() && m.len() > 0)
+            }),
+        ];
+        for (key, entries, check) in paths {
+            for entry in entries {
+                let (expected, path) = negation(entry);
+                if check(self, &self.path(path)) != expected {
+                    return Err(format!("{key} {entry}"));
+                }
+            }
+        }
+        if !conditions.kernel_command_line.is_empty() {
+            let cmdline = std::fs::read_to_string(self.path("/proc/cmdline")).unwrap_or_default();
+            for entry in &conditions.kernel_command_line {
+                let (expected, parameter) = negation(entry);
+                if kernel_parameter_set(&cmdline, parameter) != expected {
+                    return Err(format!("kernel-command-line {entry}"));
+                }
+            }
+        }
+        if let Some(entry) = &conditions.architecture {
+            let (expected, name) = negation(entry);
+            if (name == architecture() || name == std::env::consts::ARCH) != expected {
+                return Err(format!("architecture {entry}, this is {}", architecture()));
+            }
+        }
+        if let Some(entry) = &conditions.virtualization {
+            let (expected, value) = negation(entry);
+            let found = self.detector.detect();
+            if found.matches(value) != expected {
+                return Err(format!("virtualization {entry}, this is {found}"));
+            }
+        }
+        if let Some(expected) = conditions.first_boot
+            && self.is_first_boot() != expected
+        {
+            return Err(format!("first-boot {expected}"));
+        }
+        if let Some(threshold) = &conditions.memory {
+            let memory = self.memory().ok_or("memory, the total memory is unknown")?;
+            if !threshold.holds(memory) {
+                return Err(format!("memory {threshold}, there are {memory} bytes"));
+            }
+        }
+        if let Some(threshold) = &conditions.cpus {
+            let cpus = self.cpus().ok_or("cpus, the number of CPUs is unknown")?;
+            if !threshold.holds(cpus) {
+                return Err(format!("cpus {threshold}, there are {cpus}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// An absolute path under the root
+    fn path(&self, path: &str) -> PathBuf {
+        self.root.join(path.trim_start_matches('/'))
+    }
+
+    fn is_mountpoint(&self, path: &Path) -> bool {
+        let mounts = mountinfo::read(&self.path(mountinfo::PATH)).unwrap_or_default();
+        // Mount points in the table are relative to the real root
+        let path = Path::new("/").join(path.strip_prefix(&self.root).unwrap_or(path));
+        mountinfo::find(&mounts, &path).is_some()
+    }
+
+    /// `/etc/machine-id` is written on the first boot, until then it is missing, empty or `uninitialized`
+    fn is_first_boot(&self) -> bool {
+        match std::fs::read_to_string(self.path("/etc/machine-id")) {
+            Ok(id) => matches!(id.trim(), "" | "uninitialized"),
+            Err(_) => true,
+        }
+    }
+
+    /// Total memory in bytes
+    fn memory(&self) -> Option<u64> {
+        let meminfo = std::fs::read_to_string(self.path("/proc/meminfo")).ok()?;
+        let line = meminfo.lines().find(|line| line.starts_with("MemTotal:"))?;
+        let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+        kib.checked_mul(1024)
+    }
+
+    /// Number of CPUs easyinit may run on, from its affinity mask
+    ///
+    /// The mask is limited by the cpuset it runs in. Without one the online CPUs are counted.
+    fn cpus(&self) -> Option<u64> {
+        let status = std::fs::read_to_string(self.path("/proc/self/status")).unwrap_or_default();
+        match status.lines().find_map(|line| line.strip_prefix("Cpus_allowed_list:")) {
+            Some(allowed) => count_cpus(allowed),
+            None => count_cpus(&std::fs::read_to_string(self.path("/sys/devices/system/cpu/online")).ok()?),
+        }
+    }
+}
+
+/// Number of CPUs in a list like `0-3,6`
+fn count_cpus(list: &str) -> Option<u64> {
+    list.trim().split(',').try_fold(0, |count, range| {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+        Some(count + end.checked_sub(start)? + 1)
+    })
+}
+
+/// If `parameter` is on the command line, `name` matches any value and `name=value` only that one
+///
+/// The command line is split like [`config::Cmdline`] does, so quoted values can have spaces.
+fn kernel_parameter_set(cmdline: &str, parameter: &str) -> bool {
+    let (name, value) = match parameter.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (parameter, None),
+    };
+    config::tokenizer::tokenize(cmdline)
+        .params
+        .iter()
+        .any(|param| param.key == name && (value.is_none() || param.value.as_deref() == value))
+}
+
+/// The architecture with the names used by conditions, like `x86-64`
+pub fn architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "x86-64",
+        "aarch64" => "arm64",
+        "powerpc64" if cfg!(target_endian = "little") => "ppc64le",
+        "powerpc64" => "ppc64",
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::condition::Threshold;
+    use utils::testing::TempDir;
+
+
+    #[test]
+    fn kernel_parameters() {
+        let cmdline = "root=/dev/sda1 quiet foo=\"a b\" -- bar";
+        assert!(kernel_parameter_set(cmdline, "quiet"));
+        assert!(kernel_parameter_set(cmdline, "root"));
+        assert!(kernel_parameter_set(cmdline, "foo=a b"));
+        assert!(!kernel_parameter_set(cmdline, "foo=a"));
+        assert!(!kernel_parameter_set(cmdline, "quiet=1"));
+        // Arguments for init are not parameters
+        assert!(!kernel_parameter_set(cmdline, "bar"));
+    }
+
+    #[test]
+    fn cpus_from_the_affinity_mask() {
+        let root = TempDir::new("condition-cpus");
+        root.write("sys/devices/system/cpu/online", "0-7\n");
+        let host = Host::with_root(root.path());
+        assert_eq!(host.cpus(), Some(8));
+        root.write("proc/self/status", "Name:\teasyinit\nCpus_allowed:\t43\nCpus_allowed_list:\t0-1,6\n");
+        assert_eq!(host.cpus(), Some(3));
+    }
+
+    #[test]
+    fn conditions_and_assertions() {
+        let root = TempDir::new("condition-check");
+        root.write("proc/cmdline", "console=ttyS0 example.debug\n");
+        root.write("etc/machine-id", "uninitialized\n");
+        root.write("etc/example.conf", "");
+        root.write("proc/meminfo", "MemTotal:        2048000 kB\n");
+        let host = Host::with_root(root.path());
+
+        let mut definition = ServiceDefinition::default();
+        definition.conditions.kernel_command_line = vec!["example.debug".to_string(), "!console=tty0".to_string()];
+        definition.conditions.path_exists = vec!["/etc/example.conf".to_string()];
+        definition.conditions.first_boot = Some(true);
+        definition.conditions.memory = Some(Threshold::try_from(">=1G".to_string()).unwrap());
+        assert_eq!(host.check(&definition), Ok(()));
+
+        definition.conditions.file_not_empty = vec!["/etc/example.conf".to_string()];
+        let failure = host.check(&definition).unwrap_err();
+        assert!(!failure.assertion);
+        assert_eq!(failure.reason, "file-not-empty /etc/example.conf");
+
+        definition.assertions.path_is_directory = vec!["/etc".to_string(), "/missing".to_string()];
+        assert_eq!(host.check(&definition), Err(CheckFailure { assertion: true, reason: "path-is-directory /missing".to_string() }));
+    }
+}
@@ -0,0 +1,95 @@
+//! Starts the process of a service
+use config::environment::EnvironmentError;
+use config::service::{ServiceDefaults, ServiceDefinition};
+use nix::unistd::{Group, User};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+
+/// `PATH` for services that do not set their own
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Errors while starting a service
+#[derive(thiserror::Error, Debug)]
+pub enum StartError {
+    /// There is no definition for the service
+    #[error(transparent)]
+    Load(#[from] config::service::LoadError),
+    /// An environment file could not be read
+    #[error(transparent)]
+    Environment(#[from] EnvironmentError),
+    /// The user or group to run as does not exist
+    #[error("Unknown {kind} `{name}`")]
+    UnknownId {
+        /// `user` or `group`
+        kind: &'static str,
+        /// The name that was looked up
+        name: String,
+    },
+    /// The service is masked until the next boot
+    #[error("{0} is masked")]
+    Masked(String),
+    /// An assertion of the service does not hold
+    #[error("{0}")]
+    Assertion(crate::condition::CheckFailure),
+    /// The process could not be started
+    #[error("Failed to start `{program}`: {source}")]
+    Spawn {
+        /// The program of the exec line
+        program: String,
+        /// What went wrong
+        source: std::io::Error,
+    },
+}
+
+/// Starts the `exec` line of a service, `None` if it has none
+///
+/// The process gets its own process group, so signals meant for easyinit's console do not reach it.
+pub fn spawn(definition: &ServiceDefinition, defaults: &ServiceDefaults) -> Result<Option<Child>, StartError> {
+    let service = &definition.service;
+    let Some(exec) = &service.exec else {
+        return Ok(None);
+    };
+    let mut command = Command::new(exec.program());
+    command
+        .args(exec.args())
+        .env_clear()
+        .env("PATH", DEFAULT_PATH)
+        .envs(service.resolve_environment(defaults)?)
+        .stdin(Stdio::null())
+        .process_group(0);
+    if let Some(dir) = &service.working_directory {
+        command.current_dir(dir);
+    }
+    let user = service.user.as_deref().map(user).transpose()?;
+    if let Some(user) = &user {
+        command.uid(user.uid.as_raw()).gid(user.gid.as_raw()).env("HOME", &user.dir).env("USER", &user.name);
+    }
+    if let Some(name) = &service.group {
+        command.gid(group(name)?.as_raw());
+    }
+    command
+        .spawn()
+        .map(Some)
+        .map_err(|source| StartError::Spawn { program: exec.program().to_string(), source })
+}
+
+/// Looks up a user by name or number
+fn user(name: &str) -> Result<User, StartError> {
+    let found = match name.parse() {
+        Ok(uid) => User::from_uid(nix::unistd::Uid::from_raw(uid)),
+        Err(_) => User::from_name(name),
+    };
+    found.ok().flatten().ok_or_else(|| StartError::UnknownId { kind: "user", name: name.to_string() })
+}
+
+/// Looks up a group by name or number
+fn group(name: &str) -> Result<nix::unistd::Gid, StartError> {
+    if let Ok(gid) = name.parse() {
+        return Ok(nix::unistd::Gid::from_raw(gid));
+    }
+    Group::from_name(name)
+        .ok()
+        .flatten()
+        .map(|group| group.gid)
+        .ok_or_else(|| StartError::UnknownId { kind: "group", name: name.to_string() })
+}
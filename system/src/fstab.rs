@@ -0,0 +1,253 @@
+//! Reads the filesystems to mount from `/etc/fstab`
+//!
+//! Every line is `source mount-point type options dump pass`, the last three are optional.
+//! Sources can name a device by `UUID=`, `LABEL=`, `PARTUUID=` or `PARTLABEL=`, which are
+//! looked up through the links in `/dev/disk`.
+use crate::mountinfo::unescape;
+use nix::mount::MsFlags;
+use std::path::{Path, PathBuf};
+
+/// Where the filesystem table is
+pub const PATH: &str = "/etc/fstab";
+
+/// Filesystem types that need the network
+const NETWORK_TYPES: &[&str] =
+    &["nfs", "nfs4", "cifs", "smb3", "smbfs", "ncpfs", "ceph", "glusterfs", "fuse.sshfs", "sshfs", "davfs", "afs"];
+
+/// Options that only mean something to easyinit or `mount`, they are not passed to the kernel
+const USERSPACE_OPTIONS: &[&str] = &["defaults", "auto", "noauto", "nofail", "_netdev", "user", "users", "nouser", "owner", "group"];
+
+/// Options that are mount flags, with if they set or clear it
+const FLAGS: &[(&str, MsFlags, bool)] = &[
+    ("ro", MsFlags::MS_RDONLY, true),
+    ("rw", MsFlags::MS_RDONLY, false),
+    ("nosuid", MsFlags::MS_NOSUID, true),
+    ("suid", MsFlags::MS_NOSUID, false),
+    ("nodev", MsFlags::MS_NODEV, true),
+    ("dev", MsFlags::MS_NODEV, false),
+    ("noexec", MsFlags::MS_NOEXEC, true),
+    ("exec", MsFlags::MS_NOEXEC, false),
+    ("sync", MsFlags::MS_SYNCHRONOUS, true),
+    ("async", MsFlags::MS_SYNCHRONOUS, false),
+    ("dirsync", MsFlags::MS_DIRSYNC, true),
+    ("noatime", MsFlags::MS_NOATIME, true),
+    ("atime", MsFlags::MS_NOATIME, false),
+    ("nodiratime", MsFlags::MS_NODIRATIME, true),
+    ("diratime", MsFlags::MS_NODIRATIME, false),
+    ("relatime", MsFlags::MS_RELATIME, true),
+    ("norelatime", MsFlags::MS_RELATIME, false),
+    ("strictatime", MsFlags::MS_STRICTATIME, true),
+    ("lazytime", MsFlags::MS_LAZYTIME, true),
+    ("bind", MsFlags::MS_BIND, true),
+    ("rbind", MsFlags::MS_BIND.union(MsFlags::MS_REC), true),
+];
+
+/// What is mounted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// `UUID=` of a filesystem
+    Uuid(String),
+    /// `LABEL=` of a filesystem
+    Label(String),
+    /// `PARTUUID=` of a partition
+    PartUuid(String),
+    /// `PARTLABEL=` of a partition
+    PartLabel(String),
+    /// A device or directory, like `/dev/sda1`
+    Path(PathBuf),
+    /// Anything else, like `tmpfs` or `server:/export`
+    Other(String),
+}
+
+impl Source {
+    fn parse(field: &str) -> Self {
+        let tagged = [
+            ("UUID=", Source::Uuid as fn(String) -> Source),
+            ("LABEL=", Source::Label),
+            ("PARTUUID=", Source::PartUuid),
+            ("PARTLABEL=", Source::PartLabel),
+        ];
+        for (tag, source) in tagged {
+            if let Some(value) = field.strip_prefix(tag) {
+                return source(value.trim_matches('"').to_string());
+            }
+        }
+        if field.starts_with('/') { Source::Path(PathBuf::from(field)) } else { Source::Other(field.to_string()) }
+    }
+
+    /// The path of the device or directory, `None` for sources like `tmpfs`
+    pub fn path(&self) -> Option<PathBuf> {
+        let (dir, value) = match self {
+            Source::Uuid(uuid) => ("by-uuid", uuid),
+            Source::Label(label) => ("by-label", label),
+            Source::PartUuid(uuid) => ("by-partuuid", uuid),
+            Source::PartLabel(label) => ("by-partlabel", label),
+            Source::Path(path) => return Some(path.clone()),
+            Source::Other(_) => return None,
+        };
+        Some(Path::new("/dev/disk").join(dir).join(udev_escape(value)))
+    }
+
+    /// If the source is a device, that may show up after easyinit starts
+    pub fn is_device(&self) -> bool {
+        self.path().is_some_and(|path| path.starts_with("/dev"))
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Uuid(uuid) => write!(f, "UUID={uuid}"),
+            Source::Label(label) => write!(f, "LABEL={label}"),
+            Source::PartUuid(uuid) => write!(f, "PARTUUID={uuid}"),
+            Source::PartLabel(label) => write!(f, "PARTLABEL={label}"),
+            Source::Path(path) => write!(f, "{}", path.display()),
+            Source::Other(other) => f.write_str(other),
+        }
+    }
+}
+
+/// A line of the filesystem table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// What is mounted
+    pub source: Source,
+    /// Where it is mounted, `none` for swap
+    pub mount_point: PathBuf,
+    /// Type of the filesystem, like `ext4`
+    pub fs_type: String,
+    /// The options, like `noatime` or `x-easyinit.mount-timeout=10`
+    pub options: Vec<String>,
+    /// Order of the filesystem checks, `0` for none
+    pub pass: u32,
+}
+
+impl Entry {
+    /// If the option is set, like `nofail`
+    pub fn has_option(&self, option: &str) -> bool {
+        self.options.iter().any(|o| o == option)
+    }
+
+    /// The value of an option like `x-easyinit.mount-timeout=10`
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options.iter().find_map(|o| o.strip_prefix(name)?.strip_prefix('='))
+    }
+
+    /// If it is only mounted when asked for
+    pub fn is_noauto(&self) -> bool {
+        self.has_option("noauto")
+    }
+
+    /// If the boot goes on when it fails to mount
+    pub fn is_nofail(&self) -> bool {
+        self.has_option("nofail")
+    }
+
+    /// If it needs the network, from `_netdev` or the filesystem type
+    pub fn is_network(&self) -> bool {
+        self.has_option("_netdev") || NETWORK_TYPES.contains(&self.fs_type.as_str())
+    }
+
+    /// If it is swap space instead of a filesystem
+    pub fn is_swap(&self) -> bool {
+        self.fs_type == "swap"
+    }
+
+    /// The flags and the filesystem specific options to give the kernel
+    ///
+    /// Options for easyinit, `mount` and other programs like `x-` and `comment=` are left out.
+    pub fn mount_options(&self) -> (MsFlags, String) {
+        mount_options(&self.options)
+    }
+}
+
+/// Splits options into mount flags and the filesystem specific options, see [`Entry::mount_options`]
+///
+/// Works on the options of a [mount table](crate::mountinfo) too.
+pub fn mount_options(options: &[String]) -> (MsFlags, String) {
+    let mut flags = MsFlags::empty();
+    let mut data = Vec::new();
+    for option in options {
+        if USERSPACE_OPTIONS.contains(&option.as_str()) || option.starts_with("x-") || option.starts_with("comment=") {
+            continue;
+        }
+        match FLAGS.iter().find(|(name, _, _)| name == option) {
+            Some((_, flag, true)) => flags.insert(*flag),
+            Some((_, flag, false)) => flags.remove(*flag),
+            None => data.push(option.as_str()),
+        }
+    }
+    (flags, data.join(","))
+}
+
+/// A line that could not be parsed
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("Line {line}: {reason}")]
+pub struct LineError {
+    /// The line number, from 1
+    pub line: usize,
+    /// What is wrong with it
+    pub reason: String,
+}
+
+/// The parsed filesystem table
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fstab {
+    /// The entries in the order of the file
+    pub entries: Vec<Entry>,
+    /// Lines that were skipped
+    pub errors: Vec<LineError>,
+}
+
+/// Reads and parses a filesystem table
+pub fn read(path: &Path) -> std::io::Result<Fstab> {
+    Ok(parse(&std::fs::read_to_string(path)?))
+}
+
+/// Parses a filesystem table that has already been read
+pub fn parse(text: &str) -> Fstab {
+    let mut fstab = Fstab::default();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_line(line) {
+            Ok(entry) => fstab.entries.push(entry),
+            Err(reason) => fstab.errors.push(LineError { line: number + 1, reason }),
+        }
+    }
+    fstab
+}
+
+/// `UUID=1234 /home ext4 defaults,noatime 0 2`
+fn parse_line(line: &str) -> Result<Entry, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [source, mount_point, fs_type, rest @ ..] = fields.as_slice() else {
+        return Err("Expected at least a source, a mount point and a type".to_string());
+    };
+    if rest.len() > 3 {
+        return Err(format!("Expected at most 6 fields, found {}", fields.len()));
+    }
+    let options = rest.first().map_or("defaults", |o| o);
+    let pass = match rest.get(2) {
+        Some(pass) => pass.parse().map_err(|_| format!("Invalid pass number `{pass}`"))?,
+        None => 0,
+    };
+    let mount_point = unescape(mount_point);
+    if mount_point != "none" && !mount_point.starts_with('/') {
+        return Err(format!("Mount point `{mount_point}` is not an absolute path"));
+    }
+    Ok(Entry {
+        source: Source::parse(&unescape(source)),
+        mount_point: PathBuf::from(mount_point),
+        fs_type: fs_type.to_string(),
+        options: options.split(',').filter(|o| !o.is_empty()).map(unescape).collect(),
+        pass,
+    })
+}
+
+/// Escapes a label like udev does for the links in `/dev/disk`
+fn udev_escape(value: &str) -> String {
+    value.chars().map(|c| if c == '/' || c == ' ' { format!("\\x{:02x}", c as u32) } else { c.to_string() }).collect()
+}
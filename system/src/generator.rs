@@ -0,0 +1,263 @@
+//! Runs generators, programs that write service definitions before they are loaded
+//!
+//! Generators are executables in `/etc/easyinit/generators` and `/usr/lib/easyinit/generators`,
+//! one in `/etc` replaces one with the same name in `/usr/lib`. They run in order of their names,
+//! each with the three output directories as arguments:
+//!
+//! 1. `/run/easyinit/generator`, for definitions between the administrator's and the packages'
+//! 2. `/run/easyinit/generator.early`, for definitions that replace every other one
+//! 3. `/run/easyinit/generator.late`, for definitions that only fill in what is missing
+//!
+//! The output directories are emptied before the generators run, so a generator that stops
+//! writing a definition removes it on the next reload. A generator that fails or runs out of
+//! time is killed and logged, what it wrote is still loaded.
+use config::loader::{GENERATOR_DIR, GENERATOR_EARLY_DIR, GENERATOR_LATE_DIR};
+use logging::prelude::*;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// The generator directories, relative to the root, the first one wins
+const DIRS: &[&str] = &["etc/easyinit/generators", "usr/lib/easyinit/generators"];
+
+/// How often a running generator is checked
+const POLL: Duration = Duration::from_millis(10);
+
+/// `PATH` for the generators
+const PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Errors of a single generator
+#[derive(thiserror::Error, Debug)]
+pub enum GeneratorError {
+    /// The generator could not be started
+    #[error("Failed to start: {0}")]
+    Spawn(std::io::Error),
+    /// The generator could not be waited for
+    #[error("Failed to wait for it: {0}")]
+    Wait(std::io::Error),
+    /// The generator ran longer than it may
+    #[error("Killed after {}s", .0.as_secs_f32())]
+    Timeout(Duration),
+    /// The generator exited with an error
+    #[error("{status}{}", if stderr.is_empty() { String::new() } else { format!(": {stderr}") })]
+    Failed {
+        /// How it exited
+        status: std::process::ExitStatus,
+        /// What it wrote to stderr
+        stderr: String,
+    },
+}
+
+/// Errors before any generator runs
+#[derive(thiserror::Error, Debug)]
+#[error("Failed to prepare {}: {source}", path.display())]
+pub struct PrepareError {
+    /// The output directory
+    pub path: PathBuf,
+    /// What went wrong
+    pub source: std::io::Error,
+}
+
+/// What happened to every generator
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Generators that succeeded
+    pub succeeded: Vec<String>,
+    /// Generators that failed, with why
+    pub failed: Vec<(String, GeneratorError)>,
+}
+
+/// The generators of a system
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Generators {
+    root: PathBuf,
+    timeout: Duration,
+}
+
+impl Generators {
+    /// The generators of the running system, each may run for `timeout`, zero for no limit
+    pub fn system(timeout: Duration) -> Self {
+        Self::with_root(Path::new("/"), timeout)
+    }
+
+    /// Generators and output directories under a different root
+    pub fn with_root(root: &Path, timeout: Duration) -> Self {
+        Generators { root: root.to_path_buf(), timeout }
+    }
+
+    /// The executables to run by name, the ones in `/etc` replace the ones in `/usr/lib`
+    pub fn find(&self) -> BTreeMap<String, PathBuf> {
+        let mut found = BTreeMap::new();
+        for dir in DIRS {
+            let Ok(entries) = std::fs::read_dir(self.root.join(dir)) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                // A link to /dev/null disables a generator of the same name, like a masked service
+                let executable = path.metadata().is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0);
+                let name = entry.file_name().to_string_lossy().into_owned();
+                found.entry(name).or_insert((path, executable));
+            }
+        }
+        found.into_iter().filter(|(_, (_, executable))| *executable).map(|(name, (path, _))| (name, path)).collect()
+    }
+
+    /// Empties the output directories and runs every generator, logging the ones that fail
+    pub fn run(&self) -> Result<Report, PrepareError> {
+        let outputs = [GENERATOR_DIR, GENERATOR_EARLY_DIR, GENERATOR_LATE_DIR].map(|dir| self.root.join(dir));
+        for path in &outputs {
+            prepare(path).map_err(|source| PrepareError { path: path.clone(), source })?;
+        }
+        let mut report = Report::default();
+        for (name, path) in self.find() {
+            match self.run_one(&path, &outputs) {
+                Ok(()) => {
+                    debug!("Generator {name} succeeded");
+                    report.succeeded.push(name);
+                }
+                Err(e) => {
+                    warn!("Generator {name} failed: {e}");
+                    report.failed.push((name, e));
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Runs a generator until it exits or its time is up
+    fn run_one(&self, path: &Path, outputs: &[PathBuf; 3]) -> Result<(), GeneratorError> {
+        let mut child = Command::new(path)
+            .args(outputs)
+            .env_clear()
+            .env("PATH", PATH)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(GeneratorError::Spawn)?;
+        // Read stderr while it runs, so a generator that writes a lot does not block on a full pipe
+        let stderr = child.stderr.take().map(|mut pipe| {
+            std::thread::spawn(move || {
+                let mut text = String::new();
+                let _ = pipe.read_to_string(&mut text);
+                text
+            })
+        });
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(GeneratorError::Wait)? {
+                break status;
+            }
+            if !self.timeout.is_zero() && started.elapsed() >= self.timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                // The reader is left behind, a process the generator forked may still hold the pipe
+                return Err(GeneratorError::Timeout(self.timeout));
+            }
+            std::thread::sleep(POLL);
+        };
+        if status.success() {
+            return Ok(());
+        }
+        let stderr = stderr.and_then(|reader| reader.join().ok()).unwrap_or_default();
+        Err(GeneratorError::Failed { status, stderr: stderr.trim().to_string() })
+    }
+}
+
+/// Creates an empty output directory, removing what a previous run wrote
+fn prepare(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_dir_all(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    std::fs::create_dir_all(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::loader::ServiceDirs;
+    use utils::testing::TempDir;
+
+    /// Writes an executable shell script as a generator in `dir`
+    fn generator(root: &TempDir, dir: &str, name: &str, script: &str) {
+        let path = Path::new(dir).join(name);
+        root.write(&path, format!("#!/bin/sh\n{script}\n"));
+        std::fs::set_permissions(root.join(&path), std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn find() {
+        let root = TempDir::new("generators-find");
+        generator(&root, DIRS[1], "b-packaged", "exit 1");
+        generator(&root, DIRS[1], "a-replaced", "exit 1");
+        generator(&root, DIRS[0], "a-replaced", "exit 0");
+        generator(&root, DIRS[1], "disabled", "exit 1");
+        std::os::unix::fs::symlink("/dev/null", root.join(DIRS[0]).join("disabled")).unwrap();
+        root.write(Path::new(DIRS[0]).join("not-executable"), "#!/bin/sh\n");
+        let found = Generators::with_root(root.path(), Duration::ZERO).find();
+        assert_eq!(
+            found,
+            BTreeMap::from([
+                ("a-replaced".to_string(), root.join(DIRS[0]).join("a-replaced")),
+                ("b-packaged".to_string(), root.join(DIRS[1]).join("b-packaged")),
+            ])
+        );
+    }
+
+    #[test]
+    fn output_dirs() {
+        let root = TempDir::new("generators-output");
+        root.write(Path::new(GENERATOR_DIR).join("stale.toml"), "");
+        root.write("etc/easyinit/services/both.toml", "description = \"administrator\"\n");
+        generator(
+            &root,
+            DIRS[0],
+            "write",
+            "for dir in \"$1\" \"$2\" \"$3\"; do\n  name=$(basename \"$dir\")\n  for service in \"$name\" both all; do\n    echo \"description = \\\"$name\\\"\" > \"$dir/$service.toml\"\n  done\ndone",
+        );
+        let report = Generators::with_root(root.path(), Duration::ZERO).run().unwrap();
+        assert_eq!(report.succeeded, ["write"]);
+        assert!(report.failed.is_empty());
+        assert!(!root.join(GENERATOR_DIR).join("stale.toml").exists());
+
+        let dirs = ServiceDirs::with_root(root.path());
+        let description = |name: &str| dirs.load(name).unwrap().definition.description.unwrap();
+        for (dir, name) in [(GENERATOR_DIR, "generator"), (GENERATOR_EARLY_DIR, "generator.early"), (GENERATOR_LATE_DIR, "generator.late")] {
+            assert_eq!(description(name), name);
+            assert_eq!(dirs.load(name).unwrap().path, root.join(dir).join(format!("{name}.toml")));
+        }
+        // Early replaces the administrator, who replaces the others
+        assert_eq!(description("all"), "generator.early");
+        std::fs::remove_file(root.join(GENERATOR_EARLY_DIR).join("both.toml")).unwrap();
+        assert_eq!(description("both"), "administrator");
+    }
+
+    #[test]
+    fn failures() {
+        let root = TempDir::new("generators-failures");
+        generator(&root, DIRS[0], "a-fails", "echo partial > \"$1/partial.toml\"\necho 'something broke' >&2\nexit 3");
+        generator(&root, DIRS[0], "b-hangs", "echo 'description = \"hung\"' > \"$3/hung.toml\"\nexec sleep 10");
+        generator(&root, DIRS[1], "c-works", "touch \"$2/works.toml\"");
+        let started = Instant::now();
+        let report = Generators::with_root(root.path(), Duration::from_millis(200)).run().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        // The others still run
+        assert_eq!(report.succeeded, ["c-works"]);
+        match &report.failed[..] {
+            [(failed, GeneratorError::Failed { status, stderr }), (hung, GeneratorError::Timeout(timeout))] => {
+                assert_eq!((failed.as_str(), status.code(), stderr.as_str()), ("a-fails", Some(3), "something broke"));
+                assert_eq!((hung.as_str(), *timeout), ("b-hangs", Duration::from_millis(200)));
+            }
+            other => panic!("unexpected failures {other:?}"),
+        }
+        // What they wrote is still loaded
+        assert!(root.join(GENERATOR_DIR).join("partial.toml").exists());
+        assert!(root.join(GENERATOR_LATE_DIR).join("hung.toml").exists());
+        assert!(root.join(GENERATOR_EARLY_DIR).join("works.toml").exists());
+    }
+}
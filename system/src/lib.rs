@@ -1 +1,12 @@
+//! Mounts filesystems and manages the services of the system scope of easyinit.
+pub mod condition;
+pub mod exec;
+pub mod fstab;
+pub mod generator;
+pub mod manager;
+pub mod mount;
+pub mod mountinfo;
+pub mod root;
 pub mod startup;
+pub mod transaction;
+pub mod virt;
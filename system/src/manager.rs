@@ -0,0 +1,561 @@
+//! Keeps track of every service definition and the state of its service
+//!
+//! Definitions come from the [service directories](config::loader), and at a lower precedence
+//! from SysV init's `/etc/inittab` and `/etc/init.d`, so a native definition replaces a SysV one
+//! of the same name.
+//!
+//! [`Manager::reload`] loads everything again and only applies what changed. A service that is
+//! running when its definition changes keeps running, and is marked as needing a restart. One
+//! that is running when its definition is removed is kept until it stops.
+//!
+//! Instances of templates, like `getty@tty1`, are only known once they are started with
+//! [`Manager::start`], and are loaded again from their template on reload.
+use crate::condition::{CheckFailure, Host};
+use crate::exec::{self, StartError};
+use config::loader::{self, ReloadDiff, ServiceDirs};
+use config::service::{LoadError, RestartPolicy, ServiceDefaults, ServiceDefinition, ServiceKind};
+use config::sysv;
+use logging::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitStatus};
+
+/// The state of a service
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum ServiceState {
+    /// Not running
+    #[default]
+    Inactive,
+    /// Running, or a oneshot that finished successfully
+    Active,
+    /// Exited unsuccessfully
+    Failed,
+}
+
+impl std::fmt::Display for ServiceState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ServiceState::Inactive => "inactive",
+            ServiceState::Active => "active",
+            ServiceState::Failed => "failed",
+        })
+    }
+}
+
+/// A service the manager knows about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManagedService {
+    /// The definition, with drop-ins applied
+    pub definition: ServiceDefinition,
+    /// The file it was loaded from
+    pub path: PathBuf,
+    /// The state of the service
+    pub state: ServiceState,
+    /// The definition changed while the service was running
+    pub needs_restart: bool,
+    /// The definition was removed while the service was running, it is forgotten once it stops
+    pub removed: bool,
+    /// The running process
+    pub pid: Option<u32>,
+    /// The condition or assertion that failed the last time it was started
+    pub check: Option<CheckFailure>,
+}
+
+impl ManagedService {
+    fn new(definition: ServiceDefinition, path: PathBuf) -> Self {
+        ManagedService {
+            definition,
+            path,
+            state: ServiceState::Inactive,
+            needs_restart: false,
+            removed: false,
+            pid: None,
+            check: None,
+        }
+    }
+}
+
+/// A service as it is shown to `easyctl`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ServiceStatus {
+    /// Name of the service, like `getty@tty1` for an instance
+    pub name: String,
+    /// The state of the service
+    pub state: ServiceState,
+    /// The definition changed while the service was running
+    pub needs_restart: bool,
+    /// The definition was removed while the service was running
+    pub removed: bool,
+    /// The running process
+    pub pid: Option<u32>,
+    /// The file it was loaded from, the template for instances
+    pub path: PathBuf,
+    /// The condition or assertion that failed the last time it was started
+    pub check: Option<CheckFailure>,
+}
+
+/// Every service and template, the response to listing services
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ServiceList {
+    /// Every known service, including instances that were started
+    pub services: Vec<ServiceStatus>,
+    /// Names of the templates, like `getty@`
+    pub templates: Vec<String>,
+}
+
+/// Where SysV init definitions are read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SysvPaths {
+    inittab: PathBuf,
+    init_d: PathBuf,
+}
+
+/// Every service definition and the state of its service
+#[derive(Debug)]
+pub struct Manager {
+    dirs: ServiceDirs,
+    sysv: Option<SysvPaths>,
+    host: Host,
+    services: BTreeMap<String, ManagedService>,
+    /// Services that are not started until the next boot
+    masked: BTreeSet<String>,
+    /// Services that were added while running instead of loaded, they are kept on reload
+    transient: BTreeSet<String>,
+}
+
+impl Manager {
+    /// A manager for the standard directories, with nothing loaded yet
+    pub fn system() -> Self {
+        Manager { host: Host::system(), ..Manager::with_root(Path::new("/")) }
+    }
+
+    /// A manager for the standard directories under a different root, with nothing loaded yet
+    pub fn with_root(root: &Path) -> Self {
+        Manager {
+            dirs: ServiceDirs::with_root(root),
+            sysv: Some(SysvPaths {
+                inittab: root.join("etc/inittab"),
+                init_d: root.join("etc/init.d"),
+            }),
+            host: Host::with_root(root),
+            services: BTreeMap::new(),
+            masked: BTreeSet::new(),
+            transient: BTreeSet::new(),
+        }
+    }
+
+    /// A manager that only loads from `dirs`, without SysV init definitions
+    pub fn new(dirs: ServiceDirs) -> Self {
+        Manager {
+            dirs,
+            sysv: None,
+            host: Host::system(),
+            services: BTreeMap::new(),
+            masked: BTreeSet::new(),
+            transient: BTreeSet::new(),
+        }
+    }
+
+    /// Every known service, by name
+    pub fn services(&self) -> &BTreeMap<String, ManagedService> {
+        &self.services
+    }
+
+    /// A single service
+    pub fn service(&self, name: &str) -> Option<&ManagedService> {
+        self.services.get(name)
+    }
+
+    /// Stops services from being started until the next boot
+    pub fn mask(&mut self, names: impl IntoIterator<Item = String>) {
+        self.masked.extend(names);
+    }
+
+    /// Services that are not started until the next boot
+    pub fn masked(&self) -> &BTreeSet<String> {
+        &self.masked
+    }
+
+    /// Adds a service that has no definition file, like the `easyinit.run` command
+    ///
+    /// It is kept when the definitions are loaded again.
+    pub fn add_transient(&mut self, definition: ServiceDefinition) {
+        let name = definition.name.clone();
+        self.transient.insert(name.clone());
+        self.services.insert(name, ManagedService::new(definition, PathBuf::new()));
+    }
+
+    /// The definition of a service as it is loaded, or would be loaded for an instance that was not started
+    pub fn definition(&self, name: &str) -> Result<ServiceDefinition, LoadError> {
+        match self.services.get(name) {
+            Some(service) => Ok(service.definition.clone()),
+            None if loader::split_instance(name).is_some() => self.dirs.load(name).map(|loaded| loaded.definition),
+            None => Err(LoadError::NotFound { name: name.to_string() }),
+        }
+    }
+
+    /// Every service and template
+    pub fn list(&self) -> ServiceList {
+        ServiceList {
+            services: self
+                .services
+                .iter()
+                .map(|(name, service)| ServiceStatus {
+                    name: name.clone(),
+                    state: service.state,
+                    needs_restart: service.needs_restart,
+                    removed: service.removed,
+                    pid: service.pid,
+                    path: service.path.clone(),
+                    check: service.check.clone(),
+                })
+                .collect(),
+            templates: self.dirs.templates().into_iter().collect(),
+        }
+    }
+
+    /// Starts a service, loading instances of templates on demand
+    ///
+    /// Returns the started process, which the caller waits for and reports with [`Manager::exited`].
+    /// `None` if the service has no process, is already active, or a condition skipped it.
+    pub fn start(&mut self, name: &str, defaults: &ServiceDefaults) -> Result<Option<Child>, StartError> {
+        if !self.services.contains_key(name) {
+            if loader::split_instance(name).is_none() {
+                return Err(LoadError::NotFound { name: name.to_string() }.into());
+            }
+            let loaded = self.dirs.load(name)?;
+            info!("Loaded {name} from {}", loaded.path.display());
+            self.services.insert(name.to_string(), ManagedService::new(loaded.definition, loaded.path));
+        }
+        if self.masked.contains(name) {
+            return Err(StartError::Masked(name.to_string()));
+        }
+        let Some(service) = self.services.get_mut(name) else {
+            return Err(LoadError::NotFound { name: name.to_string() }.into());
+        };
+        if service.state == ServiceState::Active {
+            return Ok(None);
+        }
+        service.check = None;
+        if let Err(failure) = self.host.check(&service.definition) {
+            service.check = Some(failure.clone());
+            if failure.assertion {
+                service.state = ServiceState::Failed;
+                return Err(StartError::Assertion(failure));
+            }
+            info!("Skipping {name}, {failure}");
+            service.state = ServiceState::Inactive;
+            return Ok(None);
+        }
+        let child = match exec::spawn(&service.definition, defaults) {
+            Ok(child) => child,
+            Err(e) => {
+                service.state = ServiceState::Failed;
+                return Err(e);
+            }
+        };
+        service.state = ServiceState::Active;
+        service.needs_restart = false;
+        service.pid = child.as_ref().map(Child::id);
+        Ok(child)
+    }
+
+    /// Records that the process of a service exited, returns if it should be started again
+    ///
+    /// A process that is no longer the service's, because it was restarted meanwhile, is ignored.
+    pub fn exited(&mut self, name: &str, pid: u32, status: ExitStatus, defaults: &ServiceDefaults) -> bool {
+        let Some(service) = self.services.get_mut(name) else {
+            return false;
+        };
+        if service.pid != Some(pid) {
+            return false;
+        }
+        service.pid = None;
+        let success = status.success();
+        service.state = match (success, service.definition.service.kind) {
+            (false, _) => ServiceState::Failed,
+            (true, ServiceKind::Oneshot) if service.definition.service.exec_stop.is_some() => ServiceState::Active,
+            (true, _) => ServiceState::Inactive,
+        };
+        if service.state != ServiceState::Active {
+            service.needs_restart = false;
+            if service.removed {
+                self.services.remove(name);
+                return false;
+            }
+        }
+        match service.definition.service.restart_policy(defaults) {
+            RestartPolicy::No => false,
+            RestartPolicy::OnFailure => !success,
+            RestartPolicy::Always => service.state != ServiceState::Active,
+        }
+    }
+
+    /// Changes the state of a service, a service that stopped no longer needs a restart
+    ///
+    /// A service whose definition was removed is forgotten once it stops.
+    pub fn set_state(&mut self, name: &str, state: ServiceState) {
+        if let Some(service) = self.services.get_mut(name) {
+            service.state = state;
+            if state != ServiceState::Active {
+                service.needs_restart = false;
+                if service.removed {
+                    self.services.remove(name);
+                }
+            }
+        }
+    }
+
+    /// Loads every definition again and applies what changed
+    ///
+    /// A definition that fails to load is reported in [`ReloadDiff::errors`] and the service keeps
+    /// its old definition, so a typo does not make a running service disappear.
+    pub fn reload(&mut self) -> ReloadDiff {
+        let (mut loaded, errors, inittab_failed) = self.load();
+        let mut diff = ReloadDiff { errors, ..Default::default() };
+
+        // Services whose definition failed to load, and transient ones, are kept as they are
+        let native = self.dirs.names();
+        let templates = self.dirs.templates();
+        let failed: Vec<String> = self
+            .services
+            .iter()
+            .filter(|(name, service)| {
+                !loaded.contains_key(*name)
+                    && (native.contains(*name)
+                        || self.transient.contains(*name)
+                        || has_template(name, &templates)
+                        || self.sysv_failed(service, inittab_failed))
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        let old = std::mem::take(&mut self.services);
+        for (name, mut service) in old {
+            if failed.contains(&name) {
+                self.services.insert(name, service);
+                continue;
+            }
+            let Some((definition, path)) = loaded.remove(&name) else {
+                // A running service is kept until it stops, so its process is still tracked
+                if service.state == ServiceState::Active {
+                    if !service.removed {
+                        service.removed = true;
+                        service.needs_restart = true;
+                        diff.removed.push(name.clone());
+                        diff.needs_restart.push(name.clone());
+                    }
+                    self.services.insert(name, service);
+                } else {
+                    diff.removed.push(name);
+                }
+                continue;
+            };
+            if service.removed {
+                // The definition is back before the service stopped
+                service.removed = false;
+                service.needs_restart = false;
+                diff.added.push(name.clone());
+            }
+            if definition != service.definition || path != service.path {
+                diff.changed.push(name.clone());
+                if service.state == ServiceState::Active {
+                    service.needs_restart = true;
+                    diff.needs_restart.push(name.clone());
+                }
+                service.definition = definition;
+                service.path = path;
+            }
+            self.services.insert(name, service);
+        }
+        for (name, (definition, path)) in loaded {
+            diff.added.push(name.clone());
+            self.services.insert(name, ManagedService::new(definition, path));
+        }
+        diff
+    }
+
+    /// If a SysV service is missing because its file failed to load, rather than being removed
+    fn sysv_failed(&self, service: &ManagedService, inittab_failed: bool) -> bool {
+        let Some(paths) = &self.sysv else {
+            return false;
+        };
+        if service.path == paths.inittab {
+            inittab_failed
+        } else {
+            service.path.starts_with(&paths.init_d) && service.path.exists()
+        }
+    }
+
+    /// Every definition by name, with errors formatted for [`ReloadDiff::errors`], and if
+    /// inittab failed to load
+    fn load(&self) -> (BTreeMap<String, (ServiceDefinition, PathBuf)>, Vec<String>, bool) {
+        let mut definitions = BTreeMap::new();
+        let mut errors = Vec::new();
+        let inittab_failed = match &self.sysv {
+            Some(paths) => load_sysv(paths, &mut definitions, &mut errors),
+            None => false,
+        };
+        // Native definitions are inserted last, so they replace SysV ones
+        let (loaded, load_errors) = self.dirs.load_all();
+        for service in loaded {
+            definitions.insert(service.definition.name.clone(), (service.definition, service.path));
+        }
+        errors.extend(load_errors.iter().map(ToString::to_string));
+        // Instances that were started are loaded from their template again
+        let instances = self.services.keys().filter(|name| !definitions.contains_key(*name) && loader::split_instance(name).is_some());
+        for name in instances.cloned().collect::<Vec<_>>() {
+            match self.dirs.load(&name) {
+                Ok(service) => {
+                    definitions.insert(name, (service.definition, service.path));
+                }
+                Err(LoadError::NotFound { .. }) => {}
+                Err(e) => errors.push(e.to_string()),
+            }
+        }
+        for error in &errors {
+            warn!("{error}");
+        }
+        (definitions, errors, inittab_failed)
+    }
+}
+
+/// If `name` is an instance of one of `templates`
+fn has_template(name: &str, templates: &BTreeSet<String>) -> bool {
+    loader::split_instance(name).is_some_and(|(template, _)| templates.contains(&format!("{template}@")))
+}
+
+/// Adds the SysV init definitions, returns if inittab failed to load
+fn load_sysv(paths: &SysvPaths, definitions: &mut BTreeMap<String, (ServiceDefinition, PathBuf)>, errors: &mut Vec<String>) -> bool {
+    let mut inittab_failed = false;
+    let inittab = match sysv::Inittab::from_file(&paths.inittab) {
+        Ok(inittab) => Some(inittab),
+        Err(sysv::SysvError::Io { source, .. }) if source.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            errors.push(e.to_string());
+            inittab_failed = true;
+            None
+        }
+    };
+    let default_runlevel = inittab.as_ref().map_or(sysv::DEFAULT_RUNLEVEL, sysv::Inittab::default_runlevel);
+    if let Some(inittab) = inittab {
+        let (found, entry_errors) = inittab.definitions();
+        for definition in found {
+            definitions.insert(definition.name.clone(), (definition, paths.inittab.clone()));
+        }
+        errors.extend(entry_errors.iter().map(ToString::to_string));
+    }
+    let (scripts, script_errors) = sysv::init_d_definitions(&paths.init_d, default_runlevel);
+    for definition in scripts {
+        let path = paths.init_d.join(&definition.name);
+        definitions.insert(definition.name.clone(), (definition, path));
+    }
+    errors.extend(script_errors.iter().map(ToString::to_string));
+    // Scripts started in `S` are wanted by the sysinit target, which only inittab defines otherwise
+    let rcs = definitions.values().any(|(d, _)| d.install.wanted_by.iter().any(|t| t == sysv::SYSINIT_TARGET));
+    if rcs && !definitions.contains_key(sysv::SYSINIT_TARGET) {
+        definitions.insert(sysv::SYSINIT_TARGET.to_string(), (sysv::sysinit_target(), paths.init_d.clone()));
+    }
+    inittab_failed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::testing::TempDir;
+
+    /// Writes the definition of `name`
+    fn write(dir: &TempDir, name: &str, text: &str) {
+        dir.write(format!("{name}.toml"), text);
+    }
+
+    #[test]
+    fn reload_diff() {
+        let dir = TempDir::new("reload-diff");
+        write(&dir, "a", "description = \"A\"\n");
+        write(&dir, "b", "description = \"B\"\n");
+        let mut manager = Manager::new(ServiceDirs::new(vec![dir.path().to_path_buf()]));
+        assert_eq!(manager.reload().added, ["a", "b"]);
+
+        write(&dir, "a", "description = \"Changed\"\n");
+        std::fs::remove_file(dir.join("b.toml")).unwrap();
+        write(&dir, "c", "");
+        let diff = manager.reload();
+        assert_eq!(diff.added, ["c"]);
+        assert_eq!(diff.removed, ["b"]);
+        assert_eq!(diff.changed, ["a"]);
+        assert!(diff.needs_restart.is_empty());
+        assert!(manager.service("b").is_none());
+    }
+
+    #[test]
+    fn removed_service_is_kept_until_it_stops() {
+        let dir = TempDir::new("reload-removed");
+        write(&dir, "sleeper", "[service]\nexec = \"/bin/sleep 30\"\n");
+        let mut manager = Manager::new(ServiceDirs::new(vec![dir.path().to_path_buf()]));
+        manager.reload();
+        let defaults = config::Cmdline::default().service_defaults();
+        let mut child = manager.start("sleeper", &defaults).unwrap().unwrap();
+
+        std::fs::remove_file(dir.join("sleeper.toml")).unwrap();
+        let diff = manager.reload();
+        assert_eq!(diff.removed, ["sleeper"]);
+        assert_eq!(diff.needs_restart, ["sleeper"]);
+        let service = manager.service("sleeper").unwrap();
+        assert!(service.removed && service.needs_restart);
+        assert_eq!(service.pid, Some(child.id()));
+        // Reloading again does not report it a second time
+        assert!(manager.reload().is_empty());
+
+        child.kill().unwrap();
+        let status = child.wait().unwrap();
+        assert!(!manager.exited("sleeper", child.id(), status, &defaults));
+        assert!(manager.service("sleeper").is_none());
+    }
+
+    #[test]
+    fn rcs_scripts_without_inittab() {
+        let dir = TempDir::new("sysv-rcs");
+        dir.write("init.d/udev", "#!/bin/sh\n### BEGIN INIT INFO\n# Provides: udev\n# Default-Start: S\n### END INIT INFO\n");
+        let paths = SysvPaths { inittab: dir.join("inittab"), init_d: dir.join("init.d") };
+        let (mut definitions, mut errors) = (BTreeMap::new(), Vec::new());
+        assert!(!load_sysv(&paths, &mut definitions, &mut errors));
+        assert!(errors.is_empty());
+        // The target wanting it exists without an inittab
+        assert_eq!(definitions[sysv::SYSINIT_TARGET].0, sysv::sysinit_target());
+        assert_eq!(definitions["udev"].0.install.wanted_by, [sysv::SYSINIT_TARGET]);
+    }
+
+    #[test]
+    fn instances_reload_from_template() {
+        let dir = TempDir::new("reload-instances");
+        write(&dir, "sleeper@", "description = \"Sleeps %i\"\n[service]\nexec = \"/bin/sleep %i\"\n");
+        let mut manager = Manager::new(ServiceDirs::new(vec![dir.path().to_path_buf()]));
+        manager.reload();
+        assert!(manager.service("sleeper@30").is_none());
+        assert_eq!(manager.list().templates, ["sleeper@"]);
+        assert_eq!(manager.definition("sleeper@30").unwrap().description.as_deref(), Some("Sleeps 30"));
+
+        let defaults = config::Cmdline::default().service_defaults();
+        let mut child = manager.start("sleeper@30", &defaults).unwrap().unwrap();
+        let service = manager.service("sleeper@30").unwrap();
+        assert_eq!(service.path, dir.join("sleeper@.toml"));
+        assert_eq!(service.definition.service.exec.as_ref().unwrap().args(), ["30"]);
+        assert!(manager.list().services.iter().any(|s| s.name == "sleeper@30"));
+
+        write(&dir, "sleeper@", "description = \"Naps %i\"\n[service]\nexec = \"/bin/sleep %i\"\n");
+        let diff = manager.reload();
+        assert_eq!(diff.changed, ["sleeper@30"]);
+        assert_eq!(diff.needs_restart, ["sleeper@30"]);
+        assert_eq!(manager.service("sleeper@30").unwrap().definition.description.as_deref(), Some("Naps 30"));
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
+
@@ -0,0 +1,314 @@
+//! Mounts the filesystems of the [filesystem table](crate::fstab) as jobs
+//!
+//! A job waits for the jobs of the filesystems it is mounted in, so `/var/log` waits for `/var`,
+//! and a bind mount waits for the filesystem its source is on. Jobs that do not wait for each
+//! other run in parallel, each in its own thread.
+//!
+//! Every job has a timeout, `x-easyinit.mount-timeout=` in seconds or [`DEFAULT_TIMEOUT`]. Until
+//! then it waits for its device to show up. Jobs waiting for one that failed are not run. The boot
+//! goes on without `nofail` filesystems, so they only get [`NOFAIL_TIMEOUT`] by default.
+//!
+//! Entries of type `auto` are tried as every filesystem in `/proc/filesystems` that needs a device.
+use crate::fstab::Entry;
+use crate::mountinfo::{self, Mount};
+use logging::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// How long a job may take when its entry does not say
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// How long a `nofail` job may take when its entry does not say
+pub const NOFAIL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The filesystems the kernel knows about, for entries of type `auto`
+const PROC_FILESYSTEMS: &str = "/proc/filesystems";
+
+/// How often a job looks for its device
+const DEVICE_POLL: Duration = Duration::from_millis(100);
+
+/// Errors of a single mount job
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum MountError {
+    /// The device did not show up in time
+    #[error("{} did not show up within {}s", .0.display(), .1.as_secs())]
+    NoDevice(PathBuf, Duration),
+    /// The mount point could not be created
+    #[error("Failed to create the mount point: {0}")]
+    MountPoint(String),
+    /// The kernel refused the mount
+    #[error("Failed to mount {device} as {fs_type}: {errno}")]
+    Mount {
+        /// What was mounted
+        device: String,
+        /// Type of the filesystem
+        fs_type: String,
+        /// Why it failed
+        errno: nix::Error,
+    },
+    /// The job did not finish in time, the mount may still happen
+    #[error("Timed out after {}s", .0.as_secs())]
+    Timeout(Duration),
+    /// A filesystem it needs failed to mount
+    #[error("Needs {}, which failed to mount", .0.display())]
+    Dependency(PathBuf),
+}
+
+/// An entry to mount, with the jobs it waits for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    /// The entry of the filesystem table
+    pub entry: Entry,
+    /// Indexes of the jobs that have to finish first
+    pub after: Vec<usize>,
+}
+
+impl Job {
+    /// How long the job may take, [`NOFAIL_TIMEOUT`] or [`DEFAULT_TIMEOUT`] unless the entry says
+    pub fn timeout(&self) -> Duration {
+        let default = if self.entry.is_nofail() { NOFAIL_TIMEOUT } else { DEFAULT_TIMEOUT };
+        self.entry
+            .option("x-easyinit.mount-timeout")
+            .and_then(|secs| secs.trim_end_matches('s').parse().ok())
+            .map_or(default, Duration::from_secs)
+    }
+}
+
+/// The mount jobs of a filesystem table
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JobGraph {
+    /// The jobs, every job comes after the ones it waits for
+    pub jobs: Vec<Job>,
+    /// Entries that are not mounted, with why
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+impl JobGraph {
+    /// Makes jobs of the entries that are mounted at boot and not in `mounted` yet
+    ///
+    /// The root filesystem, swap, `noauto` and network filesystems are left out.
+    pub fn new(entries: &[Entry], mounted: &[Mount]) -> Self {
+        let mut graph = JobGraph::default();
+        let mut entries: Vec<&Entry> = entries
+            .iter()
+            .filter(|entry| {
+                let reason = if entry.is_swap() || entry.mount_point == Path::new("none") {
+                    "swap"
+                } else if entry.mount_point == Path::new("/") {
+                    "root filesystem"
+                } else if entry.is_noauto() {
+                    "noauto"
+                } else if entry.is_network() {
+                    "network filesystem"
+                } else if mountinfo::find(mounted, &entry.mount_point).is_some() {
+                    "already mounted"
+                } else {
+                    return true;
+                };
+                graph.skipped.push((entry.mount_point.clone(), reason.to_string()));
+                false
+            })
+            .collect();
+        // Parents have fewer components, the sort is stable so stacked mounts keep their order
+        entries.sort_by_key(|entry| entry.mount_point.components().count());
+        for entry in entries {
+            let mut after = Vec::new();
+            if let Some(parent) = graph.innermost(&entry.mount_point) {
+                after.push(parent);
+            }
+            if let Some(source) = entry.source.path().filter(|_| !entry.source.is_device())
+                && let Some(job) = graph.innermost(&source)
+                && !after.contains(&job)
+            {
+                after.push(job);
+            }
+            graph.jobs.push(Job { entry: entry.clone(), after });
+        }
+        graph
+    }
+
+    /// The last job mounted on `path` or a directory above it
+    fn innermost(&self, path: &Path) -> Option<usize> {
+        self.jobs
+            .iter()
+            .enumerate()
+            .filter(|(_, job)| path.starts_with(&job.entry.mount_point))
+            .max_by_key(|(index, job)| (job.entry.mount_point.components().count(), *index))
+            .map(|(index, _)| index)
+    }
+
+    /// Runs every job, in parallel where they do not wait for each other
+    ///
+    /// Returns the result of every job, in the order of [`JobGraph::jobs`].
+    pub fn run(&self) -> Vec<Result<(), MountError>> {
+        enum State {
+            Waiting,
+            Running(Instant),
+            Done(Result<(), MountError>),
+        }
+        let mut states: Vec<State> = self.jobs.iter().map(|_| State::Waiting).collect();
+        let (sender, receiver) = mpsc::channel();
+        loop {
+            for (index, job) in self.jobs.iter().enumerate() {
+                if !matches!(states[index], State::Waiting) {
+                    continue;
+                }
+                let failed = job.after.iter().find(|after| matches!(states[**after], State::Done(Err(_))));
+                if let Some(after) = failed {
+                    let error = MountError::Dependency(self.jobs[*after].entry.mount_point.clone());
+                    states[index] = State::Done(Err(error));
+                } else if job.after.iter().all(|after| matches!(states[*after], State::Done(Ok(())))) {
+                    let (entry, timeout, sender) = (job.entry.clone(), job.timeout(), sender.clone());
+                    let deadline = Instant::now() + timeout;
+                    debug!("Mounting {} on {}", entry.source, entry.mount_point.display());
+                    // A job thread that times out is left behind, the kernel may still finish the mount
+                    std::thread::spawn(move || {
+                        let _ = sender.send((index, mount_entry(&entry, deadline, timeout)));
+                    });
+                    states[index] = State::Running(deadline);
+                }
+            }
+            let deadline = states.iter().filter_map(|state| if let State::Running(d) = state { Some(*d) } else { None }).min();
+            let Some(deadline) = deadline else {
+                break;
+            };
+            match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok((index, result)) => {
+                    if matches!(states[index], State::Running(_)) {
+                        states[index] = State::Done(result);
+                    }
+                }
+                Err(_) => {
+                    let now = Instant::now();
+                    for (index, state) in states.iter_mut().enumerate() {
+                        if matches!(state, State::Running(d) if *d <= now) {
+                            *state = State::Done(Err(MountError::Timeout(self.jobs[index].timeout())));
+                        }
+                    }
+                }
+            }
+        }
+        states
+            .into_iter()
+            .map(|state| match state {
+                State::Done(result) => result,
+                // Every job either ran or waited for one that failed
+                State::Waiting | State::Running(_) => unreachable!("mount job was never run"),
+            })
+            .collect()
+    }
+}
+
+/// Waits for the device of an entry and mounts it
+fn mount_entry(entry: &Entry, deadline: Instant, timeout: Duration) -> Result<(), MountError> {
+    let source = entry.source.path().map_or_else(|| entry.source.to_string(), |path| path.display().to_string());
+    if entry.source.is_device() {
+        let device = PathBuf::from(&source);
+        while !device.exists() {
+            if Instant::now() >= deadline {
+                return Err(MountError::NoDevice(device, timeout));
+            }
+            std::thread::sleep(DEVICE_POLL);
+        }
+    }
+    std::fs::create_dir_all(&entry.mount_point).map_err(|e| MountError::MountPoint(e.to_string()))?;
+    let (flags, data) = entry.mount_options();
+    let data = (!data.is_empty()).then_some(data.as_str());
+    let mount = |fs_type: &str| nix::mount::mount(Some(source.as_str()), &entry.mount_point, Some(fs_type), flags, data);
+    if entry.fs_type != "auto" {
+        return mount(&entry.fs_type).map_err(|errno| MountError::Mount { device: source.clone(), fs_type: entry.fs_type.clone(), errno });
+    }
+    let known = std::fs::read_to_string(PROC_FILESYSTEMS).unwrap_or_default();
+    let mut result = Err(nix::Error::ENODEV);
+    for fs_type in device_filesystems(&known) {
+        result = mount(fs_type);
+        // The kernel says `EINVAL` when the device does not hold that filesystem, like `mount` try the next one
+        if !matches!(result, Err(nix::Error::EINVAL)) {
+            break;
+        }
+    }
+    result.map_err(|errno| MountError::Mount { device: source, fs_type: entry.fs_type.clone(), errno })
+}
+
+/// The filesystems of `/proc/filesystems` that are on a device, lines of the others start with `nodev`
+fn device_filesystems(known: &str) -> impl Iterator<Item = &str> {
+    known.lines().filter(|line| !line.starts_with("nodev")).map(str::trim).filter(|fs_type| !fs_type.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fstab;
+
+    fn fixture(name: &str) -> Vec<Entry> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fstab").join(name);
+        let table = fstab::read(&path).unwrap();
+        assert_eq!(table.errors, Vec::new());
+        table.entries
+    }
+
+    fn mount_points(graph: &JobGraph) -> Vec<&str> {
+        graph.jobs.iter().map(|job| job.entry.mount_point.to_str().unwrap()).collect()
+    }
+
+    fn skipped(graph: &JobGraph) -> Vec<(&str, &str)> {
+        graph.skipped.iter().map(|(path, reason)| (path.to_str().unwrap(), reason.as_str())).collect()
+    }
+
+    #[test]
+    fn desktop() {
+        let graph = JobGraph::new(&fixture("desktop.fstab"), &[]);
+        assert_eq!(mount_points(&graph), ["/home", "/tmp", "/boot/efi", "/mnt/backup"]);
+        assert!(graph.jobs.iter().all(|job| job.after.is_empty()));
+        assert_eq!(skipped(&graph), [("/", "root filesystem"), ("none", "swap"), ("/media/camera", "noauto")]);
+        assert_eq!(graph.jobs[0].timeout(), DEFAULT_TIMEOUT);
+        assert_eq!(graph.jobs[3].timeout(), NOFAIL_TIMEOUT);
+    }
+
+    #[test]
+    fn server() {
+        let graph = JobGraph::new(&fixture("server.fstab"), &[]);
+        assert_eq!(
+            mount_points(&graph),
+            ["/var", "/srv", "/var/log", "/var/www", "/var/log/cache", "/var/lib/data"]
+        );
+        let after: Vec<&[usize]> = graph.jobs.iter().map(|job| job.after.as_slice()).collect();
+        // `/var/www` is in `/var` and its source is in `/srv`, `/var/log/cache` is in `/var/log`
+        assert_eq!(after, [&[][..], &[], &[0], &[0, 1], &[2, 1], &[0]]);
+        assert_eq!(skipped(&graph), [("/", "root filesystem"), ("/srv/media", "network filesystem")]);
+        assert_eq!(graph.jobs[2].timeout(), Duration::from_secs(30));
+        // An explicit timeout wins over the one of `nofail`
+        assert_eq!(graph.jobs[5].timeout(), Duration::from_secs(120));
+    }
+
+    #[test]
+    fn already_mounted() {
+        let mounted = mountinfo::parse(
+            "22 1 252:1 / / rw,relatime shared:1 - xfs /dev/vda1 rw\n\
+             23 22 252:17 / /var rw,relatime shared:2 - xfs /dev/vdb1 rw\n",
+        );
+        let graph = JobGraph::new(&fixture("server.fstab"), &mounted);
+        assert_eq!(mount_points(&graph), ["/srv", "/var/log", "/var/www", "/var/log/cache", "/var/lib/data"]);
+        assert!(skipped(&graph).contains(&("/var", "already mounted")));
+        // Nothing waits for `/var` once it is there
+        assert_eq!(graph.jobs[2].after, [0]);
+    }
+
+    #[test]
+    fn failed_dependency() {
+        // Nothing can create a directory in `/proc`, so the first job fails right away
+        let entries = fstab::parse("tmpfs /proc/easyinit-test tmpfs defaults 0 0\n/proc/easyinit-test/a /proc/easyinit-test/b none bind 0 0\n").entries;
+        let graph = JobGraph::new(&entries, &[]);
+        assert_eq!(graph.jobs[1].after, [0]);
+        let results = graph.run();
+        assert!(matches!(results[0], Err(MountError::MountPoint(_))));
+        assert_eq!(results[1], Err(MountError::Dependency(PathBuf::from("/proc/easyinit-test"))));
+    }
+
+    #[test]
+    fn auto_types() {
+        let known = "nodev\tsysfs\nnodev\ttmpfs\n\text4\n\tvfat\nnodev\tproc\n\txfs\n";
+        assert_eq!(device_filesystems(known).collect::<Vec<_>>(), ["ext4", "vfat", "xfs"]);
+    }
+}
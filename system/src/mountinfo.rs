@@ -0,0 +1,87 @@
+//! Reads the mount table from `/proc/self/mountinfo`
+use std::path::{Path, PathBuf};
+
+/// Where the mount table of easyinit is
+pub const PATH: &str = "/proc/self/mountinfo";
+
+/// A line of the mount table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    /// Unique id of the mount
+    pub id: u32,
+    /// Id of the mount this one is on
+    pub parent: u32,
+    /// The directory of the filesystem that is mounted, `/` unless it is a bind mount
+    pub root: PathBuf,
+    /// Where it is mounted
+    pub mount_point: PathBuf,
+    /// Options of the mount, like `rw` or `nosuid`
+    pub options: Vec<String>,
+    /// Type of the filesystem, like `ext4`
+    pub fs_type: String,
+    /// The device or other source, like `/dev/sda1` or `proc`
+    pub source: String,
+    /// Options of the filesystem
+    pub super_options: Vec<String>,
+}
+
+impl Mount {
+    /// If the mount is read only
+    pub fn is_read_only(&self) -> bool {
+        self.options.iter().any(|o| o == "ro")
+    }
+}
+
+/// Reads and parses a mount table, lines that cannot be parsed are skipped
+pub fn read(path: &Path) -> std::io::Result<Vec<Mount>> {
+    Ok(parse(&std::fs::read_to_string(path)?))
+}
+
+/// Parses a mount table that has already been read, lines that cannot be parsed are skipped
+pub fn parse(text: &str) -> Vec<Mount> {
+    text.lines().filter_map(parse_line).collect()
+}
+
+/// The mount on `path`, the last one if there are several stacked on it
+pub fn find<'a>(mounts: &'a [Mount], path: &Path) -> Option<&'a Mount> {
+    mounts.iter().rev().find(|m| m.mount_point == path)
+}
+
+/// `36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue`
+fn parse_line(line: &str) -> Option<Mount> {
+    let (before, after) = line.split_once(" - ")?;
+    let mut fields = before.split(' ');
+    let id = fields.next()?.parse().ok()?;
+    let parent = fields.next()?.parse().ok()?;
+    let _device = fields.next()?;
+    let root = PathBuf::from(unescape(fields.next()?));
+    let mount_point = PathBuf::from(unescape(fields.next()?));
+    let options = fields.next()?.split(',').map(str::to_string).collect();
+    // Optional fields like `shared:1` follow, they are not needed
+    let mut fields = after.split(' ');
+    let fs_type = fields.next()?.to_string();
+    let source = unescape(fields.next()?);
+    let super_options = fields.next().unwrap_or("").split(',').filter(|o| !o.is_empty()).map(str::to_string).collect();
+    Some(Mount { id, parent, root, mount_point, options, fs_type, source, super_options })
+}
+
+/// Undoes the octal escapes the kernel uses for spaces and other special characters, like `\040`
+pub(crate) fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4).filter(|d| d.iter().all(|b| (b'0'..=b'7').contains(b)));
+        match (bytes[i], octal) {
+            (b'\\', Some(digits)) => {
+                out.push(digits.iter().fold(0u8, |n, d| n.wrapping_mul(8) + (d - b'0')));
+                i += 4;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,272 @@
+//! Checks the root filesystem and mounts it read-write
+//!
+//! The kernel mounts the root filesystem read only when it gets `ro`, so it can be checked before
+//! anything writes to it. Afterwards it is remounted with the options of its `/etc/fstab` entry,
+//! or read-write unless `easyinit.root-mode=ro` when there is no entry. The kernel's `ro` or `rw`
+//! only decides how the root starts out, not how it is remounted.
+//!
+//! The check only runs while the root is still read only, with `fsck.<type>` from `PATH`:
+//!
+//! * `fsck.mode=auto` checks when the fstab entry has a pass number, the checker decides if it is needed
+//! * `fsck.mode=force` checks every boot
+//! * `fsck.mode=skip` never checks
+//!
+//! The exit code of the checker is a set of bits, see [`Check::from_code`]. Errors it could not
+//! correct stop the boot, and a corrected root that needs a reboot asks for one.
+use crate::fstab::{self, Entry};
+use crate::mountinfo::{self, Mount};
+use crate::startup::{MountBackend, SystemBackend};
+use config::fsck::{Mode, Repair};
+use logging::prelude::*;
+use nix::mount::MsFlags;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+/// `PATH` the checker is looked up in
+const PATH: &str = "/usr/local/sbin:/usr/sbin:/sbin:/usr/bin:/bin";
+
+/// Filesystems without a checker, they are never checked
+const NO_CHECK: &[&str] =
+    &["tmpfs", "ramfs", "rootfs", "overlay", "squashfs", "erofs", "nfs", "nfs4", "cifs", "9p", "virtiofs", "fuse"];
+
+/// How the root filesystem is handled, from the kernel command line and global configuration
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Settings {
+    /// When it is checked
+    pub mode: Mode,
+    /// What the check repairs
+    pub repair: Repair,
+    /// If it stays read only when `/etc/fstab` does not say
+    pub read_only: bool,
+}
+
+/// What the checker found, from its exit code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Check {
+    /// Nothing wrong
+    Clean,
+    /// Errors were found and corrected
+    Corrected,
+    /// Errors were corrected, but the system has to reboot before it uses the filesystem
+    RebootRequired,
+    /// Errors were found that are still there
+    Uncorrected,
+    /// The checker itself failed, the filesystem may not have been checked
+    Failed(String),
+}
+
+impl Check {
+    /// Reads the bits of the exit code of `fsck`
+    ///
+    /// `1` corrected, `2` reboot required, `4` uncorrected, anything higher is an error of the checker.
+    /// Uncorrected errors win over everything else, as the filesystem cannot be trusted.
+    pub fn from_code(code: i32) -> Self {
+        if code & 4 != 0 {
+            Check::Uncorrected
+        } else if code & 2 != 0 {
+            Check::RebootRequired
+        } else if code & !3 != 0 {
+            Check::Failed(format!("exit code {code}"))
+        } else if code & 1 != 0 {
+            Check::Corrected
+        } else {
+            Check::Clean
+        }
+    }
+
+    /// Reads the exit status of `fsck`, one killed by a signal failed
+    pub fn from_status(status: ExitStatus) -> Self {
+        status.code().map_or_else(|| Check::Failed(status.to_string()), Check::from_code)
+    }
+}
+
+/// How the boot goes on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The root filesystem is ready
+    Ready,
+    /// The check asked for a reboot
+    Reboot,
+    /// The root filesystem has errors, the boot should stop with why
+    Emergency(String),
+}
+
+/// A checker to run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fsck {
+    /// The program, like `fsck.ext4`
+    pub program: String,
+    /// Its arguments, ending with the device
+    pub args: Vec<String>,
+}
+
+/// What will be done to the root filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    /// The device or other source of the root filesystem
+    pub source: String,
+    /// Type of the root filesystem
+    pub fs_type: String,
+    /// The check, `Err` with why it is skipped
+    pub fsck: Result<Fsck, String>,
+    /// Flags and options to remount it with, `None` if it stays as it is
+    pub remount: Option<(MsFlags, String)>,
+}
+
+impl Plan {
+    /// Plans from the mount table and the filesystem table, `None` if the root is not in the mount table
+    pub fn new(mounts: &[Mount], entries: &[Entry], settings: &Settings) -> Option<Self> {
+        let mounted = mountinfo::find(mounts, Path::new("/"))?;
+        let entry = entries.iter().rfind(|entry| entry.mount_point == Path::new("/"));
+        let fs_type = match entry {
+            Some(entry) if !matches!(entry.fs_type.as_str(), "auto" | "none") => entry.fs_type.clone(),
+            _ => mounted.fs_type.clone(),
+        };
+        // The mount table names the real device, unless the kernel mounted it as `/dev/root`
+        let source = match entry.and_then(|entry| entry.source.path()) {
+            Some(path) if !mounted.source.starts_with('/') || mounted.source == "/dev/root" => path.display().to_string(),
+            _ => mounted.source.clone(),
+        };
+
+        let fsck = if !mounted.is_read_only() {
+            Err("it is mounted read-write".to_string())
+        } else if NO_CHECK.contains(&fs_type.as_str()) {
+            Err(format!("{fs_type} has no checker"))
+        } else if !source.starts_with('/') {
+            Err(format!("{source} is not a device"))
+        } else {
+            match settings.mode {
+                Mode::Skip => Err("fsck.mode=skip".to_string()),
+                Mode::Auto if entry.is_none_or(|entry| entry.pass == 0) => Err("it has no pass number in /etc/fstab".to_string()),
+                mode => {
+                    let mut args = vec![settings.repair.flag().to_string()];
+                    if mode == Mode::Force {
+                        args.push("-f".to_string());
+                    }
+                    args.push(source.clone());
+                    Ok(Fsck { program: format!("fsck.{fs_type}"), args })
+                }
+            }
+        };
+
+        // The fstab entry replaces the options, without one the current ones are kept
+        let (mut flags, data) = match entry {
+            Some(entry) => entry.mount_options(),
+            None => (fstab::mount_options(&mounted.options).0, String::new()),
+        };
+        let read_only = entry.map_or(settings.read_only, |_| flags.contains(MsFlags::MS_RDONLY));
+        flags.set(MsFlags::MS_RDONLY, read_only);
+        let remount = (read_only != mounted.is_read_only() || entry.is_some()).then_some((flags | MsFlags::MS_REMOUNT, data));
+        Some(Plan { source, fs_type, fsck, remount })
+    }
+
+    /// Runs the check with `fsck` and remounts through `backend`
+    pub fn run(&self, backend: &impl MountBackend, fsck: impl FnOnce(&Fsck) -> std::io::Result<ExitStatus>) -> Outcome {
+        match &self.fsck {
+            Ok(command) => {
+                info!("Checking the root filesystem {}", self.source);
+                let check = match fsck(command) {
+                    Ok(status) => Some(Check::from_status(status)),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        warn!("Not checking the root filesystem, {} is not installed", command.program);
+                        None
+                    }
+                    Err(e) => Some(Check::Failed(format!("Failed to start {}: {e}", command.program))),
+                };
+                match check {
+                    None => {}
+                    Some(Check::Clean) => debug!("The root filesystem is clean"),
+                    Some(Check::Corrected) => warn!("Errors on the root filesystem {} were corrected", self.source),
+                    Some(Check::RebootRequired) => return Outcome::Reboot,
+                    Some(Check::Uncorrected) => {
+                        return Outcome::Emergency(format!("The root filesystem {} has errors that were not corrected", self.source));
+                    }
+                    Some(Check::Failed(why)) => error!("Checking the root filesystem failed, booting anyway: {why}"),
+                }
+            }
+            Err(why) => debug!("Not checking the root filesystem: {why}"),
+        }
+        if let Some((flags, data)) = &self.remount {
+            let data = (!data.is_empty()).then_some(data.as_str());
+            let mode = if flags.contains(MsFlags::MS_RDONLY) { "read only" } else { "read-write" };
+            match backend.mount(&self.source, Path::new("/"), &self.fs_type, *flags, data) {
+                Ok(()) => info!("Remounted the root filesystem {mode}"),
+                Err(e) => error!("Failed to remount the root filesystem {mode}: {e}"),
+            }
+        }
+        Outcome::Ready
+    }
+}
+
+/// Checks and remounts the root filesystem of the running system, with the filesystem table at `fstab`
+pub fn check_and_remount(settings: &Settings, fstab: &Path) -> Outcome {
+    let mounts = SystemBackend.mounts();
+    let entries = match fstab::read(fstab) {
+        Ok(table) => table.entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            warn!("Failed to read {}, using the kernel command line for the root: {e}", fstab.display());
+            Vec::new()
+        }
+    };
+    let Some(plan) = Plan::new(&mounts, &entries, settings) else {
+        warn!("The root filesystem is not in the mount table, leaving it as it is");
+        return Outcome::Ready;
+    };
+    plan.run(&SystemBackend, |fsck| {
+        Command::new(&fsck.program).args(&fsck.args).env("PATH", PATH).stdin(Stdio::null()).status()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plans for a root mounted by the kernel with `options`, like `ro` from the kernel command line
+    fn plan(options: &str, fstab: &str, settings: Settings) -> Plan {
+        let mounts = mountinfo::parse(&format!("22 1 8:1 / / {options} shared:1 - ext4 /dev/sda1 {options}\n"));
+        Plan::new(&mounts, &fstab::parse(fstab).entries, &settings).unwrap()
+    }
+
+    /// If the root is remounted read only, `None` if it is not remounted
+    fn read_only(plan: &Plan) -> Option<bool> {
+        plan.remount.as_ref().map(|(flags, _)| flags.contains(MsFlags::MS_RDONLY))
+    }
+
+    const FSTAB: &str = "/dev/sda1 / ext4 defaults,noatime 0 1\n";
+
+    #[test]
+    fn kernel_ro_is_only_the_first_mount() {
+        // What bootloaders do, `ro` so the root can be checked, then fstab says read-write
+        let plan = plan("ro", FSTAB, Settings::default());
+        assert!(plan.fsck.is_ok());
+        assert_eq!(read_only(&plan), Some(false));
+        assert!(plan.remount.unwrap().0.contains(MsFlags::MS_NOATIME));
+    }
+
+    #[test]
+    fn fstab_decides() {
+        assert_eq!(read_only(&plan("ro", "/dev/sda1 / ext4 ro 0 1\n", Settings::default())), Some(true));
+        assert_eq!(read_only(&plan("rw", "/dev/sda1 / ext4 ro 0 1\n", Settings::default())), Some(true));
+        // `easyinit.root-mode` only counts without an entry
+        let settings = Settings { read_only: true, ..Settings::default() };
+        assert_eq!(read_only(&plan("ro", FSTAB, settings)), Some(false));
+    }
+
+    #[test]
+    fn without_an_entry() {
+        assert_eq!(read_only(&plan("ro", "", Settings::default())), Some(false));
+        assert_eq!(read_only(&plan("rw", "", Settings::default())), None);
+        let settings = Settings { read_only: true, ..Settings::default() };
+        assert_eq!(read_only(&plan("ro", "", settings)), None);
+        assert_eq!(read_only(&plan("rw", "", settings)), Some(true));
+    }
+
+    #[test]
+    fn fsck() {
+        let checked = plan("ro", FSTAB, Settings::default());
+        assert_eq!(checked.fsck, Ok(Fsck { program: "fsck.ext4".to_string(), args: vec!["-a".to_string(), "/dev/sda1".to_string()] }));
+        assert!(plan("rw", FSTAB, Settings::default()).fsck.is_err());
+        assert!(plan("ro", "/dev/sda1 / ext4 defaults 0 0\n", Settings::default()).fsck.is_err());
+    }
+}
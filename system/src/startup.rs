@@ -1,76 +1,709 @@
 //! This is stuff that needs to be started up like the mounted stuff, reads
 
+use config::mountpoint::{Policies,Policy as NonEmpty};
+use crate::fstab;
+use crate::mount::{JobGraph,MountError};
+use crate::mountinfo;
+use crate::virt::Detector;
+use logging::prelude::*;
 use nix::mount::{MsFlags,mount};
+use std::path::{Path,PathBuf};
 
 
+/// A filesystem the kernel provides, that easyinit and services expect to be mounted
+struct ApiFs{
+    /// Where it is mounted
+    path:&'static str,
+    /// Type of the filesystem, also used as the source
+    fs_type:&'static str,
+    /// Mount flags
+    flags:MsFlags,
+    /// Filesystem specific options
+    options:Option<&'static str>,
+    /// If the boot cannot go on without it, otherwise a failure is only logged
+    required:bool,
+    /// If something that is already mounted there is kept, outside of containers
+    /// 
+    /// In a container everything already mounted is kept, the container manager set it up
+    skip_if_mounted:bool,
+    /// If the mount point has to be empty, so nothing on the root filesystem gets hidden
+    empty:bool,
+    /// Type and options tried when the kernel does not support the type or refuses the options
+    fallback:Option<(&'static str, Option<&'static str>)>,
+}
+
+/// Flags most API filesystems are mounted with
+const SAFE:MsFlags = MsFlags::MS_NOSUID.union(MsFlags::MS_NOEXEC).union(MsFlags::MS_NODEV);
+
+/// The API filesystems, in the order they are mounted, parents first
+const API_FILESYSTEMS:&[ApiFs] = &[
+    ApiFs{path:"/proc", fs_type:"proc", flags:SAFE, options:None, required:true, skip_if_mounted:true, empty:false, fallback:None},
+    ApiFs{path:"/sys", fs_type:"sysfs", flags:SAFE, options:None, required:true, skip_if_mounted:true, empty:false, fallback:None},
+    ApiFs{path:"/dev", fs_type:"devtmpfs", flags:MsFlags::MS_NOSUID, options:Some("mode=0755"), required:true, skip_if_mounted:true, empty:false, fallback:Some(("tmpfs", Some("mode=0755")))},
+    ApiFs{path:"/dev/pts", fs_type:"devpts", flags:MsFlags::MS_NOSUID.union(MsFlags::MS_NOEXEC), options:Some("mode=0620,gid=5,ptmxmode=000"), required:false, skip_if_mounted:true, empty:false, fallback:None},
+    ApiFs{path:"/dev/shm", fs_type:"tmpfs", flags:MsFlags::MS_NOSUID.union(MsFlags::MS_NODEV), options:Some("mode=1777"), required:false, skip_if_mounted:true, empty:false, fallback:None},
+    ApiFs{path:"/run", fs_type:"tmpfs", flags:SAFE.union(MsFlags::MS_RELATIME), options:Some("mode=0755"), required:true, skip_if_mounted:true, empty:true, fallback:None},
+    // usrquota needs Linux 6.6, older kernels get a plain tmpfs
+    ApiFs{path:"/tmp", fs_type:"tmpfs", flags:SAFE.union(MsFlags::MS_RELATIME), options:Some("usrquota"), required:true, skip_if_mounted:true, empty:true, fallback:Some(("tmpfs", None))},
+    ApiFs{path:"/sys/fs/cgroup", fs_type:"cgroup2", flags:SAFE.union(MsFlags::MS_RELATIME), options:Some("nsdelegate"), required:false, skip_if_mounted:true, empty:false, fallback:None},
+    ApiFs{path:"/sys/kernel/security", fs_type:"securityfs", flags:SAFE, options:None, required:false, skip_if_mounted:true, empty:false, fallback:None},
+    ApiFs{path:"/sys/kernel/debug", fs_type:"debugfs", flags:SAFE, options:None, required:false, skip_if_mounted:true, empty:false, fallback:None},
+    ApiFs{path:"/sys/kernel/tracing", fs_type:"tracefs", flags:SAFE, options:None, required:false, skip_if_mounted:true, empty:false, fallback:None},
+    ApiFs{path:"/sys/kernel/config", fs_type:"configfs", flags:SAFE, options:None, required:false, skip_if_mounted:true, empty:false, fallback:None},
+    ApiFs{path:"/sys/fs/bpf", fs_type:"bpf", flags:SAFE, options:Some("mode=0700"), required:false, skip_if_mounted:true, empty:false, fallback:None},
+];
+
+/// Why an API filesystem could not be mounted
+#[derive(thiserror::Error, Debug)]
+pub enum StartupError{
+    /// `ENODEV`, the kernel does not support the filesystem type
+    #[error("The kernel does not support {fs_type}, needed for {path}")]
+    Unsupported{
+        /// The mount point
+        path:PathBuf,
+        /// Type of the filesystem
+        fs_type:String,
+    },
+    /// `EBUSY`, something is already mounted there or the source is in use
+    #[error("{} is busy", path.display())]
+    Busy{
+        /// The mount point
+        path:PathBuf,
+    },
+    /// `EPERM`, easyinit may not mount, like in an unprivileged container
+    #[error("Not permitted to mount {}", path.display())]
+    NotPermitted{
+        /// The mount point
+        path:PathBuf,
+    },
+    /// `ENOENT`, the mount point disappeared
+    #[error("{} does not exist", path.display())]
+    NotFound{
+        /// The mount point
+        path:PathBuf,
+    },
+    /// The mount point has files that the mount would hide
+    #[error("{} is not empty, it contains {}", path.display(), list(entries))]
+    NotEmpty{
+        /// The mount point
+        path:PathBuf,
+        /// What is in it
+        entries:Vec<PathBuf>,
+    },
+    /// The mount point could not be created or read
+    #[error("Failed to prepare the mount point {}: {source}", path.display())]
+    MountPoint{
+        /// The mount point
+        path:PathBuf,
+        /// What went wrong
+        source:std::io::Error,
+    },
+    /// Any other error from the kernel
+    #[error("Failed to mount {fs_type} on {}: {errno}", path.display())]
+    Other{
+        /// The mount point
+        path:PathBuf,
+        /// Type of the filesystem
+        fs_type:String,
+        /// Why it failed
+        errno:nix::Error,
+    },
+}
+
+/// What is done about a [`StartupError`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy{
+    /// Try the fallback type and options of the filesystem, if it has them
+    Fallback,
+    /// Log it and boot without the filesystem
+    Skip,
+    /// Stop the boot and start the emergency shell
+    Emergency,
+}
+
+impl StartupError{
+    /// Names the errno of a failed mount
+    pub fn from_errno(errno:nix::Error, path:&Path, fs_type:&str)->Self{
+        use nix::errno::Errno::*;
+        let path = path.to_path_buf();
+        match errno{
+            ENODEV => StartupError::Unsupported{path, fs_type:fs_type.to_string()},
+            EBUSY => StartupError::Busy{path},
+            EPERM => StartupError::NotPermitted{path},
+            ENOENT => StartupError::NotFound{path},
+            errno => StartupError::Other{path, fs_type:fs_type.to_string(), errno},
+        }
+    }
+
+    /// What to do about the error, for a filesystem that is `required` or not
+    /// 
+    /// The `fallback` is tried when there is one left and the kernel did not take the type or the
+    /// options. Otherwise optional filesystems are skipped. Required ones only when the filesystem is
+    /// most likely there already, or a container manager is in charge of mounts.
+    pub fn policy(&self, required:bool, container:bool, fallback:bool)->Policy{
+        match self{
+            StartupError::Unsupported{..} | StartupError::Other{errno:nix::errno::Errno::EINVAL, ..} if fallback => Policy::Fallback,
+            _ if !required => Policy::Skip,
+            // The initramfs or another mount got there first, what is mounted will do
+            StartupError::Busy{..} => Policy::Skip,
+            StartupError::NotPermitted{..} if container => Policy::Skip,
+            _ => Policy::Emergency,
+        }
+    }
+}
+
+/// Lists paths for a message, shortened if there are many
+fn list(paths:&[PathBuf])->String{
+    const SHOWN:usize = 5;
+    let mut names: Vec<String> = paths.iter().take(SHOWN).map(|p| p.display().to_string()).collect();
+    if paths.len() > SHOWN{
+        names.push(format!("and {} more", paths.len() - SHOWN));
+    }
+    names.join(", ")
+}
+
+/// Everything mounting the API filesystems does to the system, so it can be replaced in tests
+pub trait MountBackend{
+    /// Mounts a filesystem, see [`mount`]
+    fn mount(&self, source:&str, target:&Path, fs_type:&str, flags:MsFlags, data:Option<&str>)->nix::Result<()>;
+    /// The current mount table, empty while `/proc` is not mounted
+    fn mounts(&self)->Vec<mountinfo::Mount>;
+    /// Creates the mount point if it is missing, and returns what is in it
+    fn prepare(&self, path:&Path)->std::io::Result<Vec<PathBuf>>;
+    /// Mounts like [`MountBackend::mount`], then moves what was in the mount point into the new filesystem
+    /// 
+    /// Returns the files that could not be moved, like sockets, they are left hidden under the mount.
+    fn mount_moving(&self, source:&str, target:&Path, fs_type:&str, flags:MsFlags, data:Option<&str>)->nix::Result<Vec<(PathBuf,std::io::Error)>>;
+}
+
+/// Mounts on the running system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemBackend;
+
+impl MountBackend for SystemBackend{
+    fn mount(&self, source:&str, target:&Path, fs_type:&str, flags:MsFlags, data:Option<&str>)->nix::Result<()>{
+        mount(Some(source), target, Some(fs_type), flags, data)
+    }
+
+    fn mounts(&self)->Vec<mountinfo::Mount>{
+        mountinfo::read(Path::new(mountinfo::PATH)).unwrap_or_default()
+    }
+
+    fn prepare(&self, path:&Path)->std::io::Result<Vec<PathBuf>>{
+        match std::fs::read_dir(path){
+            Ok(entries) => entries.map(|entry| entry.map(|e| e.path())).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                std::fs::create_dir_all(path)?;
+                // If we created it, it's definitely empty
+                Ok(Vec::new())
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    fn mount_moving(&self, source:&str, target:&Path, fs_type:&str, flags:MsFlags, data:Option<&str>)->nix::Result<Vec<(PathBuf,std::io::Error)>>{
+        use std::os::fd::AsRawFd;
+        // An open directory still reaches the old files once the mount hides them
+        let hidden = std::fs::File::open(target);
+        self.mount(source, target, fs_type, flags, data)?;
+        let hidden = match hidden{
+            Ok(hidden) => hidden,
+            Err(e) => return Ok(vec![(target.to_path_buf(), e)]),
+        };
+        let old = PathBuf::from(format!("/proc/self/fd/{}", hidden.as_raw_fd()));
+        let entries = match std::fs::read_dir(&old){
+            Ok(entries) => entries,
+            Err(e) => return Ok(vec![(target.to_path_buf(), e)]),
+        };
+        let mut failed = Vec::new();
+        for entry in entries{
+            let entry = match entry{
+                Ok(entry) => entry,
+                Err(e) => { failed.push((target.to_path_buf(), e)); continue },
+            };
+            let to = target.join(entry.file_name());
+            match move_tree(&entry.path(), &to){
+                Ok(sockets) => failed.extend(sockets.into_iter().map(|socket| (target.join(socket), std::io::Error::other("a socket cannot be moved")))),
+                Err(e) => failed.push((to, e)),
+            }
+        }
+        Ok(failed)
+    }
+}
+
+/// Moves a file, directory, link, FIFO or device node to another filesystem, keeping its owner,
+/// permissions and times
+/// 
+/// Sockets belong to the program listening on them, they are left behind and returned relative
+/// to `from`'s parent. When the copy fails nothing is removed and what was copied is removed again.
+fn move_tree(from:&Path, to:&Path)->std::io::Result<Vec<PathBuf>>{
+    let mut sockets = Vec::new();
+    copy_tree(from, to, &mut sockets)?;
+    remove_tree(from)?;
+    let base = from.parent().unwrap_or(from);
+    Ok(sockets.into_iter().map(|socket| socket.strip_prefix(base).map(Path::to_path_buf).unwrap_or(socket)).collect())
+}
+
+/// Copies like [`move_tree`], adding the sockets it skipped to `sockets`
+/// 
+/// When it fails partway, what it created at `to` is removed again.
+fn copy_tree(from:&Path, to:&Path, sockets:&mut Vec<PathBuf>)->std::io::Result<()>{
+    use nix::sys::stat::{Mode,SFlag,mknod};
+    use std::os::unix::fs::{FileTypeExt,MetadataExt};
+    let meta = from.symlink_metadata()?;
+    let kind = meta.file_type();
+    if kind.is_socket(){
+        sockets.push(from.to_path_buf());
+        return Ok(());
+    }
+    if kind.is_dir(){
+        std::fs::create_dir(to)?;
+    }else if kind.is_symlink(){
+        std::os::unix::fs::symlink(std::fs::read_link(from)?, to)?;
+    }else if kind.is_file(){
+        std::fs::File::create_new(to)?;
+    }else{
+        // FIFOs and device nodes are made again
+        let node = SFlag::from_bits_truncate(meta.mode() & SFlag::S_IFMT.bits());
+        mknod(to, node, Mode::empty(), meta.rdev())?;
+    }
+    let copied = (||{
+        if kind.is_dir(){
+            for entry in std::fs::read_dir(from)?{
+                let entry = entry?;
+                copy_tree(&entry.path(), &to.join(entry.file_name()), sockets)?;
+            }
+        }else if kind.is_file(){
+            std::io::copy(&mut std::fs::File::open(from)?, &mut std::fs::OpenOptions::new().write(true).open(to)?)?;
+        }
+        std::os::unix::fs::lchown(to, Some(meta.uid()), Some(meta.gid()))?;
+        // After the owner, changing it clears the setuid bit
+        if !kind.is_symlink(){
+            std::fs::set_permissions(to, meta.permissions())?;
+        }
+        copy_times(&meta, to)
+    })();
+    if copied.is_err(){
+        let _ = if kind.is_dir(){ std::fs::remove_dir_all(to) }else{ std::fs::remove_file(to) };
+    }
+    copied
+}
+
+/// Gives `to` the access and modification times of `meta`, links themselves and not what they point to
+fn copy_times(meta:&std::fs::Metadata, to:&Path)->std::io::Result<()>{
+    use nix::sys::stat::{UtimensatFlags,utimensat};
+    use nix::sys::time::TimeSpec;
+    use std::os::unix::fs::MetadataExt;
+    let accessed = TimeSpec::new(meta.atime(), meta.atime_nsec());
+    let modified = TimeSpec::new(meta.mtime(), meta.mtime_nsec());
+    utimensat(nix::fcntl::AT_FDCWD, to, &accessed, &modified, UtimensatFlags::NoFollowSymlink)?;
+    Ok(())
+}
+
+/// Removes what [`copy_tree`] copied, the skipped sockets and the directories they are in stay
+fn remove_tree(path:&Path)->std::io::Result<()>{
+    use std::os::unix::fs::FileTypeExt;
+    let kind = path.symlink_metadata()?.file_type();
+    if kind.is_socket(){
+        return Ok(());
+    }
+    if !kind.is_dir(){
+        return std::fs::remove_file(path);
+    }
+    for entry in std::fs::read_dir(path)?{
+        remove_tree(&entry?.path())?;
+    }
+    match std::fs::remove_dir(path){
+        Err(e) if e.kind() == std::io::ErrorKind::DirectoryNotEmpty => Ok(()),
+        result => result,
+    }
+}
+
 /// This mounts filesystems that are required to run the system.
 /// 
-/// This includes, but not limited to, `/tmp`, `/run`
+/// This includes, but not limited to, `/proc`, `/sys`, `/dev`, `/tmp`, `/run` and the optional kernel filesystems like `debugfs`
 /// 
 /// Optionally `/usr`, but easyinit discourages `/usr` on a separate partition
 /// 
-/// These are required for easyinit to successfully boot.
+/// These are required for easyinit to successfully boot, an error is returned when one fails
+/// and the boot should go to the emergency shell.
 /// 
 /// These will be mounted with specific options but may be remounted later
-pub fn mount_needed_fs(){
-
-    
-    ensure_mountpoint_safe(std::path::Path::new("/tmp")).expect("Failed to ensure /tmp is safe to mount on");
-    
-    mount(
-        None::<&str>, // Errors Rust due to it cannot being inferred
-        "/tmp",
-        Some("tmpfs"),
-        MsFlags::MS_MGC_VAL | MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_RELATIME
-        
-        ,Some("usrquota")
-    ).unwrap_or_else(|e|{handle_needed_fs_errors(e, "/tmp", "tmpfs");});
-    
-    ensure_mountpoint_safe(std::path::Path::new("/run")).expect("Failed to ensure /run is safe to mount on");
-    mount(
-        None::<&str>,
-        "/run",
-        Some("tmpfs"),
-        MsFlags::MS_MGC_VAL | MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_RELATIME
-        
-        ,None::<&str>
-    ).unwrap_or_else(|e|{handle_needed_fs_errors(e, "/run", "sysfs");});
-}
-
-fn handle_needed_fs_errors(e: nix::Error, label:&str,fstype:&str){
-    use nix::errno::Errno::*;
-    // Some of these panic as t
-    match e{
-        ENODEV => panic!("Kernel does not support {fstype} filesystem, cannot continue"),   
-
-        _ => panic!("Unhandled errno: {e:?}" ) // Used if we miss one
-        
-        
-    }
-}
-
-/// Helper function to ensure that the mountpoint exists (if not, create it), and that it has no contents. This is for required mounts.
 /// 
-/// This is to avoid mounting over a non-empty directory, which can cause data loss.
-fn ensure_mountpoint_safe(path:&std::path::Path)->std::io::Result<()>{
-    use std::fs;
-   
-    match fs::metadata(path){
-        Ok(meta) => {
-            if !meta.is_dir(){
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("{path:?} exists but is not a directory")));
-            }
-            let mut entries = fs::read_dir(path)?;
-            if entries.next().is_some(){
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("{path:?} is not empty")));
-            }
-            Ok(())
+/// Filesystems the initramfs or a container manager already mounted are left alone
+/// 
+/// `/tmp` and `/run` that are not empty are handled as `policies` say
+pub fn mount_needed_fs(policies:&Policies)->Result<(),StartupError>{
+    let virtualization = Detector::system().detect();
+    info!("Running on {virtualization}");
+    mount_api_filesystems(&SystemBackend, virtualization.is_container(), policies)
+}
+
+/// Mounts `/proc` alone, so the kernel command line can be read before the other API filesystems
+/// 
+/// Without an initramfs nothing is mounted when easyinit starts. [`mount_needed_fs`] keeps it afterwards.
+pub fn mount_proc()->Result<(),StartupError>{
+    mount_proc_with(&SystemBackend)
+}
+
+/// Mounts `/proc` through `backend` unless it is mounted already, see [`mount_proc`]
+fn mount_proc_with(backend:&impl MountBackend)->Result<(),StartupError>{
+    let Some(proc) = API_FILESYSTEMS.iter().find(|fs| fs.path == "/proc") else{
+        return Ok(());
+    };
+    if mountinfo::find(&backend.mounts(), Path::new(proc.path)).is_some(){
+        return Ok(());
+    }
+    mount_api_fs(backend, proc, false, &Policies::default())
+}
+
+/// Mounts the API filesystems through `backend`, see [`mount_needed_fs`]
+pub fn mount_api_filesystems(backend:&impl MountBackend, container:bool, policies:&Policies)->Result<(),StartupError>{
+    for fs in API_FILESYSTEMS{
+        // Read again every time, the table only shows up once /proc is mounted
+        let mounts = backend.mounts();
+        if let Some(mounted) = mountinfo::find(&mounts, Path::new(fs.path))
+            && (fs.skip_if_mounted || container)
+        {
+            info!("{} is already mounted as {}, skipping", fs.path, mounted.fs_type);
+            continue;
+        }
+        match mount_api_fs(backend, fs, container, policies){
+            Ok(()) => debug!("Mounted {} on {}", fs.fs_type, fs.path),
+            // The fallback was already tried
+            Err(e) => match e.policy(fs.required, container, false){
+                Policy::Emergency | Policy::Fallback => return Err(e),
+                Policy::Skip if fs.required => warn!("Booting without {}: {e}", fs.path),
+                Policy::Skip => info!("Not mounting optional {}: {e}", fs.path),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Mounts a single API filesystem, with its fallback if the policy says so
+fn mount_api_fs(backend:&impl MountBackend, fs:&ApiFs, container:bool, policies:&Policies)->Result<(),StartupError>{
+    let path = Path::new(fs.path);
+    let entries = backend.prepare(path).map_err(|source| StartupError::MountPoint{path:path.to_path_buf(), source})?;
+    let policy = (fs.empty && !entries.is_empty()).then(|| policies.get(path));
+    match policy{
+        Some(NonEmpty::Refuse) => return Err(StartupError::NotEmpty{path:path.to_path_buf(), entries}),
+        Some(NonEmpty::Overmount) => warn!("Mounting over {}, hiding {}", fs.path, list(&entries)),
+        Some(NonEmpty::Move) | None => {},
+    }
+    let mount_with = |fs_type:&str, options:Option<&str>|{
+        if policy != Some(NonEmpty::Move){
+            return backend.mount(fs_type, path, fs_type, fs.flags, options);
+        }
+        info!("Moving {} into the new {fs_type} on {}", list(&entries), fs.path);
+        for (file, e) in backend.mount_moving(fs_type, path, fs_type, fs.flags, options)?{
+            warn!("Failed to move {}, it stays hidden under the mount: {e}", file.display());
+        }
+        Ok(())
+    };
+    let e = match mount_with(fs.fs_type, fs.options){
+        Ok(()) => return Ok(()),
+        Err(errno) => StartupError::from_errno(errno, path, fs.fs_type),
+    };
+    let Some((fs_type, options)) = fs.fallback.filter(|_| e.policy(fs.required, container, true) == Policy::Fallback) else{
+        return Err(e);
+    };
+    warn!("{e}, trying {fs_type}{}", options.map(|o| format!(" with {o}")).unwrap_or_default());
+    mount_with(fs_type, options).map_err(|errno| StartupError::from_errno(errno, path, fs_type))
+}
+
+/// Mounts the local filesystems in the filesystem table at `path`, network filesystems are left for later
+/// 
+/// Filesystems marked `nofail` are only logged when they fail, the others are returned
+pub fn mount_local_filesystems(path:&Path)->Vec<(PathBuf,MountError)>{
+    let table = match fstab::read(path){
+        Ok(table) => table,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            error!("Failed to read {}: {e}", path.display());
+            return Vec::new();
         },
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            fs::create_dir(path)?;
-            // If we created it, it's definitely empty
+    };
+    for e in &table.errors{
+        warn!("Skipping a line of {}: {e}", path.display());
+    }
+    let mounted = mountinfo::read(Path::new(mountinfo::PATH)).unwrap_or_default();
+    let graph = JobGraph::new(&table.entries, &mounted);
+    for (mount_point, reason) in &graph.skipped{
+        debug!("Not mounting {}: {reason}", mount_point.display());
+    }
+    let mut failed = Vec::new();
+    for (job, result) in graph.jobs.iter().zip(graph.run()){
+        let mount_point = job.entry.mount_point.display();
+        match result{
+            Ok(()) => info!("Mounted {mount_point}"),
+            Err(e) if job.entry.is_nofail() => warn!("Failed to mount {mount_point}, it is nofail: {e}"),
+            Err(e) => {
+                error!("Failed to mount {mount_point}: {e}");
+                failed.push((job.entry.mount_point.clone(), e));
+            },
+        }
+    }
+    failed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::errno::Errno;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use utils::testing::TempDir;
+
+    /// A backend that records the mounts it is asked for, and fails the ones it is told to
+    #[derive(Default)]
+    struct Fake {
+        /// The mount table, mounts that succeed are added to it
+        mounts: RefCell<Vec<mountinfo::Mount>>,
+        /// Every mount, like `tmpfs /tmp usrquota`
+        calls: RefCell<Vec<String>>,
+        /// Mounts that fail, by how they show up in `calls`
+        errors: HashMap<&'static str, Errno>,
+        /// What is in the mount points
+        files: HashMap<&'static str, Vec<PathBuf>>,
+    }
+
+    impl Fake {
+        fn failing(errors: &[(&'static str, Errno)]) -> Self {
+            Fake { errors: errors.iter().copied().collect(), ..Fake::default() }
+        }
+
+        fn mounted(self, paths: &[&str]) -> Self {
+            self.mounts.borrow_mut().extend(paths.iter().map(|path| mounted(path, "tmpfs")));
+            self
+        }
+
+        fn run(&self, container: bool) -> Result<(), StartupError> {
+            self.run_with(container, "refuse")
+        }
+
+        fn run_with(&self, container: bool, policies: &str) -> Result<(), StartupError> {
+            mount_api_filesystems(self, container, &policies.parse().unwrap())
+        }
+
+        fn with_files(mut self, path: &'static str, files: &[&str]) -> Self {
+            self.files.insert(path, files.iter().map(PathBuf::from).collect());
+            self
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.borrow().clone()
+        }
+
+        /// The mount points in the mount table
+        fn mounted_on(&self) -> Vec<String> {
+            self.mounts.borrow().iter().map(|m| m.mount_point.display().to_string()).collect()
+        }
+    }
+
+    fn mounted(path: &str, fs_type: &str) -> mountinfo::Mount {
+        mountinfo::Mount {
+            id: 1,
+            parent: 0,
+            root: PathBuf::from("/"),
+            mount_point: PathBuf::from(path),
+            options: vec!["rw".to_string()],
+            fs_type: fs_type.to_string(),
+            source: fs_type.to_string(),
+            super_options: Vec::new(),
+        }
+    }
+
+    impl MountBackend for Fake {
+        fn mount(&self, _source: &str, target: &Path, fs_type: &str, _flags: MsFlags, data: Option<&str>) -> nix::Result<()> {
+            let call = format!("{fs_type} {} {}", target.display(), data.unwrap_or("-"));
+            let error = self.errors.get(call.as_str()).copied();
+            self.calls.borrow_mut().push(call);
+            if let Some(errno) = error {
+                return Err(errno);
+            }
+            self.mounts.borrow_mut().push(mounted(target.to_str().unwrap(), fs_type));
             Ok(())
-        },
-        Err(e) => Err(e),
+        }
+
+        fn mounts(&self) -> Vec<mountinfo::Mount> {
+            self.mounts.borrow().clone()
+        }
+
+        fn prepare(&self, path: &Path) -> std::io::Result<Vec<PathBuf>> {
+            Ok(self.files.get(path.to_str().unwrap()).cloned().unwrap_or_default())
+        }
+
+        fn mount_moving(&self, source: &str, target: &Path, fs_type: &str, flags: MsFlags, data: Option<&str>) -> nix::Result<Vec<(PathBuf, std::io::Error)>> {
+            self.mount(source, target, fs_type, flags, data)?;
+            self.calls.borrow_mut().push(format!("move into {}", target.display()));
+            Ok(Vec::new())
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn mounts_everything() {
+        let fake = Fake::default();
+        fake.run(false).unwrap();
+        assert_eq!(fake.mounted_on(), API_FILESYSTEMS.iter().map(|fs| fs.path).collect::<Vec<_>>());
+        assert!(fake.calls().contains(&"tmpfs /tmp usrquota".to_string()));
+    }
+
+    #[test]
+    fn fallback() {
+        let fake = Fake::failing(&[("devtmpfs /dev mode=0755", Errno::ENODEV), ("tmpfs /tmp usrquota", Errno::EINVAL)]);
+        fake.run(false).unwrap();
+        let calls = fake.calls();
+        assert_eq!(calls[2..4], ["devtmpfs /dev mode=0755", "tmpfs /dev mode=0755"]);
+        assert!(calls.contains(&"tmpfs /tmp -".to_string()));
+        assert_eq!(fake.mounted_on().len(), API_FILESYSTEMS.len());
+    }
+
+    #[test]
+    fn required_fallback_fails() {
+        let fake = Fake::failing(&[("devtmpfs /dev mode=0755", Errno::ENODEV), ("tmpfs /dev mode=0755", Errno::ENODEV)]);
+        let e = fake.run(false).unwrap_err();
+        assert!(matches!(&e, StartupError::Unsupported { fs_type, .. } if fs_type == "tmpfs"), "{e}");
+        assert_eq!(fake.mounted_on(), ["/proc", "/sys"]);
+
+        // Without a fallback the first error stops the boot
+        let fake = Fake::failing(&[("proc /proc -", Errno::ENODEV)]);
+        assert!(matches!(fake.run(false), Err(StartupError::Unsupported { .. })));
+        let fake = Fake::failing(&[("tmpfs /run mode=0755", Errno::EINVAL)]);
+        assert!(matches!(fake.run(false), Err(StartupError::Other { errno: Errno::EINVAL, .. })));
+    }
+
+    #[test]
+    fn skip() {
+        let fake = Fake::failing(&[
+            ("debugfs /sys/kernel/debug -", Errno::ENODEV),
+            ("devpts /dev/pts mode=0620,gid=5,ptmxmode=000", Errno::EPERM),
+            // Something got there first, it will do
+            ("tmpfs /run mode=0755", Errno::EBUSY),
+        ]);
+        fake.run(false).unwrap();
+        let mounted = fake.mounted_on();
+        assert!(!mounted.contains(&"/sys/kernel/debug".to_string()) && !mounted.contains(&"/run".to_string()));
+        assert_eq!(mounted.last().map(String::as_str), Some("/sys/fs/bpf"));
+    }
+
+    #[test]
+    fn emergency() {
+        let fake = Fake::failing(&[("sysfs /sys -", Errno::EPERM)]);
+        assert!(matches!(fake.run(false), Err(StartupError::NotPermitted { .. })));
+        assert_eq!(fake.calls(), ["proc /proc -", "sysfs /sys -"]);
+
+        let fake = Fake::failing(&[("tmpfs /tmp usrquota", Errno::EIO)]);
+        assert!(matches!(fake.run(false), Err(StartupError::Other { errno: Errno::EIO, .. })));
+    }
+
+    #[test]
+    fn already_mounted() {
+        let fake = Fake::default().mounted(&["/proc", "/sys", "/dev", "/run", "/tmp"]);
+        fake.run(false).unwrap();
+        let calls = fake.calls();
+        assert!(calls.iter().all(|call| !call.contains(" /proc ") && !call.contains(" /run ") && !call.contains(" /tmp ")));
+        assert_eq!(calls[0], "devpts /dev/pts mode=0620,gid=5,ptmxmode=000");
+    }
+
+    #[test]
+    fn container() {
+        let fake = Fake::failing(&[("sysfs /sys -", Errno::EPERM), ("tmpfs /run mode=0755", Errno::EPERM)]).mounted(&["/proc", "/dev"]);
+        fake.run(true).unwrap();
+        let calls = fake.calls();
+        assert!(calls.iter().all(|call| !call.contains(" /proc ") && !call.contains(" /dev ")));
+        assert!(calls.contains(&"tmpfs /tmp usrquota".to_string()));
+        // The container manager keeps /sys to itself, the boot goes on without it
+        assert!(!fake.mounted_on().contains(&"/sys".to_string()));
+    }
+
+    #[test]
+    fn proc_first() {
+        let fake = Fake::default();
+        mount_proc_with(&fake).unwrap();
+        assert_eq!(fake.calls(), ["proc /proc -"]);
+        // Mounting the rest keeps it
+        fake.run(false).unwrap();
+        assert_eq!(fake.calls().iter().filter(|call| call.contains(" /proc ")).count(), 1);
+
+        let fake = Fake::default().mounted(&["/proc"]);
+        mount_proc_with(&fake).unwrap();
+        assert!(fake.calls().is_empty());
+
+        let fake = Fake::failing(&[("proc /proc -", Errno::EPERM)]);
+        assert!(matches!(mount_proc_with(&fake), Err(StartupError::NotPermitted { .. })));
+    }
+
+    #[test]
+    fn refuse() {
+        let fake = Fake::default().with_files("/run", &["/run/leftover"]);
+        let e = fake.run_with(false, "overmount,/run=refuse").unwrap_err();
+        assert!(matches!(&e, StartupError::NotEmpty { entries, .. } if entries == &[PathBuf::from("/run/leftover")]), "{e}");
+        assert!(fake.calls().iter().all(|call| !call.contains(" /run ")));
+
+        // Only /tmp and /run have to be empty
+        let fake = Fake::default().with_files("/dev", &["/dev/console"]);
+        fake.run(false).unwrap();
+    }
+
+    #[test]
+    fn overmount() {
+        let fake = Fake::default().with_files("/run", &["/run/leftover"]).with_files("/tmp", &["/tmp/leftover"]);
+        fake.run_with(false, "overmount").unwrap();
+        assert!(fake.calls().iter().all(|call| !call.starts_with("move")));
+        assert_eq!(fake.mounted_on().len(), API_FILESYSTEMS.len());
+    }
+
+    #[test]
+    fn move_files() {
+        let fake = Fake::failing(&[("tmpfs /tmp usrquota", Errno::EINVAL)]).with_files("/tmp", &["/tmp/leftover"]);
+        fake.run_with(false, "move").unwrap();
+        let calls = fake.calls();
+        // The fallback moves the files too
+        let tmp: Vec<&String> = calls.iter().filter(|call| call.contains(" /tmp")).collect();
+        assert_eq!(tmp, ["tmpfs /tmp usrquota", "tmpfs /tmp -", "move into /tmp"]);
+        assert!(!calls.contains(&"move into /run".to_string()));
+    }
+
+    #[test]
+    fn move_tree_keeps_metadata() {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+        let dir = TempDir::new("move");
+        let (from, to) = (dir.join("from/leftover"), dir.join("to/leftover"));
+        std::fs::create_dir_all(from.join("sub")).unwrap();
+        std::fs::create_dir_all(dir.join("to")).unwrap();
+        let file = std::fs::File::create(from.join("sub/file")).unwrap();
+        std::io::Write::write_all(&mut &file, b"contents").unwrap();
+        let modified = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        file.set_modified(modified).unwrap();
+        std::fs::set_permissions(from.join("sub/file"), std::fs::Permissions::from_mode(0o640)).unwrap();
+        std::os::unix::fs::symlink("sub/file", from.join("link")).unwrap();
+        nix::unistd::mkfifo(&from.join("fifo"), nix::sys::stat::Mode::from_bits_truncate(0o600)).unwrap();
+        let _listener = std::os::unix::net::UnixListener::bind(from.join("sub/socket")).unwrap();
+
+        let sockets = move_tree(&from, &to).unwrap();
+        assert_eq!(sockets, [PathBuf::from("leftover/sub/socket")]);
+        assert_eq!(std::fs::read(to.join("sub/file")).unwrap(), b"contents");
+        let meta = to.join("sub/file").metadata().unwrap();
+        assert_eq!((meta.modified().unwrap(), meta.permissions().mode() & 0o777), (modified, 0o640));
+        assert_eq!(std::fs::read_link(to.join("link")).unwrap(), Path::new("sub/file"));
+        assert!(to.join("fifo").symlink_metadata().unwrap().file_type().is_fifo());
+        assert!(!to.join("sub/socket").exists());
+        // Only the socket and the directory it is in stay behind
+        assert!(from.join("sub/socket").exists());
+        assert!(!from.join("sub/file").exists() && !from.join("fifo").exists() && !from.join("link").exists());
+    }
+
+    #[test]
+    fn failed_move_keeps_everything() {
+        let dir = TempDir::new("failed-move");
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        std::fs::create_dir_all(&from).unwrap();
+        std::fs::write(from.join("file"), b"new").unwrap();
+        std::fs::create_dir_all(&to).unwrap();
+        std::fs::write(to.join("file"), b"old").unwrap();
+        assert!(move_tree(&from.join("file"), &to.join("file")).is_err());
+        assert_eq!(std::fs::read(from.join("file")).unwrap(), b"new");
+        assert_eq!(std::fs::read(to.join("file")).unwrap(), b"old");
+    }
+}
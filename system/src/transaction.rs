@@ -0,0 +1,274 @@
+//! Works out which services a target starts, and in what order
+//!
+//! A target starts everything it `wants` or `requires`, and everything that is `wanted-by` or
+//! `required-by` it, and so on for each of those. The target itself does not need a definition,
+//! `default` can be nothing more than the services that install themselves into it.
+//!
+//! Services are ordered by `after` and `before`, otherwise by name, and the target comes last.
+//! Masked services are left out, and so are the services that require one.
+use crate::manager::Manager;
+use config::service::ServiceDefinition;
+use logging::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The services to start for a target
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transaction {
+    /// Services in the order they are started, including the target if it has a definition
+    pub order: Vec<String>,
+    /// Services that were left out, with why
+    pub skipped: Vec<(String, String)>,
+}
+
+impl Transaction {
+    /// Plans booting `target`, with `wants` started on top and `masked` left out
+    pub fn plan(manager: &Manager, target: &str, wants: &[String], masked: &BTreeSet<String>) -> Self {
+        let mut transaction = Transaction::default();
+        let mut definitions = BTreeMap::new();
+        let mut queue: Vec<String> = std::iter::once(target.to_string()).chain(wants.iter().cloned()).collect();
+        let mut seen = BTreeSet::new();
+
+        while let Some(name) = queue.pop() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            if masked.contains(&name) {
+                transaction.skipped.push((name, "masked".to_string()));
+                continue;
+            }
+            let definition = match manager.definition(&name) {
+                Ok(definition) => Some(definition),
+                // The target does not need a definition of its own
+                Err(_) if name == target => None,
+                Err(e) => {
+                    transaction.skipped.push((name, e.to_string()));
+                    continue;
+                }
+            };
+            if let Some(definition) = &definition {
+                queue.extend(definition.dependencies.wants.iter().cloned());
+                queue.extend(definition.dependencies.requires.iter().cloned());
+            }
+            queue.extend(installed_into(manager, &name));
+            if let Some(definition) = definition {
+                definitions.insert(name, definition);
+            }
+        }
+
+        transaction.skip_missing_requirements(&mut definitions, manager, target);
+        transaction.order = order(&definitions);
+        // A target is reached once everything it pulled in is started
+        if let Some(position) = transaction.order.iter().position(|name| name == target) {
+            let target = transaction.order.remove(position);
+            transaction.order.push(target);
+        }
+        transaction
+    }
+
+    /// Leaves out services that require one that is not started, until nothing changes
+    fn skip_missing_requirements(&mut self, definitions: &mut BTreeMap<String, ServiceDefinition>, manager: &Manager, target: &str) {
+        loop {
+            let missing = definitions.iter().find_map(|(name, definition)| {
+                let required = definition.dependencies.requires.iter().cloned().chain(
+                    // `required-by` is the same as the other service requiring this one
+                    manager
+                        .services()
+                        .iter()
+                        .filter(|(_, other)| other.definition.install.required_by.contains(name))
+                        .map(|(other, _)| other.clone()),
+                );
+                // The target is always started, even without a definition
+                required
+                    .filter(|required| required != target)
+                    .find(|required| !definitions.contains_key(required))
+                    .map(|required| (name.clone(), required))
+            });
+            let Some((name, required)) = missing else {
+                return;
+            };
+            definitions.remove(&name);
+            self.skipped.push((name, format!("requires {required}, which is not started")));
+        }
+    }
+}
+
+/// Services that are `wanted-by` or `required-by` the named one
+fn installed_into(manager: &Manager, name: &str) -> Vec<String> {
+    manager
+        .services()
+        .iter()
+        .filter(|(_, service)| {
+            let install = &service.definition.install;
+            install.wanted_by.iter().chain(&install.required_by).any(|into| into == name)
+        })
+        .map(|(other, _)| other.clone())
+        .collect()
+}
+
+/// Orders the services by `after` and `before`, a cycle is broken at the name that sorts first
+fn order(definitions: &BTreeMap<String, ServiceDefinition>) -> Vec<String> {
+    // For every service, the services that have to be started before it
+    let mut before: BTreeMap<&str, BTreeSet<&str>> = definitions.keys().map(|name| (name.as_str(), BTreeSet::new())).collect();
+    for (name, definition) in definitions {
+        for after in &definition.dependencies.after {
+            if definitions.contains_key(after) {
+                before.entry(name).or_default().insert(after);
+            }
+        }
+        for later in &definition.dependencies.before {
+            if definitions.contains_key(later) {
+                before.entry(later).or_default().insert(name);
+            }
+        }
+    }
+    let mut order = Vec::with_capacity(definitions.len());
+    while !before.is_empty() {
+        let ready = before.iter().find(|(_, waiting)| waiting.is_empty()).map(|(name, _)| *name);
+        let next = ready.unwrap_or_else(|| {
+            let cycle = cycle(&before);
+            let name = cycle.iter().min().copied().unwrap_or_default();
+            warn!("Ordering cycle between {cycle:?}, starting {name} first");
+            name
+        });
+        before.remove(next);
+        for waiting in before.values_mut() {
+            waiting.remove(next);
+        }
+        order.push(next.to_string());
+    }
+    order
+}
+
+/// A cycle in `before`, when no service is ready to start
+///
+/// Every service waits for another one, so following them from any service ends in a cycle.
+fn cycle<'a>(before: &BTreeMap<&'a str, BTreeSet<&'a str>>) -> Vec<&'a str> {
+    let mut path = Vec::new();
+    let mut next = before.keys().next().copied();
+    while let Some(name) = next {
+        if let Some(start) = path.iter().position(|visited| *visited == name) {
+            return path.split_off(start);
+        }
+        path.push(name);
+        next = before.get(name).and_then(|waiting| waiting.first().copied());
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::loader::ServiceDirs;
+    use utils::testing::TempDir;
+
+    /// A manager with the definitions in `services`, by name
+    fn manager(dir: &TempDir, services: &[(&str, &str)]) -> Manager {
+        for (name, text) in services {
+            dir.write(format!("{name}.toml"), text);
+        }
+        let mut manager = Manager::new(ServiceDirs::new(vec![dir.path().to_path_buf()]));
+        manager.reload();
+        manager
+    }
+
+    /// Why each service was skipped
+    fn skipped(transaction: &Transaction) -> BTreeMap<&str, &str> {
+        transaction.skipped.iter().map(|(name, why)| (name.as_str(), why.as_str())).collect()
+    }
+
+    #[test]
+    fn wants_and_installed_into() {
+        let dir = TempDir::new("transaction-wants");
+        let manager = manager(
+            &dir,
+            &[
+                ("default", "[dependencies]\nwants = [\"network\"]\n"),
+                ("network", "[dependencies]\nrequires = [\"udev\"]\n"),
+                ("udev", ""),
+                ("sshd", "[install]\nwanted-by = [\"default\"]\n[dependencies]\nafter = [\"network\"]\n"),
+                ("cron", "[install]\nrequired-by = [\"sshd\"]\n"),
+                ("unused", "[install]\nwanted-by = [\"rescue\"]\n"),
+            ],
+        );
+        let transaction = Transaction::plan(&manager, "default", &["extra".to_string()], &BTreeSet::new());
+        // Requiring a service does not order it
+        assert_eq!(transaction.order, ["cron", "network", "sshd", "udev", "default"]);
+        assert_eq!(skipped(&transaction), BTreeMap::from([("extra", "No definition found for `extra`")]));
+
+        // A target without a definition is only what is installed into it
+        let transaction = Transaction::plan(&manager, "rescue", &["udev".to_string()], &BTreeSet::new());
+        assert_eq!(transaction.order, ["udev", "unused"]);
+        assert!(transaction.skipped.is_empty());
+    }
+
+    #[test]
+    fn masked_and_missing_requirements() {
+        let dir = TempDir::new("transaction-masked");
+        let manager = manager(
+            &dir,
+            &[
+                ("web", "[install]\nwanted-by = [\"default\"]\n[dependencies]\nrequires = [\"database\"]\n"),
+                ("database", "[install]\nwanted-by = [\"default\"]\n"),
+                ("proxy", "[install]\nwanted-by = [\"default\"]\n[dependencies]\nrequires = [\"web\"]\n"),
+                ("cache", "[install]\nwanted-by = [\"default\"]\n[dependencies]\nwants = [\"database\"]\n"),
+                ("mail", "[install]\nwanted-by = [\"default\"]\n[dependencies]\nrequires = [\"spool\"]\n"),
+                ("backup", "[install]\nwanted-by = [\"default\"]\n"),
+                ("monitor", "[install]\nwanted-by = [\"default\"]\nrequired-by = [\"backup\"]\n"),
+            ],
+        );
+        let masked = BTreeSet::from(["database".to_string(), "monitor".to_string()]);
+        let transaction = Transaction::plan(&manager, "default", &[], &masked);
+        // Only wanting a masked service still starts
+        assert_eq!(transaction.order, ["cache"]);
+        assert_eq!(
+            skipped(&transaction),
+            BTreeMap::from([
+                ("backup", "requires monitor, which is not started"),
+                ("database", "masked"),
+                ("mail", "requires spool, which is not started"),
+                ("monitor", "masked"),
+                ("proxy", "requires web, which is not started"),
+                ("spool", "No definition found for `spool`"),
+                ("web", "requires database, which is not started"),
+            ])
+        );
+    }
+
+    #[test]
+    fn ordering() {
+        let dir = TempDir::new("transaction-order");
+        let manager = manager(
+            &dir,
+            &[
+                ("default", "[dependencies]\nwants = [\"a\", \"b\", \"c\", \"d\", \"e\"]\nbefore = [\"a\"]\n"),
+                ("a", "[dependencies]\nafter = [\"b\", \"missing\"]\n"),
+                ("b", "[dependencies]\nafter = [\"c\"]\n"),
+                ("c", ""),
+                ("d", "[dependencies]\nbefore = [\"c\"]\n"),
+                ("e", "[dependencies]\nafter = [\"d\"]\n"),
+            ],
+        );
+        let transaction = Transaction::plan(&manager, "default", &[], &BTreeSet::new());
+        // The target comes last, even when it is ordered before something
+        assert_eq!(transaction.order, ["d", "c", "b", "a", "e", "default"]);
+    }
+
+    #[test]
+    fn cycles() {
+        let dir = TempDir::new("transaction-cycles");
+        let manager = manager(
+            &dir,
+            &[
+                ("default", "[dependencies]\nwants = [\"x\", \"y\", \"z\", \"after-cycle\"]\n"),
+                ("x", "[dependencies]\nafter = [\"z\"]\n"),
+                ("y", "[dependencies]\nafter = [\"x\"]\n"),
+                ("z", "[dependencies]\nafter = [\"y\"]\n"),
+                ("after-cycle", "[dependencies]\nafter = [\"z\"]\n"),
+            ],
+        );
+        let transaction = Transaction::plan(&manager, "default", &[], &BTreeSet::new());
+        // Broken at the first name in the cycle, not the first name waiting for it
+        assert_eq!(transaction.order, ["x", "y", "z", "after-cycle", "default"]);
+        assert!(transaction.skipped.is_empty());
+    }
+}
@@ -0,0 +1,372 @@
+//! Finds out if easyinit runs in a virtual machine or a container
+//!
+//! Containers are looked for first, as a container in a virtual machine sees the machine's
+//! hardware:
+//!
+//! 1. the `container=` variable container managers give init, from the environment or `/proc/1/environ`
+//! 2. `/run/.containerenv` from podman, `/.dockerenv` from docker, `/run/systemd/container` from nspawn
+//! 3. the cgroup of init, like `/docker/<id>` or `/kubepods/...`
+//! 4. the kernel release of WSL
+//!
+//! Then hypervisors:
+//!
+//! 1. `/sys/hypervisor/type`, set by Xen
+//! 2. the vendor in the DMI tables under `/sys/class/dmi/id`
+//! 3. the hypervisor signature from `cpuid`, on x86
+//! 4. the `hypervisor` flag in `/proc/cpuinfo`, for hypervisors nothing else knows about
+//!
+//! Every file is read under a root, so [`Detector::with_root`] can look at a fake `/proc` and `/sys`.
+use std::path::{Path, PathBuf};
+
+/// DMI files that may contain the name of a hypervisor
+const DMI_FILES: &[&str] = &["sys_vendor", "product_name", "board_vendor", "bios_vendor", "product_version"];
+/// How hypervisors name themselves in the DMI tables
+const DMI_VENDORS: &[(&str, &str)] = &[
+    ("KVM", "kvm"),
+    ("OpenStack", "kvm"),
+    ("KubeVirt", "kvm"),
+    ("Amazon EC2", "amazon"),
+    ("QEMU", "qemu"),
+    ("VMware", "vmware"),
+    ("VMW", "vmware"),
+    ("innotek GmbH", "oracle"),
+    ("VirtualBox", "oracle"),
+    ("Oracle Corporation", "oracle"),
+    ("Xen", "xen"),
+    ("Bochs", "bochs"),
+    ("Parallels", "parallels"),
+    ("BHYVE", "bhyve"),
+    ("Hyper-V", "microsoft"),
+    ("Apple Virtualization", "apple"),
+    ("Google Compute Engine", "google"),
+];
+
+/// `cpuid` hypervisor signatures
+const CPUID_VENDORS: &[(&str, &str)] = &[
+    ("KVMKVMKVM", "kvm"),
+    ("Linux KVM Hv", "kvm"),
+    ("TCGTCGTCGTCG", "qemu"),
+    ("VMwareVMware", "vmware"),
+    ("XenVMMXenVMM", "xen"),
+    ("Microsoft Hv", "microsoft"),
+    ("bhyve bhyve ", "bhyve"),
+    ("QNXQVMBSQG", "qnx"),
+    ("ACRNACRNACRN", "acrn"),
+    (" lrpepyh  vr", "parallels"),
+    ("SRESRESRESRE", "sre"),
+    ("VBoxVBoxVBox", "oracle"),
+];
+
+/// Parts of init's cgroup path that give away a container manager
+const CGROUP_CONTAINERS: &[(&str, &str)] = &[
+    ("/docker/", "docker"),
+    ("/docker-", "docker"),
+    ("/libpod-", "podman"),
+    ("/kubepods", "kubernetes"),
+    ("/lxc.payload", "lxc"),
+    ("/lxc/", "lxc"),
+];
+
+/// What easyinit runs on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Virtualization {
+    /// Real hardware
+    None,
+    /// A virtual machine, with the name of the hypervisor like `kvm`
+    Vm(String),
+    /// A container, with the name of the container manager like `docker`
+    Container(String),
+}
+
+impl Virtualization {
+    /// The name of the hypervisor or container manager, `none` on real hardware
+    pub fn name(&self) -> &str {
+        match self {
+            Virtualization::None => "none",
+            Virtualization::Vm(name) | Virtualization::Container(name) => name,
+        }
+    }
+
+    /// If this is a container
+    pub fn is_container(&self) -> bool {
+        matches!(self, Virtualization::Container(_))
+    }
+
+    /// If `value` describes this, `value` is `yes`, `no`, `vm`, `container` or a name
+    pub fn matches(&self, value: &str) -> bool {
+        match value {
+            "yes" | "true" => *self != Virtualization::None,
+            "no" | "false" | "none" => *self == Virtualization::None,
+            "vm" => matches!(self, Virtualization::Vm(_)),
+            "container" => self.is_container(),
+            name => self.name() == name,
+        }
+    }
+}
+
+impl std::fmt::Display for Virtualization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Virtualization::None => f.write_str("none"),
+            Virtualization::Vm(name) => write!(f, "vm {name}"),
+            Virtualization::Container(name) => write!(f, "container {name}"),
+        }
+    }
+}
+
+/// Where the `cpuid` signature comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Cpuid {
+    /// The CPU easyinit runs on
+    Native,
+    /// A fixed signature, `None` for a CPU without a hypervisor
+    Fixed(Option<String>),
+}
+
+/// Looks for virtualization
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detector {
+    root: PathBuf,
+    /// `container=` from easyinit's own environment
+    environment: Option<String>,
+    cpuid: Cpuid,
+}
+
+impl Detector {
+    /// Looks at the running system
+    pub fn system() -> Self {
+        Detector {
+            root: PathBuf::from("/"),
+            environment: std::env::var("container").ok(),
+            cpuid: Cpuid::Native,
+        }
+    }
+
+    /// Looks at files under a different root, without `cpuid` or the environment
+    pub fn with_root(root: &Path) -> Self {
+        Detector { root: root.to_path_buf(), environment: None, cpuid: Cpuid::Fixed(None) }
+    }
+
+    /// Uses a fixed `cpuid` hypervisor signature, like `KVMKVMKVM`
+    pub fn cpuid_signature(mut self, signature: Option<&str>) -> Self {
+        self.cpuid = Cpuid::Fixed(signature.map(str::to_string));
+        self
+    }
+
+    /// Looks for a container and then a hypervisor
+    pub fn detect(&self) -> Virtualization {
+        if let Some(name) = self.container() {
+            return Virtualization::Container(name);
+        }
+        if let Some(name) = self.vm() {
+            return Virtualization::Vm(name);
+        }
+        Virtualization::None
+    }
+
+    /// The container manager, if there is one
+    pub fn container(&self) -> Option<String> {
+        if let Some(name) = self.environment.clone().filter(|name| !name.is_empty()) {
+            return Some(name);
+        }
+        if let Ok(environ) = std::fs::read(self.path("proc/1/environ"))
+            && let Some(name) = environ.split(|b| *b == 0).find_map(|var| var.strip_prefix(b"container="))
+            && !name.is_empty()
+        {
+            return Some(String::from_utf8_lossy(name).into_owned());
+        }
+        if self.path("run/.containerenv").exists() {
+            return Some("podman".to_string());
+        }
+        if self.path(".dockerenv").exists() {
+            return Some("docker".to_string());
+        }
+        if let Some(name) = self.read("run/systemd/container") {
+            return Some(name);
+        }
+        if let Some(cgroup) = self.read("proc/1/cgroup")
+            && let Some((_, name)) = CGROUP_CONTAINERS.iter().find(|(part, _)| cgroup.contains(part))
+        {
+            return Some(name.to_string());
+        }
+        let release = self.read("proc/sys/kernel/osrelease").unwrap_or_default();
+        (release.contains("Microsoft") || release.contains("WSL")).then(|| "wsl".to_string())
+    }
+
+    /// The hypervisor, if there is one
+    pub fn vm(&self) -> Option<String> {
+        if let Some(name) = self.read("sys/hypervisor/type") {
+            return Some(name);
+        }
+        let dmi = DMI_FILES.iter().filter_map(|file| self.read(&format!("sys/class/dmi/id/{file}")));
+        for value in dmi {
+            if let Some((_, name)) = DMI_VENDORS.iter().find(|(vendor, _)| value.starts_with(vendor)) {
+                return Some(name.to_string());
+            }
+            // Hyper-V only says it is Microsoft's in the vendor, and a virtual machine in the product
+            if value == "Virtual Machine" {
+                return Some("microsoft".to_string());
+            }
+        }
+        if let Some(signature) = self.cpuid() {
+            let name = CPUID_VENDORS.iter().find(|(vendor, _)| signature == *vendor).map_or("vm-other", |(_, name)| name);
+            return Some(name.to_string());
+        }
+        let cpuinfo = self.read("proc/cpuinfo")?;
+        let hypervisor = cpuinfo
+            .lines()
+            .filter(|line| line.starts_with("flags"))
+            .any(|line| line.split_whitespace().any(|flag| flag == "hypervisor"));
+        hypervisor.then(|| "vm-other".to_string())
+    }
+
+    /// The hypervisor signature, `None` if the CPU does not report a hypervisor
+    fn cpuid(&self) -> Option<String> {
+        match &self.cpuid {
+            Cpuid::Fixed(signature) => signature.clone(),
+            Cpuid::Native => native_cpuid(),
+        }
+    }
+
+    /// A path under the root
+    fn path(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+
+    /// The trimmed contents of a file under the root, `None` if it is missing or empty
+    fn read(&self, path: &str) -> Option<String> {
+        let text = std::fs::read_to_string(self.path(path)).ok()?;
+        let text = text.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+        (!text.is_empty()).then(|| text.to_string())
+    }
+}
+
+/// The hypervisor signature from the `ebx`, `ecx` and `edx` registers of `cpuid` leaf `0x40000000`
+///
+/// Each register holds four characters, the lowest byte first, padded with zero bytes.
+fn decode_signature(registers: [u32; 3]) -> String {
+    let bytes: Vec<u8> = registers.iter().flat_map(|r| r.to_le_bytes()).collect();
+    String::from_utf8_lossy(&bytes).trim_end_matches('\0').to_string()
+}
+
+#[cfg(target_arch = "x86_64")]
+fn native_cpuid() -> Option<String> {
+    use std::arch::x86_64::__cpuid;
+    // Bit 31 of ecx in leaf 1 is set by every hypervisor
+    if __cpuid(1).ecx & (1 << 31) == 0 {
+        return None;
+    }
+    let leaf = __cpuid(0x4000_0000);
+    Some(decode_signature([leaf.ebx, leaf.ecx, leaf.edx]))
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn native_cpuid() -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::testing::TempDir;
+
+    /// Creates a fake root for the test `name`
+    fn fake_root(name: &str) -> TempDir {
+        TempDir::new(&format!("virt-{name}"))
+    }
+
+    fn detect(root: &TempDir) -> Virtualization {
+        Detector::with_root(root.path()).detect()
+    }
+
+    #[test]
+    fn bare_metal() {
+        let root = fake_root("bare-metal");
+        root.write("sys/class/dmi/id/sys_vendor", b"Dell Inc.\n")
+            .write("proc/1/cgroup", b"0::/init.scope\n")
+            .write("proc/sys/kernel/osrelease", b"6.1.0-18-amd64\n")
+            .write("proc/cpuinfo", b"processor\t: 0\nflags\t\t: fpu vme sse sse2\n");
+        assert_eq!(detect(&root), Virtualization::None);
+        assert!(detect(&root).matches("no"));
+        assert!(!detect(&root).matches("vm"));
+    }
+
+    #[test]
+    fn kvm() {
+        let root = fake_root("kvm");
+        root.write("sys/class/dmi/id/sys_vendor", b"QEMU\n").write("sys/class/dmi/id/product_name", b"KVM\n");
+        assert_eq!(detect(&root), Virtualization::Vm("qemu".to_string()));
+        // The DMI files are looked at in order, so the product name only counts without a vendor
+        std::fs::remove_file(root.join("sys/class/dmi/id/sys_vendor")).unwrap();
+        assert_eq!(detect(&root), Virtualization::Vm("kvm".to_string()));
+        assert!(detect(&root).matches("vm") && detect(&root).matches("kvm") && detect(&root).matches("yes"));
+    }
+
+    #[test]
+    fn hypervisor_fallbacks() {
+        let root = fake_root("fallbacks");
+        root.write("sys/hypervisor/type", b"xen\n");
+        assert_eq!(detect(&root), Virtualization::Vm("xen".to_string()));
+
+        let root = fake_root("hyperv");
+        root.write("sys/class/dmi/id/product_name", b"Virtual Machine\n");
+        assert_eq!(detect(&root), Virtualization::Vm("microsoft".to_string()));
+
+        let root = fake_root("cpuinfo");
+        root.write("proc/cpuinfo", b"flags\t\t: fpu hypervisor sse\n");
+        assert_eq!(detect(&root), Virtualization::Vm("vm-other".to_string()));
+    }
+
+    #[test]
+    fn cpuid() {
+        let root = fake_root("cpuid");
+        let detector = Detector::with_root(root.path());
+        assert_eq!(detector.clone().cpuid_signature(Some("KVMKVMKVM")).detect(), Virtualization::Vm("kvm".to_string()));
+        assert_eq!(detector.clone().cpuid_signature(Some("Microsoft Hv")).vm(), Some("microsoft".to_string()));
+        assert_eq!(detector.clone().cpuid_signature(Some("NewVisor")).vm(), Some("vm-other".to_string()));
+        assert_eq!(detector.cpuid_signature(None).vm(), None);
+    }
+
+    #[test]
+    fn cpuid_signature_decoding() {
+        // `KVMKVMKVM` padded with zero bytes
+        assert_eq!(decode_signature([0x4b4d_564b, 0x564b_4d56, 0x0000_004d]), "KVMKVMKVM");
+        // `VMwareVMware`
+        assert_eq!(decode_signature([0x6177_4d56, 0x4d56_6572, 0x6572_6177]), "VMwareVMware");
+        assert_eq!(decode_signature([0, 0, 0]), "");
+    }
+
+    #[test]
+    fn containers() {
+        let root = fake_root("environ");
+        root.write("proc/1/environ", b"PATH=/usr/bin\0container=lxc\0HOME=/\0")
+            .write("sys/class/dmi/id/sys_vendor", b"QEMU\n");
+        // A container in a virtual machine is a container
+        assert_eq!(detect(&root), Virtualization::Container("lxc".to_string()));
+        assert!(detect(&root).is_container() && detect(&root).matches("container"));
+
+        let root = fake_root("empty-environ");
+        root.write("proc/1/environ", b"container=\0").write(".dockerenv", b"");
+        assert_eq!(detect(&root), Virtualization::Container("docker".to_string()));
+
+        let root = fake_root("podman");
+        root.write("run/.containerenv", b"engine=\"podman-4.9\"\n");
+        assert_eq!(detect(&root), Virtualization::Container("podman".to_string()));
+
+        let root = fake_root("nspawn");
+        root.write("run/systemd/container", b"systemd-nspawn\n");
+        assert_eq!(detect(&root), Virtualization::Container("systemd-nspawn".to_string()));
+
+        let root = fake_root("cgroup");
+        root.write("proc/1/cgroup", b"0::/kubepods/besteffort/pod1234/abcd\n");
+        assert_eq!(detect(&root), Virtualization::Container("kubernetes".to_string()));
+    }
+
+    #[test]
+    fn wsl() {
+        let root = fake_root("wsl");
+        root.write("proc/sys/kernel/osrelease", b"5.15.133.1-microsoft-standard-WSL2\n");
+        assert_eq!(detect(&root), Virtualization::Container("wsl".to_string()));
+        assert_eq!(detect(&root).to_string(), "container wsl");
+    }
+}
@@ -0,0 +1 @@
+//! Handles the user scope of easyinit.
@@ -2,6 +2,8 @@
 
 
 pub mod signals;
+#[cfg(feature = "testing")]
+pub mod testing;
 /// Affects how backtraces are printed in logs.
 ///
 /// This currently is the `RUST_LIB_BACKTRACE` and `RUST_BACKTRACE` variables. These affect
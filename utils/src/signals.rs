@@ -1,14 +1,11 @@
 //! Handing signals
-use std::sync::atomic::AtomicBool;
 use nix::sys::signal::{sigaction,SaFlags,Signal,SigAction,SigHandler,SigSet, self as sig};
 /// Sets 
 pub fn basic_signal_handling(){
-    
-    
-    let sig_running = std::sync::Arc::new(AtomicBool::new(false));
     // Don't block signals these being blocked can cause undefined behavior, besides 
     // https://man.archlinux.org/man/sigprocmask.2.en#NOTES
-    let sigmask = {
+    // Not applied yet, the signals easyinit handles need a handler first
+    let _sigmask = {
         let mut s = sig::SigSet::all();
         s.remove(sig::SIGBUS);
         s.remove(sig::SIGFPE);
@@ -21,6 +18,32 @@ pub fn basic_signal_handling(){
     
 }
 
+/// Calls `reload` from a background thread every time easyinit gets `SIGHUP`
+/// 
+/// Signals that arrive while `reload` is running are merged into a single call afterwards.
+pub fn on_sighup(mut reload: impl FnMut() + Send + 'static)->std::io::Result<()>{
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGHUP])?;
+    std::thread::Builder::new().name("sighup".to_string()).spawn(move ||{
+        for _ in signals.forever(){
+            reload();
+        }
+    })?;
+    Ok(())
+}
+
+/// Calls `handler` from a background thread with every one of `signals` easyinit gets
+/// 
+/// The same signal arriving again while `handler` is running is merged into a single call afterwards.
+pub fn on_signals(signals:&[i32], mut handler: impl FnMut(i32) + Send + 'static)->std::io::Result<()>{
+    let mut signals = signal_hook::iterator::Signals::new(signals)?;
+    std::thread::Builder::new().name("signals".to_string()).spawn(move ||{
+        for signal in signals.forever(){
+            handler(signal);
+        }
+    })?;
+    Ok(())
+}
+
 /// Set a signal to be ignored
 /// 
 /// Returns the previous action on success.
@@ -0,0 +1,52 @@
+//! Helpers for the tests of the other crates, behind the `testing` feature
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts the directories made by this process, so a test that runs twice gets a new one
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary directory that is removed when dropped
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates `easyinit-<name>-<pid>-<count>` in the temporary directory of the system
+    ///
+    /// # Panics
+    ///
+    /// When the directory cannot be created.
+    pub fn new(name: &str) -> Self {
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("easyinit-{name}-{}-{count}", std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    /// Where the directory is
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// A path in the directory
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+
+    /// Writes a file at `path` in the directory, creating the directories it is in
+    ///
+    /// # Panics
+    ///
+    /// When the file cannot be written.
+    pub fn write(&self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> &Self {
+        let path = self.0.join(path);
+        std::fs::create_dir_all(path.parent().unwrap_or(&self.0)).unwrap();
+        std::fs::write(path, contents).unwrap();
+        self
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}